@@ -0,0 +1,472 @@
+use std::collections::BTreeMap;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    input::GamepadInput,
+    player::PlayerIndex,
+    settings::{read_config, write_config},
+    touch::TouchControls,
+};
+
+pub const ACTION_MAP_FILE: &str = "bindings.yaml";
+
+/// everything the player can trigger with a key or button. Systems query actions through
+/// ActionInput instead of reading raw KeyCodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum InputAction {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Fire,
+    SecondaryFire,
+    TogglePortal,
+    CycleFlightAssist,
+    CycleAimMode,
+    ToggleShipFollowsAim,
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    RotateCameraLeft,
+    RotateCameraRight,
+    ToggleMinimap,
+    Pause,
+    EditorSave,
+    QuickSave,
+    QuickLoad,
+    WorldShrink,
+    WorldGrow,
+    DumpPhysics,
+}
+
+impl InputAction {
+    pub const ALL: [InputAction; 24] = [
+        InputAction::MoveUp,
+        InputAction::MoveDown,
+        InputAction::MoveLeft,
+        InputAction::MoveRight,
+        InputAction::Jump,
+        InputAction::Fire,
+        InputAction::SecondaryFire,
+        InputAction::TogglePortal,
+        InputAction::CycleFlightAssist,
+        InputAction::CycleAimMode,
+        InputAction::ToggleShipFollowsAim,
+        InputAction::ZoomIn,
+        InputAction::ZoomOut,
+        InputAction::ZoomReset,
+        InputAction::RotateCameraLeft,
+        InputAction::RotateCameraRight,
+        InputAction::ToggleMinimap,
+        InputAction::Pause,
+        InputAction::EditorSave,
+        InputAction::QuickSave,
+        InputAction::QuickLoad,
+        InputAction::WorldShrink,
+        InputAction::WorldGrow,
+        InputAction::DumpPhysics,
+    ];
+
+    /// actions every local player triggers with their own bindings. The other actions are
+    /// global and only bound in the first player's set.
+    pub const PLAYER_ACTIONS: [InputAction; 9] = [
+        InputAction::MoveUp,
+        InputAction::MoveDown,
+        InputAction::MoveLeft,
+        InputAction::MoveRight,
+        InputAction::Jump,
+        InputAction::Fire,
+        InputAction::SecondaryFire,
+        InputAction::TogglePortal,
+        InputAction::CycleFlightAssist,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputAction::MoveUp => "Move up / thrust",
+            InputAction::MoveDown => "Move down / brake",
+            InputAction::MoveLeft => "Move left",
+            InputAction::MoveRight => "Move right",
+            InputAction::Jump => "Jump",
+            InputAction::Fire => "Fire",
+            InputAction::SecondaryFire => "Deploy",
+            InputAction::TogglePortal => "Toggle portal",
+            InputAction::CycleFlightAssist => "Flight assist",
+            InputAction::CycleAimMode => "Aim mode",
+            InputAction::ToggleShipFollowsAim => "Ship follows aim",
+            InputAction::ZoomIn => "Zoom in",
+            InputAction::ZoomOut => "Zoom out",
+            InputAction::ZoomReset => "Zoom reset",
+            InputAction::RotateCameraLeft => "Rotate left",
+            InputAction::RotateCameraRight => "Rotate right",
+            InputAction::ToggleMinimap => "Minimap",
+            InputAction::Pause => "Pause",
+            InputAction::EditorSave => "Save level",
+            InputAction::QuickSave => "Quicksave",
+            InputAction::QuickLoad => "Quickload",
+            InputAction::WorldShrink => "Shrink world",
+            InputAction::WorldGrow => "Grow world",
+            InputAction::DumpPhysics => "Dump physics",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Binding {
+    #[serde(default)]
+    pub keys: Vec<KeyCode>,
+    #[serde(default)]
+    pub buttons: Vec<GamepadButtonType>,
+}
+
+impl Binding {
+    fn new(keys: &[KeyCode], buttons: &[GamepadButtonType]) -> Self {
+        Self {
+            keys: keys.to_vec(),
+            buttons: buttons.to_vec(),
+        }
+    }
+}
+
+/// binding table from actions to keys and gamepad buttons. Persisted as yaml in ACTION_MAP_FILE
+/// in the config directory.
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+pub struct ActionMap {
+    // first player, including the global actions
+    pub bindings: BTreeMap<InputAction, Binding>,
+    // second local player, only InputAction::PLAYER_ACTIONS are used. The gamepad buttons
+    // apply to the second player's pad.
+    #[serde(default)]
+    pub second_player: BTreeMap<InputAction, Binding>,
+}
+
+impl Default for ActionMap {
+    fn default() -> Self {
+        use GamepadButtonType as B;
+        use InputAction as A;
+        let bindings = [
+            (A::MoveUp, Binding::new(&[KeyCode::W], &[])),
+            (A::MoveDown, Binding::new(&[KeyCode::S], &[])),
+            (A::MoveLeft, Binding::new(&[KeyCode::A], &[])),
+            (A::MoveRight, Binding::new(&[KeyCode::D], &[])),
+            (A::Jump, Binding::new(&[KeyCode::Space], &[B::South])),
+            (A::Fire, Binding::new(&[KeyCode::J], &[B::RightTrigger])),
+            (
+                A::SecondaryFire,
+                Binding::new(&[KeyCode::N], &[B::LeftTrigger]),
+            ),
+            (A::TogglePortal, Binding::new(&[KeyCode::H], &[B::North])),
+            (
+                A::CycleFlightAssist,
+                Binding::new(&[KeyCode::Tab], &[B::Select]),
+            ),
+            (A::CycleAimMode, Binding::new(&[KeyCode::F2], &[])),
+            (A::ToggleShipFollowsAim, Binding::new(&[KeyCode::F3], &[])),
+            (A::ZoomIn, Binding::new(&[KeyCode::I], &[B::DPadUp])),
+            (A::ZoomOut, Binding::new(&[KeyCode::P], &[B::DPadDown])),
+            (A::ZoomReset, Binding::new(&[KeyCode::O], &[B::RightThumb])),
+            (
+                A::RotateCameraLeft,
+                Binding::new(&[KeyCode::K], &[B::DPadLeft]),
+            ),
+            (
+                A::RotateCameraRight,
+                Binding::new(&[KeyCode::L], &[B::DPadRight]),
+            ),
+            (A::ToggleMinimap, Binding::new(&[KeyCode::M], &[])),
+            (A::Pause, Binding::new(&[KeyCode::Escape], &[B::Start])),
+            (A::EditorSave, Binding::new(&[KeyCode::F5], &[])),
+            (A::QuickSave, Binding::new(&[KeyCode::F6], &[])),
+            (A::QuickLoad, Binding::new(&[KeyCode::F9], &[])),
+            (A::WorldShrink, Binding::new(&[KeyCode::Y], &[])),
+            (A::WorldGrow, Binding::new(&[KeyCode::U], &[])),
+            (A::DumpPhysics, Binding::new(&[KeyCode::Q], &[])),
+        ]
+        .into_iter()
+        .collect();
+        let second_player = [
+            (A::MoveUp, Binding::new(&[KeyCode::Up], &[])),
+            (A::MoveDown, Binding::new(&[KeyCode::Down], &[])),
+            (A::MoveLeft, Binding::new(&[KeyCode::Left], &[])),
+            (A::MoveRight, Binding::new(&[KeyCode::Right], &[])),
+            (A::Jump, Binding::new(&[KeyCode::Return], &[B::South])),
+            (
+                A::Fire,
+                Binding::new(&[KeyCode::RControl], &[B::RightTrigger]),
+            ),
+            (
+                A::SecondaryFire,
+                Binding::new(&[KeyCode::RShift], &[B::LeftTrigger]),
+            ),
+            (
+                A::TogglePortal,
+                Binding::new(&[KeyCode::Period], &[B::North]),
+            ),
+            (
+                A::CycleFlightAssist,
+                Binding::new(&[KeyCode::Slash], &[B::Select]),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        Self {
+            bindings,
+            second_player,
+        }
+    }
+}
+
+impl ActionMap {
+    /// load the bindings from ACTION_MAP_FILE. Falls back to the defaults if the file is
+    /// missing or broken. Actions missing from the file keep their default binding.
+    pub fn load() -> Self {
+        let mut action_map = Self::default();
+        let Some(contents) = read_config(ACTION_MAP_FILE) else {
+            return action_map;
+        };
+        match serde_yaml::from_str::<ActionMap>(&contents) {
+            Ok(loaded) => {
+                action_map.bindings.extend(loaded.bindings);
+                action_map.second_player.extend(
+                    loaded
+                        .second_player
+                        .into_iter()
+                        .filter(|(action, _)| InputAction::PLAYER_ACTIONS.contains(action)),
+                );
+            }
+            Err(err) => warn!("failed to load {}: {}", ACTION_MAP_FILE, err),
+        }
+        action_map
+    }
+
+    pub fn save(&self) {
+        let result = serde_yaml::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| write_config(ACTION_MAP_FILE, &contents));
+        if let Err(err) = result {
+            warn!("failed to save {}: {}", ACTION_MAP_FILE, err);
+        }
+    }
+
+    fn player_bindings(&self, player: PlayerIndex) -> &BTreeMap<InputAction, Binding> {
+        match player.0 {
+            0 => &self.bindings,
+            _ => &self.second_player,
+        }
+    }
+
+    fn player_bindings_mut(&mut self, player: PlayerIndex) -> &mut BTreeMap<InputAction, Binding> {
+        match player.0 {
+            0 => &mut self.bindings,
+            _ => &mut self.second_player,
+        }
+    }
+
+    pub fn keys(&self, player: PlayerIndex, action: InputAction) -> &[KeyCode] {
+        self.player_bindings(player)
+            .get(&action)
+            .map(|binding| binding.keys.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn buttons(&self, player: PlayerIndex, action: InputAction) -> &[GamepadButtonType] {
+        self.player_bindings(player)
+            .get(&action)
+            .map(|binding| binding.buttons.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// the binding (other than `action` of `player`) already using `key`, if any. The players
+    /// share the keyboard, so the keys of all players count.
+    pub fn conflict(
+        &self,
+        player: PlayerIndex,
+        action: InputAction,
+        key: KeyCode,
+    ) -> Option<(PlayerIndex, InputAction)> {
+        PlayerIndex::ALL.iter().find_map(|other_player| {
+            self.player_bindings(*other_player)
+                .iter()
+                .find(|(other, binding)| {
+                    (*other_player, **other) != (player, action) && binding.keys.contains(&key)
+                })
+                .map(|(other, _)| (*other_player, *other))
+        })
+    }
+
+    /// replace the player's keys of `action` by `key`. Fails with the conflicting binding if
+    /// the key is already in use.
+    pub fn rebind(
+        &mut self,
+        player: PlayerIndex,
+        action: InputAction,
+        key: KeyCode,
+    ) -> Result<(), (PlayerIndex, InputAction)> {
+        if let Some(other) = self.conflict(player, action, key) {
+            return Err(other);
+        }
+        self.player_bindings_mut(player)
+            .entry(action)
+            .or_default()
+            .keys = vec![key];
+        Ok(())
+    }
+
+    pub fn describe(&self, player: PlayerIndex, action: InputAction) -> String {
+        let keys = self.keys(player, action);
+        if keys.is_empty() {
+            "-".into()
+        } else {
+            keys.iter()
+                .map(|key| format!("{:?}", key))
+                .collect::<Vec<_>>()
+                .join(" / ")
+        }
+    }
+}
+
+/// action state from keyboard, the players' gamepads and the touch controls. Analog triggers
+/// are available through `gamepad`.
+#[derive(SystemParam)]
+pub struct ActionInput<'w> {
+    action_map: Res<'w, ActionMap>,
+    keyboard_input: Res<'w, Input<KeyCode>>,
+    pub gamepad: GamepadInput<'w>,
+    touch: Res<'w, TouchControls>,
+}
+
+impl ActionInput<'_> {
+    /// analog movement from the player's left stick or (first player only) the touch stick
+    pub fn move_stick(&self, player: PlayerIndex) -> Option<Vec2> {
+        let touch_stick = self.touch.stick.filter(|_| player == PlayerIndex(0));
+        self.gamepad.left_stick(player).or(touch_stick)
+    }
+
+    /// action state of a single player. The touch controls belong to the first player.
+    pub fn player_pressed(&self, player: PlayerIndex, action: InputAction) -> bool {
+        (player == PlayerIndex(0) && self.touch.pressed(action))
+            || self
+                .keyboard_input
+                .any_pressed(self.action_map.keys(player, action).iter().copied())
+            || self
+                .action_map
+                .buttons(player, action)
+                .iter()
+                .any(|button| self.gamepad.pressed(player, *button))
+    }
+
+    pub fn player_just_pressed(&self, player: PlayerIndex, action: InputAction) -> bool {
+        (player == PlayerIndex(0) && self.touch.just_pressed(action))
+            || self
+                .keyboard_input
+                .any_just_pressed(self.action_map.keys(player, action).iter().copied())
+            || self
+                .action_map
+                .buttons(player, action)
+                .iter()
+                .any(|button| self.gamepad.just_pressed(player, *button))
+    }
+
+    /// any player triggered the action. For actions that are not tied to a player (pause,
+    /// zoom, ...)
+    pub fn pressed(&self, action: InputAction) -> bool {
+        PlayerIndex::ALL
+            .iter()
+            .any(|player| self.player_pressed(*player, action))
+    }
+
+    pub fn just_pressed(&self, action: InputAction) -> bool {
+        PlayerIndex::ALL
+            .iter()
+            .any(|player| self.player_just_pressed(*player, action))
+    }
+}
+
+pub struct ActionPlugin;
+impl Plugin for ActionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ActionMap::load());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const P1: PlayerIndex = PlayerIndex(0);
+    const P2: PlayerIndex = PlayerIndex(1);
+
+    #[test]
+    fn conflict() {
+        let action_map = ActionMap::default();
+        // the binding's own key
+        assert_eq!(action_map.conflict(P1, InputAction::Fire, KeyCode::J), None);
+        assert_eq!(
+            action_map.conflict(P2, InputAction::Fire, KeyCode::RControl),
+            None
+        );
+        assert_eq!(
+            action_map.conflict(P1, InputAction::Jump, KeyCode::J),
+            Some((P1, InputAction::Fire))
+        );
+        // the players share the keyboard, even the same action of the other player conflicts
+        assert_eq!(
+            action_map.conflict(P1, InputAction::Fire, KeyCode::RControl),
+            Some((P2, InputAction::Fire))
+        );
+        assert_eq!(
+            action_map.conflict(P2, InputAction::Fire, KeyCode::J),
+            Some((P1, InputAction::Fire))
+        );
+        assert_eq!(
+            action_map.conflict(P1, InputAction::Pause, KeyCode::Up),
+            Some((P2, InputAction::MoveUp))
+        );
+        assert_eq!(
+            action_map.conflict(P1, InputAction::Fire, KeyCode::F12),
+            None
+        );
+    }
+
+    #[test]
+    fn default_bindings_are_free_of_conflicts() {
+        let action_map = ActionMap::default();
+        for player in PlayerIndex::ALL {
+            for action in InputAction::ALL {
+                for key in action_map.keys(player, action) {
+                    assert_eq!(
+                        action_map.conflict(player, action, *key),
+                        None,
+                        "{} {:?}",
+                        player.name(),
+                        action
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rebind() {
+        let mut action_map = ActionMap::default();
+        assert_eq!(
+            action_map.rebind(P1, InputAction::Fire, KeyCode::W),
+            Err((P1, InputAction::MoveUp))
+        );
+        assert_eq!(action_map.keys(P1, InputAction::Fire), [KeyCode::J]);
+        assert_eq!(
+            action_map.rebind(P1, InputAction::Fire, KeyCode::F12),
+            Ok(())
+        );
+        assert_eq!(action_map.keys(P1, InputAction::Fire), [KeyCode::F12]);
+        assert_eq!(action_map.conflict(P1, InputAction::Jump, KeyCode::J), None);
+
+        // the second player's table is separate
+        assert_eq!(action_map.rebind(P2, InputAction::Fire, KeyCode::J), Ok(()));
+        assert_eq!(action_map.keys(P2, InputAction::Fire), [KeyCode::J]);
+        assert_eq!(action_map.keys(P1, InputAction::Fire), [KeyCode::F12]);
+    }
+}
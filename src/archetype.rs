@@ -0,0 +1,154 @@
+// player archetypes: the kinds of vessels a player can start with. Each archetype's
+// spawn recipe lives here, game_setup only attaches the player to the spawned vessel.
+// All vessels apart from the benchmark emitter carry DroidHealth, so they are destroyed like
+// droids when their hitpoints run out.
+
+use bevy::prelude::*;
+use bevy_prototype_lyon::{prelude::*, shapes};
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    deployable::Deployer,
+    droid::{DroidBundle, DroidHealth},
+    game::GameMarker,
+    hexton::{HextonBundle, HEXTON_VERTICES},
+    particle::ColorGenerator,
+    prelude::*,
+    ship::{ShipBundle, SHIP_VERTICES},
+    weapon::WeaponTarget,
+};
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PlayerArchetype {
+    #[default]
+    Droid,
+    Ship,
+    Hexton,
+    // particle emitter without any gameplay, used for benchmarking the particle system
+    Benchmark,
+}
+
+impl PlayerArchetype {
+    pub const ALL: [PlayerArchetype; 4] = [
+        PlayerArchetype::Droid,
+        PlayerArchetype::Ship,
+        PlayerArchetype::Hexton,
+        PlayerArchetype::Benchmark,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlayerArchetype::Droid => "droid",
+            PlayerArchetype::Ship => "ship",
+            PlayerArchetype::Hexton => "hexton",
+            PlayerArchetype::Benchmark => "benchmark",
+        }
+    }
+
+    /// spawn the vessel for this archetype. The returned entity is the one the player
+    /// entity must be attached to.
+    pub fn spawn(&self, commands: &mut Commands, gravity: bool, translation: Vec3) -> Entity {
+        let vessel = match self {
+            PlayerArchetype::Droid => spawn_droid(commands, gravity, translation),
+            PlayerArchetype::Ship => spawn_ship(commands, translation),
+            PlayerArchetype::Hexton => spawn_hexton(commands, translation),
+            PlayerArchetype::Benchmark => spawn_benchmark(commands, translation),
+        };
+        commands.entity(vessel).insert(VesselArchetype(*self));
+        vessel
+    }
+}
+
+/// marks the vessel a player started with, as opposed to droids taken over later
+#[derive(Component, Clone, Copy, Debug)]
+pub struct VesselArchetype(pub PlayerArchetype);
+
+fn spawn_droid(commands: &mut Commands, gravity: bool, translation: Vec3) -> Entity {
+    let shape = shapes::RegularPolygon {
+        sides: 6,
+        feature: shapes::RegularPolygonFeature::Radius(32.0),
+        ..shapes::RegularPolygon::default()
+    };
+    commands
+        .spawn(DroidBundle::new("player", gravity))
+        .insert(ShapeBundle {
+            path: GeometryBuilder::build_as(&shape),
+            spatial: SpatialBundle {
+                transform: Transform::from_translation(translation),
+                ..default()
+            },
+            ..default()
+        })
+        .insert(default_stroke(GREEN_HDR))
+        .insert(Deployer::default())
+        .insert(Team::Player)
+        .insert(GameMarker)
+        .id()
+}
+
+fn spawn_ship(commands: &mut Commands, translation: Vec3) -> Entity {
+    let ship_shape = shapes::Polygon {
+        points: SHIP_VERTICES.into(),
+        closed: true,
+    };
+    commands
+        .spawn(ShipBundle::new("ship"))
+        .insert(ShapeBundle {
+            path: GeometryBuilder::build_as(&ship_shape),
+            spatial: SpatialBundle {
+                transform: Transform::from_translation(translation),
+                ..default()
+            },
+            ..default()
+        })
+        .insert(default_stroke(YELLOW_HDR))
+        .insert(DroidHealth::default())
+        .insert(WeaponTarget::default())
+        .insert(Team::Player)
+        .insert(GameMarker)
+        .id()
+}
+
+fn spawn_hexton(commands: &mut Commands, translation: Vec3) -> Entity {
+    let hexton_shape = shapes::Polygon {
+        points: HEXTON_VERTICES.into(),
+        closed: true,
+    };
+    commands
+        .spawn(HextonBundle::new("hexton"))
+        .insert(ShapeBundle {
+            path: GeometryBuilder::build_as(&hexton_shape),
+            spatial: SpatialBundle {
+                transform: Transform::from_translation(translation),
+                ..default()
+            },
+            ..default()
+        })
+        .insert(default_stroke(BLUE_HDR))
+        .insert(DroidHealth::default())
+        .insert(WeaponTarget::default())
+        .insert(Team::Player)
+        .insert(GameMarker)
+        .id()
+}
+
+fn spawn_benchmark(commands: &mut Commands, translation: Vec3) -> Entity {
+    commands
+        .spawn(SpatialBundle {
+            transform: Transform::from_translation(translation),
+            ..default()
+        })
+        .insert(ParticleSource {
+            rate: 50,
+            direction: ParticleDirection::Uniform,
+            speed_distr: Normal::new(200.0, 90.0).unwrap(),
+            lifetime_distr: Normal::new(0.8, 0.5).unwrap(),
+            velocity_offset: Vec2::default(),
+            damping: default(),
+            initial_offset: 0.0,
+            color_generator: ColorGenerator::Static(7),
+        })
+        .insert(GameMarker)
+        .id()
+}
@@ -1,17 +1,43 @@
+use std::ops::{Add, Mul, Sub};
+
 use bevy::{
-    core_pipeline::{bloom::BloomSettings, tonemapping::Tonemapping},
+    core_pipeline::{
+        bloom::BloomSettings, clear_color::ClearColorConfig, tonemapping::Tonemapping,
+    },
+    math::Vec3Swizzles,
     prelude::*,
+    render::camera::Viewport,
+    window::PrimaryWindow,
 };
 use bevy_mouse_tracking_plugin::{
     mouse_pos::{InitMouseTracking, InitWorldTracking},
     MainCamera,
 };
+use bevy_rapier2d::prelude::Velocity;
+use hexagon_tiles::layout::LayoutTool;
 // use bevy_mouse_tracking_plugin::prelude::*;
 
+use crate::{feedback::ScreenShake, hex_point_to_vec2, prelude::*, settings::Settings, HEX_LAYOUT};
+
 #[derive(Component, Default)]
 pub struct CameraTarget;
 
-fn setup_camera_system(mut commands: Commands) {
+/// second camera of the split view. Inactive while all targets fit into one view.
+#[derive(Component)]
+pub struct SplitCamera;
+
+#[derive(Resource, Default)]
+pub struct SplitView {
+    pub active: bool,
+    // split along a vertical line (the views are next to each other) or a horizontal one
+    side_by_side: bool,
+}
+
+fn setup_camera_system(mut commands: Commands, settings: Res<Settings>) {
+    let rig_state = CameraRigState {
+        zoom: settings.camera_zoom,
+        ..default()
+    };
     commands
         .spawn((
             Camera2dBundle {
@@ -24,41 +50,425 @@ fn setup_camera_system(mut commands: Commands) {
             },
             // BloomSettings::SCREEN_BLUR,
             BloomSettings::default(),
+            rig_state.clone(),
+            ScreenShake::default(),
         ))
         .add(InitWorldTracking)
         .insert(MainCamera);
+
+    commands.spawn((
+        Camera2dBundle {
+            camera: Camera {
+                hdr: true,
+                is_active: false,
+                // render on top of the main camera, which already cleared the window
+                order: 1,
+                ..default()
+            },
+            camera_2d: Camera2d {
+                clear_color: ClearColorConfig::None,
+            },
+            tonemapping: Tonemapping::TonyMcMapface,
+            ..default()
+        },
+        BloomSettings::default(),
+        // the ui is drawn once, by the main camera
+        UiCameraConfig { show_ui: false },
+        rig_state,
+        ScreenShake::default(),
+        SplitCamera,
+    ));
+}
+
+/// tuning of the camera following the CameraTarget
+#[derive(Resource)]
+pub struct CameraRig {
+    // time (s) the critically damped follow needs to (mostly) catch up
+    pub smooth_time: f32,
+    // the camera leads the target by its velocity times this (s)
+    pub look_ahead_time: f32,
+    pub max_look_ahead: f32,
+    // zoom out by this factor per unit of speed (pixels/s)
+    pub speed_zoom: f32,
+    pub max_speed_zoom: f32,
+    pub zoom_smooth_time: f32,
+    // keep the view inside the tile extents (plus margin)
+    pub use_tile_bounds: bool,
+    pub bounds_margin: f32,
+    // space (pixels) kept around the targets when framing several of them
+    pub frame_padding: f32,
+    // the view splits when framing all targets would need a larger scale than this
+    pub max_frame_zoom: f32,
+    // ...and joins again below max_frame_zoom times this
+    pub join_factor: f32,
+}
+
+impl Default for CameraRig {
+    fn default() -> Self {
+        Self {
+            smooth_time: 0.3,
+            look_ahead_time: 0.4,
+            max_look_ahead: 250.0,
+            speed_zoom: 0.0005,
+            max_speed_zoom: 1.5,
+            zoom_smooth_time: 0.8,
+            use_tile_bounds: true,
+            bounds_margin: 200.0,
+            frame_padding: 200.0,
+            max_frame_zoom: 2.5,
+            join_factor: 0.8,
+        }
+    }
 }
 
-fn track_camera_system(
-    mut camera_query: Query<&mut Transform, (With<Camera2d>, Without<CameraTarget>)>,
-    target_query: Query<&GlobalTransform, With<CameraTarget>>,
+#[derive(Component, Clone)]
+pub struct CameraRigState {
+    // manual zoom (camera scale), the speed and framing zoom is applied on top
+    pub zoom: f32,
+    // position the rig steers. The transform may differ by effects like the screen shake.
+    center: Option<Vec2>,
+    velocity: Vec2,
+    auto_zoom: f32,
+    auto_zoom_velocity: f32,
+    // CameraTargets shown by this camera, assigned by camera_layout_system
+    targets: Vec<Entity>,
+}
+
+impl Default for CameraRigState {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            center: None,
+            velocity: Vec2::ZERO,
+            auto_zoom: 1.0,
+            auto_zoom_velocity: 0.0,
+            targets: Vec::new(),
+        }
+    }
+}
+
+/// critically damped smoothing of `current` towards `target` (see Game Programming Gems 4, 1.10).
+/// Frame rate independent: `velocity` carries the state between calls.
+pub fn smooth_damp<T>(current: T, target: T, velocity: &mut T, smooth_time: f32, dt: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let omega = 2.0 / smooth_time.max(0.0001);
+    let x = omega * dt;
+    let exp = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+    let change = current - target;
+    let temp = (*velocity + change * omega) * dt;
+    *velocity = (*velocity - temp * omega) * exp;
+    target + (change + temp) * exp
+}
+
+/// pixel extents (min, max) of all tiles
+fn tile_bounds(tile_cache: &TileCache) -> Option<Rect> {
+    tile_cache
+        .tiles
+        .keys()
+        .map(|tile_pos| hex_point_to_vec2(LayoutTool::hex_to_pixel(HEX_LAYOUT, tile_pos.0)))
+        .fold(None, |rect: Option<Rect>, pos| {
+            Some(rect.map_or(Rect::from_corners(pos, pos), |rect| rect.union_point(pos)))
+        })
+}
+
+fn clamp_to_bounds(center: Vec2, half_view: Vec2, bounds: Rect) -> Vec2 {
+    let min = bounds.min + half_view;
+    let max = bounds.max - half_view;
+    // center the view on axes where the level is smaller than the view
+    Vec2::new(
+        if min.x <= max.x {
+            center.x.clamp(min.x, max.x)
+        } else {
+            bounds.center().x
+        },
+        if min.y <= max.y {
+            center.y.clamp(min.y, max.y)
+        } else {
+            bounds.center().y
+        },
+    )
+}
+
+/// center and the smallest camera scale that shows all `points` (plus `padding`) in a view of
+/// `view_size`. Computed in the frame of the (rotated) camera.
+fn frame_points(
+    points: impl IntoIterator<Item = Vec2>,
+    rotation: Quat,
+    view_size: Vec2,
+    padding: f32,
+) -> Option<(Vec2, f32)> {
+    let inverse = rotation.inverse();
+    let rect = points
+        .into_iter()
+        .map(|point| inverse.mul_vec3(point.extend(0.0)).xy())
+        .fold(None, |rect: Option<Rect>, pos| {
+            Some(rect.map_or(Rect::from_corners(pos, pos), |rect| rect.union_point(pos)))
+        })?;
+    let center = rotation.mul_vec3(rect.center().extend(0.0)).xy();
+    let half_size = rect.half_size() + Vec2::splat(padding);
+    let scale = (half_size / (view_size * 0.5).max(Vec2::ONE)).max_element();
+    Some((center, scale))
+}
+
+/// split the targets into two groups around the two targets furthest apart
+fn split_targets(targets: &[(Entity, Vec2)]) -> (Vec<Entity>, Vec<Entity>) {
+    let mut anchors = (0, 0);
+    let mut max_distance = -1.0;
+    for (i, (_, a)) in targets.iter().enumerate() {
+        for (j, (_, b)) in targets.iter().enumerate().skip(i + 1) {
+            let distance = a.distance_squared(*b);
+            if distance > max_distance {
+                max_distance = distance;
+                anchors = (i, j);
+            }
+        }
+    }
+    let (a, b) = (targets[anchors.0].1, targets[anchors.1].1);
+    let (first, second): (Vec<_>, Vec<_>) = targets
+        .iter()
+        .partition(|(_, pos)| pos.distance_squared(a) <= pos.distance_squared(b));
+    (
+        first.into_iter().map(|(entity, _)| *entity).collect(),
+        second.into_iter().map(|(entity, _)| *entity).collect(),
+    )
+}
+
+fn centroid(targets: &[(Entity, Vec2)], group: &[Entity]) -> Vec2 {
+    let sum: Vec2 = targets
+        .iter()
+        .filter(|(entity, _)| group.contains(entity))
+        .map(|(_, pos)| *pos)
+        .sum();
+    sum / group.len().max(1) as f32
+}
+
+// only touch the camera if the viewport changed, the render setup depends on it
+pub(crate) fn set_viewport(camera: &mut Mut<Camera>, viewport: Option<(UVec2, UVec2)>) {
+    let current = camera
+        .viewport
+        .as_ref()
+        .map(|viewport| (viewport.physical_position, viewport.physical_size));
+    if current != viewport {
+        camera.viewport = viewport.map(|(physical_position, physical_size)| Viewport {
+            physical_position,
+            physical_size,
+            ..default()
+        });
+    }
+}
+
+/// decide between one view framing all targets and a split view, assign the targets and
+/// viewports to the cameras
+#[allow(clippy::type_complexity)]
+fn camera_layout_system(
+    rig: Res<CameraRig>,
+    mut split_view: ResMut<SplitView>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    target_query: Query<(Entity, &GlobalTransform), With<CameraTarget>>,
+    mut main_query: Query<
+        (&mut Camera, &mut CameraRigState, &Transform),
+        (With<MainCamera>, Without<SplitCamera>),
+    >,
+    mut split_query: Query<
+        (&mut Camera, &mut CameraRigState, &mut Transform),
+        (With<SplitCamera>, Without<MainCamera>),
+    >,
 ) {
-    if let (Ok(mut camera_transform), Ok(target_transform)) =
-        (camera_query.get_single_mut(), target_query.get_single())
-    {
+    let (
+        Ok(window),
+        Ok((mut main_camera, mut main_state, main_transform)),
+        Ok((mut split_camera, mut split_state, mut split_transform)),
+    ) = (
+        window_query.get_single(),
+        main_query.get_single_mut(),
+        split_query.get_single_mut(),
+    )
+    else {
+        return;
+    };
+    let mut targets: Vec<_> = target_query
+        .iter()
+        .map(|(entity, transform)| (entity, transform.translation().xy()))
+        .collect();
+    // keeps the assignment of targets to cameras stable
+    targets.sort_by_key(|(entity, _)| *entity);
+
+    let rotation = main_transform.rotation;
+    let window_size = Vec2::new(window.width(), window.height());
+    let frame_scale = frame_points(
+        targets.iter().map(|(_, pos)| *pos),
+        rotation,
+        window_size,
+        rig.frame_padding,
+    )
+    .map_or(0.0, |(_, scale)| scale);
+    let split = targets.len() >= 2
+        && if split_view.active {
+            frame_scale > rig.max_frame_zoom * rig.join_factor
+        } else {
+            frame_scale > rig.max_frame_zoom
+        };
+
+    if !split {
+        if split_view.active {
+            info!("joining split view");
+            split_view.active = false;
+        }
+        main_state.targets = targets.iter().map(|(entity, _)| *entity).collect();
+        split_state.targets.clear();
+        set_viewport(&mut main_camera, None);
+        if split_camera.is_active {
+            split_camera.is_active = false;
+        }
+        return;
+    }
+
+    let (first, second) = split_targets(&targets);
+    let inverse = rotation.inverse();
+    let separation = inverse
+        .mul_vec3((centroid(&targets, &second) - centroid(&targets, &first)).extend(0.0))
+        .xy();
+    if !split_view.active {
+        info!("splitting view");
+        split_view.active = true;
+        split_view.side_by_side = separation.x.abs() >= separation.y.abs();
+        // the second view starts where the first one is and moves over
+        split_state.center = main_state.center;
+        split_state.velocity = main_state.velocity;
+        split_state.zoom = main_state.zoom;
+        split_state.auto_zoom = main_state.auto_zoom;
+        *split_transform = *main_transform;
+    } else if split_view.side_by_side && separation.y.abs() > 1.2 * separation.x.abs() {
+        split_view.side_by_side = false;
+    } else if !split_view.side_by_side && separation.x.abs() > 1.2 * separation.y.abs() {
+        split_view.side_by_side = true;
+    }
+    main_state.targets = first;
+    split_state.targets = second;
+
+    let physical_size = UVec2::new(window.physical_width(), window.physical_height());
+    let (half_size, offset, main_first) = if split_view.side_by_side {
+        let half = UVec2::new(physical_size.x / 2, physical_size.y);
+        (half, UVec2::new(half.x, 0), separation.x >= 0.0)
+    } else {
+        // viewports are positioned from the top
+        let half = UVec2::new(physical_size.x, physical_size.y / 2);
+        (half, UVec2::new(0, half.y), separation.y < 0.0)
+    };
+    let (main_position, split_position) = if main_first {
+        (UVec2::ZERO, offset)
+    } else {
+        (offset, UVec2::ZERO)
+    };
+    set_viewport(&mut main_camera, Some((main_position, half_size)));
+    set_viewport(&mut split_camera, Some((split_position, half_size)));
+    if !split_camera.is_active {
+        split_camera.is_active = true;
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn track_camera_system(
+    time: Res<Time>,
+    rig: Res<CameraRig>,
+    tile_cache: Res<TileCache>,
+    mut bounds: Local<Option<Rect>>,
+    mut camera_query: Query<
+        (&mut Transform, &mut CameraRigState, &OrthographicProjection),
+        (With<Camera2d>, Without<CameraTarget>),
+    >,
+    target_query: Query<(&GlobalTransform, Option<&Parent>), With<CameraTarget>>,
+    velocity_query: Query<&Velocity>,
+) {
+    let dt = time.delta_seconds();
+    if rig.use_tile_bounds && (tile_cache.is_changed() || bounds.is_none()) {
+        *bounds = tile_bounds(&tile_cache);
+    }
+    for (mut camera_transform, mut state, projection) in &mut camera_query {
+        let current = state
+            .center
+            .unwrap_or_else(|| camera_transform.translation.xy());
+        // position and velocity of the targets. The camera target is usually a child of the
+        // moving body.
+        let targets: Vec<(Vec2, Vec2)> = state
+            .targets
+            .iter()
+            .filter_map(|entity| target_query.get(*entity).ok())
+            .map(|(target_transform, parent)| {
+                let velocity = parent
+                    .and_then(|parent| velocity_query.get(parent.get()).ok())
+                    .map(|velocity| velocity.linvel)
+                    .unwrap_or_default();
+                (target_transform.translation().xy(), velocity)
+            })
+            .collect();
+        if targets.is_empty() {
+            // nothing to track: hold the position
+            camera_transform.translation = current.extend(camera_transform.translation.z);
+            continue;
+        }
+        if dt <= 0.0 {
+            continue;
+        }
         debug!(
             "camera track: {:?} {:?}",
-            target_transform.translation(),
-            camera_transform.translation
+            targets, camera_transform.translation
         );
-        let dist = target_transform.translation() - camera_transform.translation;
-        let l = dist.length();
-        const DEADZONE: f32 = 100.0;
-        const OUTER: f32 = 200.0;
-        const MAX_SPEED: f32 = 50.0;
-
-        if l > DEADZONE {
-            let dir = dist.normalize_or_zero();
-            let v = ((l - DEADZONE).clamp(0.0, OUTER) / OUTER) * MAX_SPEED;
-            camera_transform.translation += dir * v;
+
+        let Some((target, frame_scale)) = frame_points(
+            targets.iter().map(|(pos, velocity)| {
+                *pos + (*velocity * rig.look_ahead_time).clamp_length_max(rig.max_look_ahead)
+            }),
+            camera_transform.rotation,
+            projection.area.size(),
+            rig.frame_padding,
+        ) else {
+            continue;
+        };
+
+        let mut velocity_state = state.velocity;
+        let mut center = smooth_damp(current, target, &mut velocity_state, rig.smooth_time, dt);
+        state.velocity = velocity_state;
+
+        let speed = targets
+            .iter()
+            .map(|(_, velocity)| velocity.length())
+            .fold(0.0, f32::max);
+        let speed_zoom = (1.0 + speed * rig.speed_zoom).min(rig.max_speed_zoom);
+        // a single target always fits, several targets may need to zoom out
+        let frame_zoom = (frame_scale / state.zoom).min(rig.max_frame_zoom);
+        let zoom_target = speed_zoom.max(frame_zoom);
+        let mut zoom_velocity = state.auto_zoom_velocity;
+        state.auto_zoom = smooth_damp(
+            state.auto_zoom,
+            zoom_target,
+            &mut zoom_velocity,
+            rig.zoom_smooth_time,
+            dt,
+        );
+        state.auto_zoom_velocity = zoom_velocity;
+        let scale = state.zoom * state.auto_zoom;
+        camera_transform.scale = Vec3::new(scale, scale, camera_transform.scale.z);
+
+        if let (true, Some(bounds)) = (rig.use_tile_bounds, *bounds) {
+            // the view may be rotated, use the larger extent in both directions
+            let half_view = Vec2::splat(projection.area.size().max_element() * 0.5 * scale);
+            center = clamp_to_bounds(center, half_view, bounds.inset(rig.bounds_margin));
         }
+        state.center = Some(center);
+        camera_transform.translation = center.extend(camera_transform.translation.z);
     }
 }
+
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup_camera_system)
-            .add_systems(Update, track_camera_system);
+        app.init_resource::<CameraRig>()
+            .init_resource::<SplitView>()
+            .add_systems(Startup, setup_camera_system)
+            .add_systems(Update, (camera_layout_system, track_camera_system).chain());
     }
 }
@@ -1,12 +1,14 @@
 use crate::{
-    droid::DroidOverloadMarker,
+    collision_groups,
+    droid::{DroidHealth, DroidMarker, DroidOverloadMarker},
+    feedback::FeedbackEvent,
     particle::ColorGenerator,
     player::{PlayerMarker, PlayerTakeover},
     prelude::*,
     ship::ShipMarker,
-    weapon::Projectile,
+    weapon::{ImpactBehaviour, Projectile, WeaponTarget},
 };
-use bevy::prelude::*;
+use bevy::{math::Vec3Swizzles, prelude::*, utils::HashSet};
 use bevy_rapier2d::prelude::*;
 use rand_distr::Normal;
 
@@ -20,35 +22,103 @@ pub enum CollisionFxType {
 //     }
 // }
 
+fn projectile_explode(commands: &mut Commands, projectile: Entity) {
+    commands
+        .entity(projectile)
+        .insert(ParticleSource {
+            rate: 200,
+            direction: ParticleDirection::Uniform,
+            speed_distr: Normal::new(200.0, 90.0).unwrap(),
+            lifetime_distr: Normal::new(0.8, 0.5).unwrap(),
+            velocity_offset: Vec2::default(),
+            damping: default(),
+            initial_offset: 0.0,
+            color_generator: ColorGenerator::Static(7),
+            // color_generator: ColorGenerator::Random,
+        })
+        .insert(GameDespawn::time_to_live(0.1))
+        // don't register more Projectile collisions in the next frames
+        .remove::<Projectile>();
+}
+
+fn projectile_stick(commands: &mut Commands, projectile: Entity) {
+    commands
+        .entity(projectile)
+        .insert(RigidBody::Fixed)
+        .insert(Velocity::zero())
+        // stuck projectiles are pure decoration
+        .insert(CollisionGroups::new(
+            collision_groups::PROJECTILES,
+            Group::NONE,
+        ))
+        .insert(GameDespawn::time_to_live(3.0))
+        .remove::<Projectile>();
+}
+
 fn projectile_collision_system(
     mut commands: Commands,
     mut collision_events: EventReader<CollisionEvent>,
-    projectile_query: Query<Entity, With<Projectile>>,
+    mut feedback_events: EventWriter<FeedbackEvent>,
+    mut projectile_query: Query<(&mut Projectile, &mut Velocity, &GlobalTransform)>,
+    target_query: Query<(), With<WeaponTarget>>,
+    mut health_query: Query<&mut DroidHealth>,
 ) {
+    // exploded and stuck projectiles lose Projectile only once the commands are applied. Further
+    // events of the same step (e.g. two overlapping droids) must not hit or bounce again.
+    let mut spent = HashSet::new();
     for collision_event in collision_events.read() {
         match collision_event {
             CollisionEvent::Started(a, b, _) => {
-                let projectile = projectile_query
-                    .get(*a)
-                    .or_else(|_| projectile_query.get(*b));
+                let (projectile_entity, other) = if projectile_query.contains(*a) {
+                    (*a, *b)
+                } else if projectile_query.contains(*b) {
+                    (*b, *a)
+                } else {
+                    continue;
+                };
+                if spent.contains(&projectile_entity) {
+                    continue;
+                }
+                let Ok((mut projectile, mut velocity, transform)) =
+                    projectile_query.get_mut(projectile_entity)
+                else {
+                    continue;
+                };
+                let position = transform.translation().xy();
 
-                if let Ok(projectile) = projectile {
-                    commands
-                        .entity(projectile)
-                        .insert(ParticleSource {
-                            rate: 200,
-                            direction: ParticleDirection::Uniform,
-                            speed_distr: Normal::new(200.0, 90.0).unwrap(),
-                            lifetime_distr: Normal::new(0.8, 0.5).unwrap(),
-                            velocity_offset: Vec2::default(),
-                            damping: default(),
-                            initial_offset: 0.0,
-                            color_generator: ColorGenerator::Static(7),
-                            // color_generator: ColorGenerator::Random,
-                        })
-                        .insert(GameDespawn::time_to_live(0.1))
-                        // don't register more Projectile collisions in the next frames
-                        .remove::<Projectile>();
+                if target_query.contains(other) {
+                    // the projectile is still live, so the hit counts
+                    if let Ok(mut health) = health_query.get_mut(other) {
+                        health.apply_damage(projectile.damage);
+                        health.last_hit_by = Some(projectile.owner);
+                    }
+                    feedback_events.send(FeedbackEvent::new(0.25, 0.3, position));
+                    match projectile.impact {
+                        ImpactBehaviour::PassThrough => (),
+                        ImpactBehaviour::Explode => {
+                            projectile_explode(&mut commands, projectile_entity);
+                            spent.insert(projectile_entity);
+                        }
+                        ImpactBehaviour::Stick => {
+                            projectile_stick(&mut commands, projectile_entity);
+                            spent.insert(projectile_entity);
+                        }
+                    }
+                } else if projectile.bounces_left > 0 {
+                    // ricochet off the level: stays live, but loses some energy
+                    projectile.bounces_left -= 1;
+                    velocity.linvel *= projectile.bounce_damping;
+                } else {
+                    match projectile.impact {
+                        ImpactBehaviour::Explode | ImpactBehaviour::PassThrough => {
+                            feedback_events.send(FeedbackEvent::new(0.1, 0.2, position));
+                            projectile_explode(&mut commands, projectile_entity);
+                        }
+                        ImpactBehaviour::Stick => {
+                            projectile_stick(&mut commands, projectile_entity);
+                        }
+                    }
+                    spent.insert(projectile_entity);
                 }
             }
             CollisionEvent::Stopped(_, _, _) => (),
@@ -60,8 +130,10 @@ fn collision_droid_takeover_system(
     mut collision_events: EventReader<CollisionEvent>,
     player_query: Query<&Parent, With<PlayerMarker>>,
     ship_query: Query<(Entity, &Children), With<ShipMarker>>,
-    droid_query: Query<Entity, With<DroidOverloadMarker>>,
+    // only real droids can be taken over (i.e. no turrets)
+    droid_query: Query<(Entity, &GlobalTransform), (With<DroidOverloadMarker>, With<DroidMarker>)>,
     mut event_writer: EventWriter<PlayerTakeover>,
+    mut feedback_events: EventWriter<FeedbackEvent>,
 ) {
     for collision_event in collision_events.read() {
         if let CollisionEvent::Started(a, b, _) = collision_event {
@@ -83,7 +155,8 @@ fn collision_droid_takeover_system(
             else {
                 continue;
             };
-            let Ok(droid) = droid_query.get(*a).or_else(|_| droid_query.get(*b)) else {
+            let Ok((droid, droid_transform)) = droid_query.get(*a).or_else(|_| droid_query.get(*b))
+            else {
                 continue;
             };
 
@@ -91,7 +164,12 @@ fn collision_droid_takeover_system(
             event_writer.send(PlayerTakeover {
                 player,
                 target: droid,
-            })
+            });
+            feedback_events.send(FeedbackEvent::new(
+                0.6,
+                0.6,
+                droid_transform.translation().xy(),
+            ));
             // TODO
         }
     }
@@ -0,0 +1,270 @@
+// deployable entities: proximity mines and stationary turrets
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy_prototype_lyon::{prelude::*, shapes};
+use bevy_rapier2d::prelude::*;
+use rand_distr::Normal;
+
+use crate::{
+    collision_groups,
+    droid::{
+        ai::new_turret_ai, AiDroidBundle, AttackRequest, DroidHealth, DroidOverloadMarker,
+        WeaponDirection, WeaponState,
+    },
+    game::GameMarker,
+    hex_point_to_vec2,
+    particle::ColorGenerator,
+    prelude::*,
+    weapon::{ProjectileRules, WeaponEnergy, WeaponTarget},
+    HEX_LAYOUT,
+};
+use hexagon_tiles::layout::LayoutTool;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum DeployableKind {
+    #[default]
+    Mine,
+    Turret,
+}
+
+/// allows an entity to drop deployables via [`AttackRequest::secondary_attack`]
+#[derive(Component)]
+pub struct Deployer {
+    pub kind: DeployableKind,
+    pub count: u32,
+    pub cooldown: f32,
+}
+
+impl Default for Deployer {
+    fn default() -> Self {
+        Self {
+            kind: default(),
+            count: 5,
+            cooldown: 0.0,
+        }
+    }
+}
+
+pub const DEPLOY_COOLDOWN: f32 = 1.0;
+
+#[derive(Component)]
+pub struct ProximityMine {
+    pub owner: Entity,
+    // mine is inactive until this reaches zero
+    pub arm_timeout: f32,
+    // trigger distance
+    pub radius: f32,
+    // everything within this distance takes damage on detonation
+    pub blast_radius: f32,
+    pub damage: f32,
+}
+
+impl ProximityMine {
+    pub fn new(owner: Entity) -> Self {
+        Self {
+            owner,
+            arm_timeout: 1.5,
+            radius: 60.0,
+            blast_radius: 150.0,
+            damage: 60.0,
+        }
+    }
+}
+
+#[derive(Component, Default)]
+pub struct TurretMarker;
+
+/// spawn request for a turret on a tile (e.g. from level data). The request entity is
+/// despawned once the turret exists.
+#[derive(Component)]
+pub struct TurretSpawn {
+    pub tile_pos: TilePos,
+    pub team: Team,
+}
+
+pub fn spawn_mine(commands: &mut Commands, owner: Entity, team: Team, translation: Vec3) -> Entity {
+    let shape = shapes::Circle {
+        radius: 12.0,
+        ..default()
+    };
+    commands
+        .spawn(ShapeBundle {
+            path: GeometryBuilder::build_as(&shape),
+            spatial: SpatialBundle::from_transform(Transform::from_translation(translation)),
+            ..default()
+        })
+        .insert(default_stroke(YELLOW_HDR))
+        .insert(ProximityMine::new(owner))
+        .insert(team)
+        .insert(Name::new("mine"))
+        .insert(GameMarker)
+        .id()
+}
+
+pub fn spawn_turret(commands: &mut Commands, team: Team, translation: Vec3) -> Entity {
+    let shape = shapes::RegularPolygon {
+        sides: 4,
+        feature: shapes::RegularPolygonFeature::Radius(24.0),
+        ..default()
+    };
+    let color = if team == Team::Player {
+        GREEN_HDR
+    } else {
+        RED_HDR
+    };
+    let turret_ai = commands
+        .spawn((
+            AiDroidBundle::with_enemy(Entity::PLACEHOLDER),
+            new_turret_ai(),
+        ))
+        .id();
+    commands
+        .spawn(ShapeBundle {
+            path: GeometryBuilder::build_as(&shape),
+            spatial: SpatialBundle::from_transform(Transform::from_translation(translation)),
+            ..default()
+        })
+        .insert(default_stroke(color))
+        .insert((
+            Collider::ball(24.0),
+            CollisionGroups::new(
+                collision_groups::DROIDS,
+                collision_groups::DROIDS | collision_groups::PROJECTILES | collision_groups::LEVEL,
+            ),
+            RigidBody::Fixed,
+            ActiveEvents::COLLISION_EVENTS,
+        ))
+        .insert((
+            WeaponDirection::default(),
+            WeaponState::default(),
+            WeaponEnergy::default(),
+            AttackRequest::default(),
+            WeaponTarget::default(),
+            DroidHealth::default(),
+            CollisionFxType::Spark,
+        ))
+        .insert(team)
+        .insert(TurretMarker)
+        .insert(Name::new("turret"))
+        .insert(GameMarker)
+        .add_child(turret_ai)
+        .id()
+}
+
+fn deploy_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &AttackRequest,
+            &mut Deployer,
+            Option<&Team>,
+        ),
+        Without<DroidOverloadMarker>,
+    >,
+) {
+    for (entity, transform, attack_request, mut deployer, team) in &mut query {
+        deployer.cooldown = (deployer.cooldown - time.delta_seconds()).max(0.0);
+        if !attack_request.secondary_attack || deployer.cooldown > 0.0 || deployer.count == 0 {
+            continue;
+        }
+        deployer.cooldown = DEPLOY_COOLDOWN;
+        deployer.count -= 1;
+
+        let team = team.copied().unwrap_or_default();
+        let translation = transform.translation();
+        match deployer.kind {
+            DeployableKind::Mine => {
+                spawn_mine(&mut commands, entity, team, translation);
+            }
+            DeployableKind::Turret => {
+                spawn_turret(&mut commands, team, translation);
+            }
+        }
+    }
+}
+
+fn turret_spawn_system(mut commands: Commands, query: Query<(Entity, &TurretSpawn)>) {
+    for (entity, turret_spawn) in &query {
+        let center = hex_point_to_vec2(LayoutTool::hex_to_pixel(
+            HEX_LAYOUT,
+            turret_spawn.tile_pos.0,
+        ));
+        spawn_turret(&mut commands, turret_spawn.team, center.extend(0.0));
+        commands.entity(entity).despawn();
+    }
+}
+
+fn mine_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    rules: Res<ProjectileRules>,
+    mut mine_query: Query<(Entity, &GlobalTransform, &mut ProximityMine, &Team)>,
+    target_query: Query<(Entity, &GlobalTransform, &WeaponTarget, Option<&Team>)>,
+    mut health_query: Query<&mut DroidHealth>,
+) {
+    for (mine_entity, mine_transform, mut mine, mine_team) in &mut mine_query {
+        if mine.arm_timeout > 0.0 {
+            mine.arm_timeout -= time.delta_seconds();
+            continue;
+        }
+        let mine_pos = mine_transform.translation().xy();
+        let affected =
+            |target: Entity, target_team: Option<&Team>, weapon_target: &WeaponTarget| {
+                weapon_target.kinetic_projectile
+                    && rules.allows_hit(mine.owner, Some(*mine_team), target, target_team.copied())
+            };
+
+        let triggered =
+            target_query
+                .iter()
+                .any(|(target, target_transform, weapon_target, target_team)| {
+                    affected(target, target_team, weapon_target)
+                        && (target_transform.translation().xy() - mine_pos).length() <= mine.radius
+                });
+        if !triggered {
+            continue;
+        }
+
+        for (target, target_transform, weapon_target, target_team) in &target_query {
+            if !affected(target, target_team, weapon_target)
+                || (target_transform.translation().xy() - mine_pos).length() > mine.blast_radius
+            {
+                continue;
+            }
+            if let Ok(mut health) = health_query.get_mut(target) {
+                health.apply_damage(mine.damage);
+            }
+        }
+
+        commands
+            .spawn(SpatialBundle::from_transform(
+                mine_transform.compute_transform(),
+            ))
+            .insert(ParticleSource {
+                rate: 400,
+                direction: ParticleDirection::Uniform,
+                speed_distr: Normal::new(400.0, 100.0).unwrap(),
+                lifetime_distr: Normal::new(0.4, 0.1).unwrap(),
+                velocity_offset: Vec2::default(),
+                damping: default(),
+                initial_offset: 0.0,
+                color_generator: ColorGenerator::Static(1),
+            })
+            .insert(GameDespawn::frames_to_live(1));
+        commands.entity(mine_entity).despawn_recursive();
+    }
+}
+
+pub struct DeployablePlugin;
+impl Plugin for DeployablePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (deploy_system, mine_system).run_if(in_state(GameState::Game)),
+        )
+        .add_systems(PostUpdate, turret_spawn_system);
+    }
+}
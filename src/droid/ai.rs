@@ -1,10 +1,12 @@
+use crate::fog::{line_of_sight, pixel_to_tile};
 use crate::player::PlayerMarker;
 use crate::prelude::*;
-use crate::weapon::PROJECTILE_SPEED;
+use crate::weapon::{WeaponTarget, PROJECTILE_SPEED};
 use crate::{droid::WeaponState, weapon::Projectile};
 use bevy::{
     math::{Vec2Swizzles, Vec3Swizzles},
     prelude::*,
+    utils::FloatOrd,
 };
 use bevy_rapier2d::parry::{
     query::{self},
@@ -93,15 +95,53 @@ pub struct EnemyEvaluation {
 }
 
 fn enemy_select_system(
-    mut query: Query<&mut PrimaryEnemy>,
-
-    player_query: Query<Entity, With<PlayerMarker>>,
+    mut query: Query<(&mut PrimaryEnemy, &Parent, &GlobalTransform)>,
+    team_query: Query<&Team>,
+    tile_cache: Res<TileCache>,
+    player_query: Query<(Entity, &GlobalTransform), With<PlayerMarker>>,
+    hostile_query: Query<(Entity, &Team, &GlobalTransform), With<WeaponTarget>>,
 ) {
-    let Some(enemy) = player_query.iter().next() else {
-        return;
-    };
-    for mut primary_enemy in &mut query {
-        primary_enemy.enemy = enemy;
+    for (mut primary_enemy, parent, my_transform) in &mut query {
+        let my_pos = my_transform.translation().xy();
+        if matches!(team_query.get(parent.get()), Ok(Team::Player)) {
+            // AIs on the player side (e.g. deployed turrets) go for the closest hostile
+            if let Some((enemy, _, _)) = hostile_query
+                .iter()
+                .filter(|(_, team, _)| **team == Team::Hostile)
+                .min_by_key(|(_, _, transform)| {
+                    FloatOrd((transform.translation().xy() - my_pos).length())
+                })
+            {
+                primary_enemy.enemy = enemy;
+            }
+        } else {
+            // hostile AIs go for the closest player in line of sight. Without one they stay on
+            // their current player, or fall back to the closest one.
+            let my_tile = pixel_to_tile(my_pos);
+            let closest = |in_sight: bool| {
+                player_query
+                    .iter()
+                    .filter(|(_, transform)| {
+                        !in_sight
+                            || line_of_sight(
+                                &tile_cache,
+                                my_tile,
+                                pixel_to_tile(transform.translation().xy()),
+                            )
+                    })
+                    .min_by_key(|(_, transform)| {
+                        FloatOrd((transform.translation().xy() - my_pos).length())
+                    })
+                    .map(|(player, _)| player)
+            };
+            if let Some(player) = closest(true) {
+                primary_enemy.enemy = player;
+            } else if !player_query.contains(primary_enemy.enemy) {
+                if let Some(player) = closest(false) {
+                    primary_enemy.enemy = player;
+                }
+            }
+        }
     }
 }
 fn enemy_evaluation_system(
@@ -208,7 +248,12 @@ fn assault_predict_system(
         let Ok(weapon_state) = droid_query.get(parent.get()) else {
             continue;
         };
-        let Ok(enemy_parent) = player_query.get(*enemy) else {
+        // the enemy is either a player (which is attached to its vessel) or a body itself
+        let enemy_body = if enemy_query.contains(*enemy) {
+            *enemy
+        } else if let Ok(enemy_parent) = player_query.get(*enemy) {
+            enemy_parent.get()
+        } else {
             continue;
         };
         if let Ok((
@@ -217,7 +262,7 @@ fn assault_predict_system(
                 linvel: enemy_velocity,
                 ..
             },
-        )) = enemy_query.get(enemy_parent.get())
+        )) = enemy_query.get(enemy_body)
         {
             if weapon_state.reload_timeout > f32::EPSILON {
                 // enemy not moving
@@ -378,7 +423,10 @@ impl Plugin for AiPlugin {
                 actions::idle_action_system.in_set(BigBrainSet::Actions),
                 actions::evade_enemy_action_system.in_set(BigBrainSet::Actions),
                 actions::evade_projectile_action_system.in_set(BigBrainSet::Actions),
-                actions::roam_action_system.in_set(BigBrainSet::Actions),
+                // both draw from GameRng, a fixed order keeps replays identical
+                actions::roam_action_system
+                    .in_set(BigBrainSet::Actions)
+                    .after(actions::evade_projectile_action_system),
             )
                 .run_if(in_state(GameState::Game)),
         );
@@ -422,3 +470,12 @@ pub fn new_shooting_droid_ai() -> ThinkerBuilder {
         .when(scorers::IdleBoredomScore, actions::RoamAction::default())
         .when(FixedScore::build(0.1), actions::IdleAction)
 }
+
+// stationary variant of the shooting droid: never evades or roams
+pub fn new_turret_ai() -> ThinkerBuilder {
+    Thinker::build()
+        .label("turret")
+        .picker(Highest)
+        .when(scorers::EnemyHitScore, actions::ShootAction)
+        .when(FixedScore::build(0.1), actions::IdleAction)
+}
@@ -1,8 +1,11 @@
 use super::{EnemyEvaluation, IncomingProjectile, PredictedHit};
-use crate::droid::{ai::DIRECTIONS, AttackRequest, TargetDirection, WeaponDirection};
+use crate::{
+    droid::{ai::DIRECTIONS, AttackRequest, TargetDirection, WeaponDirection},
+    rng::GameRng,
+};
 use bevy::{prelude::*, utils::FloatOrd};
 use big_brain::prelude::*;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 #[derive(Component, Debug, Clone, ActionBuilder)]
 pub struct ShootAction;
@@ -141,6 +144,7 @@ pub fn evade_projectile_action_system(
     ai_query: Query<&Parent>,
     mut direction_query: Query<&mut TargetDirection>,
     incoming_query: Query<&IncomingProjectile>,
+    mut rng: ResMut<GameRng>,
 ) {
     for (Actor(actor), mut state, mut evade) in &mut query {
         info!("evade projectile action {:?}", state);
@@ -151,7 +155,6 @@ pub fn evade_projectile_action_system(
                 };
                 if let Ok(_target_direction) = direction_query.get_mut(parent.get()) {
                     if let Ok(incoming_projectile) = incoming_query.get(*actor) {
-                        let mut rng = thread_rng();
                         if let Some(dir) = DIRECTIONS.iter().min_by_key(|dir| {
                             // if move_sideways {
                             //     1.0 - enemy_dir.dot(*dir).abs()
@@ -213,13 +216,12 @@ pub fn roam_action_system(
     mut query: Query<(&Actor, &mut ActionState, &mut RoamAction)>,
     ai_query: Query<&Parent>,
     mut direction_query: Query<&mut TargetDirection>,
+    mut rng: ResMut<GameRng>,
 ) {
     for (Actor(actor), mut state, mut roam) in &mut query {
         debug!("roam action {:?}", state);
         match *state {
             ActionState::Requested => {
-                let mut rng = thread_rng();
-
                 roam.direction = DIRECTIONS[rng.gen_range(0..6)];
                 roam.timer = Timer::from_seconds(0.5, TimerMode::Once);
                 *state = ActionState::Executing;
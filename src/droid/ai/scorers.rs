@@ -5,7 +5,10 @@ use big_brain::{
     prelude::*,
 };
 
-use crate::droid::MovementStats;
+use crate::{
+    droid::{MovementStats, WeaponState},
+    weapon::WeaponEnergy,
+};
 
 use super::{EnemyEvaluation, IncomingProjectile, PredictedHit};
 
@@ -14,14 +17,24 @@ pub struct EnemyHitScore;
 
 pub fn enemy_hit_score_system(
     mut scorer_query: Query<(&Actor, &mut Score), With<EnemyHitScore>>,
-    predict_query: Query<&PredictedHit>,
+    predict_query: Query<(&PredictedHit, &Parent)>,
+    weapon_query: Query<(&WeaponState, &WeaponEnergy)>,
 ) {
     for (Actor(actor), mut score) in &mut scorer_query {
-        if let Ok(predicted) = predict_query.get(*actor) {
+        if let Ok((predicted, parent)) = predict_query.get(*actor) {
             // if projectile and enemy are predicted to reach intersection at roughly the
             // same time, shoot in this direction.
             let dt = predicted.dt;
-            let value = LinearEvaluator::new_ranged(0.5, 0.0).evaluate(dt);
+            let mut value = LinearEvaluator::new_ranged(0.5, 0.0).evaluate(dt);
+
+            // save energy for good shots: the lower the energy the more reluctant to shoot
+            if let Ok((weapon_state, weapon_energy)) = weapon_query.get(parent.get()) {
+                value *= if weapon_energy.can_fire(weapon_state.kind) {
+                    LinearEvaluator::new_ranged(0.0, 0.5).evaluate(weapon_energy.energy_fraction())
+                } else {
+                    0.0
+                };
+            }
             // info!("hit: {}", value);
             score.set(value);
         }
@@ -1,13 +1,22 @@
 use self::ai::{EnemyEvaluation, PredictedHit, PrimaryEnemy};
 use crate::collision_groups;
-use crate::weapon::WeaponTarget;
+use crate::weapon::{ImpactBehaviour, ProjectileRules, WeaponEnergy, WeaponKind, WeaponTarget};
 use crate::{
-    collision::CollisionFxType, prelude::*, weapon, weapon::kinetic_projectile_shape_bundle,
+    collision::CollisionFxType,
+    feedback::FeedbackEvent,
+    particle::ColorGenerator,
+    pickup::{spawn_pickup, PickupKind, DROP_CHANCE},
+    prelude::*,
+    rng::GameRng,
+    weapon,
+    weapon::kinetic_projectile_shape_bundle,
 };
-use bevy::prelude::*;
+use bevy::{math::Vec3Swizzles, prelude::*};
 use bevy_prototype_lyon::prelude::Stroke;
 use bevy_rapier2d::prelude::*;
 use rand::Rng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
 use std::{borrow::Cow, time::Duration};
 
 pub mod ai;
@@ -26,14 +35,38 @@ pub struct DroidMarker;
 #[derive(Component)]
 pub struct GroundFriction;
 
-#[derive(Component, Default)]
+#[derive(Component)]
 pub struct WeaponDirection {
-    direction: Vec2,
+    pub direction: Vec2,
+    // if true, the weapon points along the last movement direction. Cleared for free aiming.
+    pub follow_movement: bool,
 }
 
-#[derive(Component, Default)]
+impl Default for WeaponDirection {
+    fn default() -> Self {
+        Self {
+            direction: Vec2::ZERO,
+            follow_movement: true,
+        }
+    }
+}
+
+#[derive(Component)]
 pub struct WeaponState {
     pub reload_timeout: f32,
+    // reload_timeout after each shot
+    pub reload_time: f32,
+    pub kind: WeaponKind,
+}
+
+impl Default for WeaponState {
+    fn default() -> Self {
+        Self {
+            reload_timeout: 0.0,
+            reload_time: RELOAD_TIMEOUT,
+            kind: default(),
+        }
+    }
 }
 
 #[derive(Component, Default)]
@@ -44,6 +77,7 @@ pub struct TargetDirection {
 #[derive(Component, Default)]
 pub struct AttackRequest {
     pub primary_attack: bool,
+    pub secondary_attack: bool,
 }
 
 #[derive(Component, Default)]
@@ -80,7 +114,9 @@ fn droid_apply_direction_system(
     {
         if target_direction.direction.length() > f32::EPSILON {
             external_impulse.impulse = IMPULSE_MULTIPLIER * target_direction.direction;
-            weapon_direction.direction = target_direction.direction;
+            if weapon_direction.follow_movement {
+                weapon_direction.direction = target_direction.direction;
+            }
             movement_stats.idle_duration = default();
         } else {
             movement_stats.idle_duration += time.delta();
@@ -88,9 +124,11 @@ fn droid_apply_direction_system(
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn droid_attack_system(
     mut commands: Commands,
     time: Res<Time>,
+    rules: Res<ProjectileRules>,
     mut query: Query<
         (
             Entity,
@@ -98,6 +136,8 @@ fn droid_attack_system(
             &AttackRequest,
             &mut WeaponState,
             &WeaponDirection,
+            Option<&mut WeaponEnergy>,
+            Option<&DroidClass>,
         ),
         Without<DroidOverloadMarker>,
     >,
@@ -105,42 +145,71 @@ fn droid_attack_system(
     for (
         entity,
         Transform { translation, .. },
-        AttackRequest { primary_attack },
+        AttackRequest { primary_attack, .. },
         mut weapon_state,
         weapon_direction,
+        weapon_energy,
+        class,
     ) in query.iter_mut()
     {
         weapon_state.reload_timeout = (weapon_state.reload_timeout - time.delta_seconds()).max(0.0);
         if !primary_attack || weapon_state.reload_timeout > f32::EPSILON {
             continue;
         }
-        weapon_state.reload_timeout = RELOAD_TIMEOUT;
-        commands
-            .spawn(weapon::KineticProjectileBundle::with_direction(
-                entity,
-                // *translation,
-                weapon_direction.direction,
-            ))
-            .insert(kinetic_projectile_shape_bundle(
-                *translation,
-                weapon_direction.direction,
-            ))
-            .insert(Stroke::new(GREEN_HDR, 10.0));
+        if let Some(mut weapon_energy) = weapon_energy {
+            if !weapon_energy.can_fire(weapon_state.kind) {
+                continue;
+            }
+            weapon_energy.fire(weapon_state.kind);
+        }
+        weapon_state.reload_timeout = weapon_state.reload_time;
+        match weapon_state.kind {
+            WeaponKind::Kinetic => {
+                let class = class.copied().unwrap_or_default();
+                let (bounces, bounce_damping) = class.projectile_bounces();
+                commands
+                    .spawn(
+                        weapon::KineticProjectileBundle::with_direction(
+                            entity,
+                            // *translation,
+                            weapon_direction.direction,
+                            &rules,
+                        )
+                        .with_bounces(bounces, bounce_damping)
+                        .with_impact(class.projectile_impact()),
+                    )
+                    .insert(kinetic_projectile_shape_bundle(
+                        *translation,
+                        weapon_direction.direction,
+                    ))
+                    .insert(Stroke::new(GREEN_HDR, 10.0));
+            }
+            WeaponKind::Wave => {
+                commands.spawn(weapon::WaveAttackBundle::wave_attack(entity, *translation));
+            }
+        }
     }
 }
 
 fn droid_overload_system(
     mut commands: Commands,
-    query: Query<(Entity, &DroidHealth), Without<DroidOverloadMarker>>,
+    query: Query<(Entity, &DroidHealth, &GlobalTransform), Without<DroidOverloadMarker>>,
     mut query_overload: Query<
         (Entity, &mut DroidHealth, &mut Transform),
         With<DroidOverloadMarker>,
     >,
+    mut rng: ResMut<GameRng>,
+    mut feedback_events: EventWriter<FeedbackEvent>,
 ) {
     //
-    for (entity, health) in &query {
+    for (entity, health, global_transform) in &query {
         if health.emp_load >= 1.0 {
             commands.entity(entity).insert(DroidOverloadMarker);
+            feedback_events.send(FeedbackEvent::new(
+                0.4,
+                0.5,
+                global_transform.translation().xy(),
+            ));
         }
     }
     for (entity, mut health, mut transform) in &mut query_overload {
@@ -148,7 +217,6 @@ fn droid_overload_system(
             commands.entity(entity).remove::<DroidOverloadMarker>();
             transform.scale = Vec3::ONE;
         } else {
-            let mut rng = rand::thread_rng();
             transform.scale = Vec3::splat(rng.gen_range(0.95..1.05));
         }
         health.emp_load = (health.emp_load - 0.005).max(0.0);
@@ -171,6 +239,7 @@ pub struct DroidBundle {
     // pub ground_friction: GroundFriction,
     pub weapon_direction: WeaponDirection,
     pub weapon_state: WeaponState,
+    pub weapon_energy: WeaponEnergy,
     pub target_direction: TargetDirection,
     pub attack_request: AttackRequest,
     pub movement_stats: MovementStats,
@@ -184,6 +253,47 @@ pub struct DroidBundle {
     pub weapon_target: WeaponTarget,
     pub droid_marker: DroidMarker,
     pub health: DroidHealth,
+    pub class: DroidClass,
+}
+
+/// droid classes differ in which weapons they are immune against and in how their projectiles
+/// ricochet and impact.
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DroidClass {
+    #[default]
+    Standard,
+    // immune to kinetic projectiles
+    Shielded,
+    // immune to EMP wave attacks
+    Hardened,
+}
+
+impl DroidClass {
+    pub fn weapon_target(&self) -> WeaponTarget {
+        match self {
+            DroidClass::Standard => WeaponTarget::default(),
+            DroidClass::Shielded => WeaponTarget::immune_to_kinetic(),
+            DroidClass::Hardened => WeaponTarget::immune_to_wave(),
+        }
+    }
+
+    /// ricochets off the level and velocity factor per ricochet of the projectiles fired by
+    /// this class
+    pub fn projectile_bounces(&self) -> (u32, f32) {
+        match self {
+            DroidClass::Standard => (2, 0.8),
+            DroidClass::Shielded => (0, 1.0),
+            DroidClass::Hardened => (4, 0.9),
+        }
+    }
+
+    pub fn projectile_impact(&self) -> ImpactBehaviour {
+        match self {
+            DroidClass::Standard => ImpactBehaviour::Explode,
+            DroidClass::Shielded => ImpactBehaviour::Stick,
+            DroidClass::Hardened => ImpactBehaviour::PassThrough,
+        }
+    }
 }
 
 impl DroidBundle {
@@ -208,14 +318,21 @@ impl DroidBundle {
             collider: Collider::ball(28.0),
             collision_groups: CollisionGroups::new(
                 collision_groups::DROIDS,
-                collision_groups::DROIDS | collision_groups::PROJECTILES | collision_groups::LEVEL,
+                collision_groups::DROIDS
+                    | collision_groups::PROJECTILES
+                    | collision_groups::LEVEL
+                    | collision_groups::PICKUPS,
             ),
             rigid_body: RigidBody::Dynamic,
             locked_axes,
             velocity: Velocity::default(),
             name: Name::new(name),
-            weapon_direction: WeaponDirection { direction: Vec2::X },
+            weapon_direction: WeaponDirection {
+                direction: Vec2::X,
+                ..default()
+            },
             weapon_state: default(),
+            weapon_energy: default(),
             external_force: default(),
             external_impulse: default(),
             target_direction: default(),
@@ -231,13 +348,94 @@ impl DroidBundle {
             weapon_target: default(),
             droid_marker: default(),
             health: default(),
+            class: default(),
         }
     }
+
+    pub fn with_class(mut self, class: DroidClass) -> Self {
+        self.class = class;
+        self.weapon_target = class.weapon_target();
+        self
+    }
 }
 
-#[derive(Component, Default)]
+#[derive(Component)]
 pub struct DroidHealth {
     pub emp_load: f32,
+    pub hitpoints: f32,
+    pub max_hitpoints: f32,
+    // absorbs damage before hitpoints are affected
+    pub shield: f32,
+    // owner of the last projectile that hit, credited if the droid is destroyed
+    pub last_hit_by: Option<Entity>,
+}
+
+pub const DROID_HITPOINTS: f32 = 100.0;
+
+impl Default for DroidHealth {
+    fn default() -> Self {
+        Self {
+            emp_load: 0.0,
+            hitpoints: DROID_HITPOINTS,
+            max_hitpoints: DROID_HITPOINTS,
+            shield: 0.0,
+            last_hit_by: None,
+        }
+    }
+}
+
+impl DroidHealth {
+    pub fn apply_damage(&mut self, damage: f32) {
+        let absorbed = damage.min(self.shield);
+        self.shield -= absorbed;
+        self.hitpoints -= damage - absorbed;
+    }
+}
+
+#[derive(Event)]
+pub struct DroidDestroyed {
+    pub droid: Entity,
+    pub destroyed_by: Option<Entity>,
+}
+
+fn droid_destroyed_system(
+    mut commands: Commands,
+    query: Query<(Entity, &DroidHealth, &GlobalTransform)>,
+    mut rng: ResMut<GameRng>,
+    mut destroyed_events: EventWriter<DroidDestroyed>,
+) {
+    for (entity, health, transform) in &query {
+        if health.hitpoints > 0.0 {
+            continue;
+        }
+        info!("droid destroyed: {:?}", entity);
+        destroyed_events.send(DroidDestroyed {
+            droid: entity,
+            destroyed_by: health.last_hit_by,
+        });
+        commands
+            .spawn(SpatialBundle::from_transform(transform.compute_transform()))
+            .insert(ParticleSource {
+                rate: 600,
+                direction: ParticleDirection::Uniform,
+                speed_distr: Normal::new(300.0, 120.0).unwrap(),
+                lifetime_distr: Normal::new(1.0, 0.4).unwrap(),
+                velocity_offset: Vec2::default(),
+                damping: default(),
+                initial_offset: 0.0,
+                color_generator: ColorGenerator::Random,
+            })
+            .insert(GameDespawn::frames_to_live(1));
+        commands.entity(entity).despawn_recursive();
+
+        if rng.gen_bool(DROP_CHANCE) {
+            spawn_pickup(
+                &mut commands,
+                PickupKind::random(&mut **rng),
+                transform.translation(),
+            );
+        }
+    }
 }
 #[derive(Component)]
 pub struct DroidOverloadMarker;
@@ -267,7 +465,7 @@ pub struct DroidPlugin;
 
 impl Plugin for DroidPlugin {
     fn build(&self, app: &mut App) {
-        app
+        app.add_event::<DroidDestroyed>()
             // .add_system(droid_stop_system)
             .add_systems(
                 Update,
@@ -275,8 +473,67 @@ impl Plugin for DroidPlugin {
                     droid_apply_direction_system, //.after(droid_stop_system))
                     droid_attack_system,
                     droid_overload_system,
+                    // both draw from GameRng, a fixed order keeps replays identical
+                    droid_destroyed_system.after(droid_overload_system),
                 )
                     .run_if(in_state(GameState::Game)),
             );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(seed: u64) -> (Vec<Vec3>, u64) {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, DroidPlugin))
+            .add_state::<GameState>()
+            .add_event::<FeedbackEvent>()
+            .init_resource::<ProjectileRules>()
+            .insert_resource(GameRng::from_seed(seed));
+        app.world
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Game);
+        for i in 0..4 {
+            let transform = Transform::from_xyz(i as f32 * 100.0, 0.0, 0.0);
+            // overloaded droids jitter in size
+            app.world.spawn((
+                DroidHealth {
+                    emp_load: 0.9,
+                    ..default()
+                },
+                DroidOverloadMarker,
+                transform,
+                GlobalTransform::from(transform),
+            ));
+            // destroyed droids may drop a pickup
+            app.world.spawn((
+                DroidHealth {
+                    hitpoints: 0.0,
+                    ..default()
+                },
+                transform,
+                GlobalTransform::from(transform),
+            ));
+        }
+        let mut scales = Vec::new();
+        for _ in 0..10 {
+            app.update();
+            let mut query = app
+                .world
+                .query_filtered::<&Transform, With<DroidOverloadMarker>>();
+            scales.extend(query.iter(&app.world).map(|transform| transform.scale));
+        }
+        let next = app.world.resource_mut::<GameRng>().gen();
+        (scales, next)
+    }
+
+    #[test]
+    fn same_seed_same_draws() {
+        let (scales, next) = run(1234);
+        assert_eq!(scales.len(), 40);
+        assert!(scales.iter().any(|scale| *scale != Vec3::ONE));
+        assert_eq!(run(1234), (scales, next));
+    }
+}
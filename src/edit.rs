@@ -1,4 +1,10 @@
-use crate::{portal::PortalToggleRequest, prelude::*, HEX_LAYOUT};
+use crate::{
+    action::{ActionInput, InputAction},
+    input::InputSet,
+    portal::PortalToggleRequest,
+    prelude::*,
+    HEX_LAYOUT,
+};
 use bevy::prelude::*;
 use bevy_mouse_tracking_plugin::{MousePos, MousePosWorld};
 use hexagon_tiles::{hexagon::HexRound, layout::LayoutTool, point::Point};
@@ -27,8 +33,8 @@ fn mouse_input_system(
     }
 }
 
-fn edit_command_system(input: Res<Input<KeyCode>>, tile_query: Query<&TilePos>) {
-    if input.just_pressed(KeyCode::F5) {
+fn edit_command_system(action_input: ActionInput, tile_query: Query<&TilePos>) {
+    if action_input.just_pressed(InputAction::EditorSave) {
         if let Ok(mut file) = std::fs::File::create("/tmp/level.txt") {
             for tile in &tile_query {
                 write!(file, "{} {} {}\n", tile.0.q(), tile.0.r(), tile.0.s());
@@ -40,6 +46,12 @@ fn edit_command_system(input: Res<Input<KeyCode>>, tile_query: Query<&TilePos>)
 pub struct EditPlugin;
 impl Plugin for EditPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (mouse_input_system, edit_command_system));
+        app.add_systems(
+            Update,
+            (
+                mouse_input_system.in_set(InputSet::Read),
+                edit_command_system,
+            ),
+        );
     }
 }
@@ -0,0 +1,115 @@
+//! impact feedback. Gameplay systems send FeedbackEvents, the camera turns them into a
+//! trauma-based screen shake (shake = trauma^2, trauma decays over the event duration).
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+
+use crate::camera::{track_camera_system, CameraRigState};
+
+/// something that should be felt: `intensity` (0..1) adds trauma, which decays within `duration`
+/// seconds. The effect fades with the distance of `position` to the camera.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct FeedbackEvent {
+    pub intensity: f32,
+    pub duration: f32,
+    pub position: Vec2,
+}
+
+impl FeedbackEvent {
+    pub fn new(intensity: f32, duration: f32, position: Vec2) -> Self {
+        Self {
+            intensity,
+            duration,
+            position,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct FeedbackSettings {
+    // can be switched off for people sensitive to motion
+    pub screen_shake: bool,
+    pub max_offset: f32,
+    // events further away from the camera than this have no effect
+    pub range: f32,
+}
+
+impl Default for FeedbackSettings {
+    fn default() -> Self {
+        Self {
+            screen_shake: true,
+            max_offset: 20.0,
+            range: 1500.0,
+        }
+    }
+}
+
+#[derive(Component, Default)]
+pub struct ScreenShake {
+    trauma: f32,
+    // trauma per second
+    decay: f32,
+    time: f32,
+}
+
+// sum of sines with incommensurable frequencies: smooth, non-repeating and independent of the
+// game rng (so it does not disturb replays)
+fn shake_noise(t: f32, phase: f32) -> f32 {
+    0.5 * (13.1 * t + phase).sin() + 0.3 * (29.7 * t + 2.0 * phase).sin() + 0.2 * (5.3 * t).sin()
+}
+
+fn feedback_event_system(
+    settings: Res<FeedbackSettings>,
+    mut events: EventReader<FeedbackEvent>,
+    mut camera_query: Query<(&GlobalTransform, &mut ScreenShake)>,
+) {
+    for event in events.read() {
+        // with a split view each camera shakes for what happens in its half
+        for (camera_transform, mut shake) in &mut camera_query {
+            let distance = camera_transform.translation().xy().distance(event.position);
+            let falloff = (1.0 - distance / settings.range).clamp(0.0, 1.0);
+            let intensity = event.intensity * falloff;
+            if intensity <= 0.0 {
+                continue;
+            }
+            shake.trauma = (shake.trauma + intensity).min(1.0);
+            shake.decay = shake.decay.max(intensity / event.duration.max(0.01));
+        }
+    }
+}
+
+fn screen_shake_system(
+    time: Res<Time>,
+    settings: Res<FeedbackSettings>,
+    mut camera_query: Query<(&mut Transform, &mut ScreenShake), With<CameraRigState>>,
+) {
+    let dt = time.delta_seconds();
+    for (mut transform, mut shake) in &mut camera_query {
+        shake.time += dt;
+        shake.trauma = (shake.trauma - shake.decay * dt).max(0.0);
+        if shake.trauma <= 0.0 {
+            shake.decay = 0.0;
+            continue;
+        }
+        if !settings.screen_shake {
+            continue;
+        }
+        // the rig resets the translation every frame, so the offset does not accumulate
+        let amount = shake.trauma * shake.trauma * settings.max_offset * transform.scale.x;
+        let offset = Vec2::new(shake_noise(shake.time, 0.0), shake_noise(shake.time, 1.7)) * amount;
+        transform.translation += offset.extend(0.0);
+    }
+}
+
+pub struct FeedbackPlugin;
+impl Plugin for FeedbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<FeedbackEvent>()
+            .init_resource::<FeedbackSettings>()
+            .add_systems(
+                Update,
+                (feedback_event_system, screen_shake_system)
+                    .chain()
+                    .after(track_camera_system),
+            );
+    }
+}
@@ -0,0 +1,311 @@
+//! fog of war. Every hex is unseen, seen (explored before) or visible (in line of sight of a
+//! player right now). Edge loops are dimmed outside of the visible area and hostile droids are
+//! only shown while visible. `line_of_sight` is shared with the AI perception.
+
+use bevy::{math::Vec3Swizzles, prelude::*, utils::HashSet};
+use bevy_prototype_lyon::prelude::Stroke;
+use hexagon_tiles::{
+    hexagon::{Hex, HexMath, HexRound},
+    layout::LayoutTool,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    droid::DroidMarker, hex_point_to_vec2, player::PlayerMarker, prelude::*, tiles::BoundaryMarker,
+    vec2_to_hex_point, HEX_LAYOUT,
+};
+
+// sight radius in hexes
+pub const SIGHT_RADIUS: i32 = 12;
+// brightness of edge loops that were seen but are not visible
+const DIM_FACTOR: f32 = 0.25;
+// keeps sampled points off the hex corners, where the rounding is ambiguous
+const LINE_NUDGE: Vec2 = Vec2::new(1e-3, 2e-3);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HexVisibility {
+    #[default]
+    Unseen,
+    Seen,
+    Visible,
+}
+
+#[derive(Resource)]
+pub struct FogOfWar {
+    pub enabled: bool,
+    // false while there are no players, everything counts as visible then
+    active: bool,
+    explored: HashSet<TilePos>,
+    visible: HashSet<TilePos>,
+    // player hexes the visible set was computed for
+    viewpoints: Vec<TilePos>,
+}
+
+impl Default for FogOfWar {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            active: false,
+            explored: default(),
+            visible: default(),
+            viewpoints: default(),
+        }
+    }
+}
+
+/// explored hexes (q, r) as stored in a saved game
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ExploredTiles(pub Vec<[i32; 2]>);
+
+impl FogOfWar {
+    pub fn state(&self, tile_pos: TilePos) -> HexVisibility {
+        if !self.active || self.visible.contains(&tile_pos) {
+            HexVisibility::Visible
+        } else if self.explored.contains(&tile_pos) {
+            HexVisibility::Seen
+        } else {
+            HexVisibility::Unseen
+        }
+    }
+
+    pub fn state_at(&self, pos: Vec2) -> HexVisibility {
+        self.state(pixel_to_tile(pos))
+    }
+
+    pub fn is_visible_at(&self, pos: Vec2) -> bool {
+        self.state_at(pos) == HexVisibility::Visible
+    }
+
+    pub fn explored_tiles(&self) -> ExploredTiles {
+        ExploredTiles(
+            self.explored
+                .iter()
+                .map(|tile_pos| [tile_pos.0.q(), tile_pos.0.r()])
+                .collect(),
+        )
+    }
+
+    pub fn restore_explored(&mut self, explored: &ExploredTiles) {
+        self.explored = explored
+            .0
+            .iter()
+            .map(|[q, r]| TilePos(Hex::new(*q, *r)))
+            .collect();
+        // force a recomputation of the visible set
+        self.viewpoints.clear();
+    }
+
+    fn reset(&mut self) {
+        self.explored.clear();
+        self.visible.clear();
+        self.viewpoints.clear();
+    }
+}
+
+pub fn pixel_to_tile(pos: Vec2) -> TilePos {
+    TilePos(LayoutTool::pixel_to_hex(HEX_LAYOUT, vec2_to_hex_point(pos)).round())
+}
+
+fn tile_center(tile_pos: TilePos) -> Vec2 {
+    hex_point_to_vec2(LayoutTool::hex_to_pixel(HEX_LAYOUT, tile_pos.0))
+}
+
+pub fn hex_distance(a: TilePos, b: TilePos) -> i32 {
+    let d = a.0.sub(b.0);
+    (d.q().abs() + d.r().abs() + d.s().abs()) / 2
+}
+
+/// true if no wall tile lies strictly between the two hexes. Samples the straight line
+/// between the hex centers once per hex step.
+pub fn line_of_sight(tile_cache: &TileCache, from: TilePos, to: TilePos) -> bool {
+    let steps = hex_distance(from, to);
+    let a = tile_center(from) + LINE_NUDGE;
+    let b = tile_center(to) + LINE_NUDGE;
+    (1..steps).all(|i| {
+        let tile_pos = pixel_to_tile(a.lerp(b, i as f32 / steps as f32));
+        !tile_cache.tiles.contains_key(&tile_pos)
+    })
+}
+
+fn fog_reset_system(mut fog: ResMut<FogOfWar>) {
+    fog.reset();
+}
+
+fn fog_update_system(
+    mut fog: ResMut<FogOfWar>,
+    tile_cache: Res<TileCache>,
+    player_query: Query<&GlobalTransform, With<PlayerMarker>>,
+) {
+    let active = fog.enabled && !player_query.is_empty();
+    if fog.active != active {
+        fog.active = active;
+    }
+    if !active {
+        return;
+    }
+    let viewpoints: Vec<_> = player_query
+        .iter()
+        .map(|transform| pixel_to_tile(transform.translation().xy()))
+        .collect();
+    if viewpoints == fog.viewpoints && !tile_cache.is_changed() {
+        return;
+    }
+
+    let mut visible = HashSet::new();
+    for center in &viewpoints {
+        for dq in -SIGHT_RADIUS..=SIGHT_RADIUS {
+            let r_min = (-SIGHT_RADIUS).max(-dq - SIGHT_RADIUS);
+            let r_max = SIGHT_RADIUS.min(-dq + SIGHT_RADIUS);
+            for dr in r_min..=r_max {
+                let tile_pos = TilePos(center.0.add(Hex::new(dq, dr)));
+                if !visible.contains(&tile_pos) && line_of_sight(&tile_cache, *center, tile_pos) {
+                    visible.insert(tile_pos);
+                }
+            }
+        }
+    }
+    let fog = &mut *fog;
+    fog.explored.extend(visible.iter().copied());
+    fog.visible = visible;
+    fog.viewpoints = viewpoints;
+}
+
+#[derive(Component)]
+struct FogBaseColor(Color);
+
+/// dim edge loops without visible tiles, hide those without explored tiles
+#[allow(clippy::type_complexity)]
+fn fog_edge_loop_system(
+    mut commands: Commands,
+    fog: Res<FogOfWar>,
+    tile_query: Query<&TilePos>,
+    added_query: Query<(), Added<BoundaryMarker>>,
+    mut loop_query: Query<(
+        Entity,
+        &BoundaryMarker,
+        &mut Stroke,
+        &mut Visibility,
+        Option<&FogBaseColor>,
+    )>,
+) {
+    if !fog.is_changed() && added_query.is_empty() {
+        return;
+    }
+    for (entity, boundary, mut stroke, mut visibility, base_color) in &mut loop_query {
+        let base_color = match base_color {
+            Some(FogBaseColor(color)) => *color,
+            None => {
+                commands.entity(entity).insert(FogBaseColor(stroke.color));
+                stroke.color
+            }
+        };
+        let state = boundary
+            .tiles
+            .iter()
+            .filter_map(|tile| tile_query.get(*tile).ok())
+            .map(|tile_pos| fog.state(*tile_pos))
+            .max()
+            .unwrap_or_default();
+        *visibility = if state == HexVisibility::Unseen {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+        stroke.color = if state == HexVisibility::Seen {
+            let [r, g, b, a] = base_color.as_rgba_f32();
+            Color::rgba(r * DIM_FACTOR, g * DIM_FACTOR, b * DIM_FACTOR, a)
+        } else {
+            base_color
+        };
+    }
+}
+
+/// droids not on the player's side are only shown while visible
+fn fog_droid_system(
+    fog: Res<FogOfWar>,
+    mut droid_query: Query<(&GlobalTransform, Option<&Team>, &mut Visibility), With<DroidMarker>>,
+) {
+    for (transform, team, mut visibility) in &mut droid_query {
+        let shown =
+            matches!(team, Some(Team::Player)) || fog.is_visible_at(transform.translation().xy());
+        let new_visibility = if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != new_visibility {
+            *visibility = new_visibility;
+        }
+    }
+}
+
+pub struct FogPlugin;
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FogOfWar>()
+            .add_systems(OnExit(GameState::None), fog_reset_system)
+            .add_systems(
+                Update,
+                (fog_update_system, fog_edge_loop_system, fog_droid_system).chain(),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(q: i32, r: i32) -> TilePos {
+        TilePos(Hex::new(q, r))
+    }
+
+    fn tile_cache(walls: &[TilePos]) -> TileCache {
+        let mut tile_cache = TileCache::default();
+        for (i, wall) in walls.iter().enumerate() {
+            tile_cache.tiles.insert(*wall, Entity::from_raw(i as u32));
+        }
+        tile_cache
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(hex_distance(tile(0, 0), tile(0, 0)), 0);
+        assert_eq!(hex_distance(tile(0, 0), tile(1, 0)), 1);
+        assert_eq!(hex_distance(tile(0, 0), tile(1, -1)), 1);
+        assert_eq!(hex_distance(tile(0, 0), tile(3, -1)), 3);
+        assert_eq!(hex_distance(tile(2, 3), tile(-1, 1)), 5);
+        assert_eq!(
+            hex_distance(tile(-4, 2), tile(3, -5)),
+            hex_distance(tile(3, -5), tile(-4, 2))
+        );
+    }
+
+    #[test]
+    fn tile_center_round_trip() {
+        for q in -3..=3 {
+            for r in -3..=3 {
+                assert_eq!(pixel_to_tile(tile_center(tile(q, r))), tile(q, r));
+            }
+        }
+    }
+
+    #[test]
+    fn line_of_sight_open() {
+        let tile_cache = tile_cache(&[]);
+        assert!(line_of_sight(&tile_cache, tile(0, 0), tile(0, 0)));
+        assert!(line_of_sight(&tile_cache, tile(0, 0), tile(4, -2)));
+    }
+
+    #[test]
+    fn line_of_sight_blocked() {
+        let tile_cache = tile_cache(&[tile(1, 0)]);
+        assert!(!line_of_sight(&tile_cache, tile(0, 0), tile(2, 0)));
+        assert!(!line_of_sight(&tile_cache, tile(2, 0), tile(0, 0)));
+        assert!(!line_of_sight(&tile_cache, tile(0, 0), tile(3, 0)));
+        // the end points themselves don't block, walls are visible
+        assert!(line_of_sight(&tile_cache, tile(0, 0), tile(1, 0)));
+        // lines passing by the wall
+        assert!(line_of_sight(&tile_cache, tile(0, 0), tile(0, 2)));
+        assert!(line_of_sight(&tile_cache, tile(0, 0), tile(-2, 0)));
+    }
+}
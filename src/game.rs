@@ -3,209 +3,176 @@ use bevy_prototype_lyon::{prelude::*, shapes};
 use hexagon_tiles::hexagon::Hex;
 
 use crate::{
+    archetype::PlayerArchetype,
     camera::CameraTarget,
-    droid::{ai::new_shooting_droid_ai, AiDroidBundle, DroidBundle},
-    input::InputTarget,
-    player::PlayerMarker,
+    deployable::TurretSpawn,
+    droid::{ai::new_shooting_droid_ai, AiDroidBundle, DroidBundle, DroidClass},
+    input::{AimInput, InputTarget},
+    level::Level,
+    pickup::{PickupKind, PickupSpawn},
+    player::{PlayerIndex, PlayerMarker, PlayerScore, MAX_PLAYERS},
     portal::Portal,
     prelude::*,
-    ship::{ShipBundle, SHIP_VERTICES},
+    savegame::PendingLoad,
+    weapon::{RechargeTile, WeaponKind},
 };
 
 #[derive(Resource)]
 pub struct GameSpawnInfo {
-    pub spawn_player_ship: bool,
-    pub spawn_player_droid: bool,
-    pub spawn_player_jnr: bool,
+    pub player: PlayerArchetype,
     pub spawn_enemy_droids: u32,
-    pub spawn_benchmark: bool,
     pub gravity: bool,
+    // number of local players
+    pub players: usize,
 }
 
 #[derive(Component)]
 pub struct GameMarker;
 
-fn game_setup(mut commands: Commands, spawn_info: Res<GameSpawnInfo>) {
-    let shape = shapes::RegularPolygon {
-        sides: 6,
-        feature: shapes::RegularPolygonFeature::Radius(32.0),
-        ..shapes::RegularPolygon::default()
-    };
-
-    let ship_shape = shapes::Polygon {
-        points: SHIP_VERTICES.into(),
-        closed: true,
-    };
+pub const PLAYER_START: Vec3 = Vec3::new(100.0, 100.0, 0.0);
+// start of each further player relative to the previous one
+pub const PLAYER_START_OFFSET: Vec3 = Vec3::new(100.0, 0.0, 0.0);
 
-    let player = commands
+/// player entity, the caller attaches it to a vessel
+pub(crate) fn spawn_player(
+    commands: &mut Commands,
+    index: PlayerIndex,
+    score: PlayerScore,
+) -> Entity {
+    commands
         .spawn((
             SpatialBundle::default(),
             PlayerMarker,
+            index,
+            score,
             GameMarker,
             InputTarget,
+            AimInput::default(),
             CameraTarget,
         ))
-        .id();
-    if true {
-        let ship_shape_builder = GeometryBuilder::build_as(&ship_shape);
-        commands
-            .spawn(ShipBundle::new("ship"))
-            .insert(ShapeBundle {
-                path: ship_shape_builder,
-                spatial: SpatialBundle {
-                    transform: Transform::from_translation(Vec3::new(100.0, 100.0, 0.0)),
-                    ..default()
-                },
-                ..default()
-            })
-            .insert(default_stroke(YELLOW_HDR))
-            .insert(GameMarker)
-            .add_child(player);
-    } else {
-        let enemy_shape_builder = GeometryBuilder::build_as(&shape);
-        commands
-            .spawn(DroidBundle::new("player", spawn_info.gravity))
-            .insert(ShapeBundle {
-                path: enemy_shape_builder,
-                spatial: SpatialBundle {
-                    transform: Transform::from_translation(Vec3::new(100.0, 100.0, 0.0)),
-                    ..default()
-                },
+        .id()
+}
+
+/// hostile droid without AI, see spawn_droid_ai
+pub(crate) fn spawn_hostile_droid(
+    commands: &mut Commands,
+    gravity: bool,
+    class: DroidClass,
+    translation: Vec3,
+) -> Entity {
+    let shape = shapes::RegularPolygon {
+        sides: 6,
+        feature: shapes::RegularPolygonFeature::Radius(32.0),
+        ..shapes::RegularPolygon::default()
+    };
+    commands
+        .spawn(DroidBundle::new("r2d2", gravity).with_class(class))
+        .insert(ShapeBundle {
+            path: GeometryBuilder::build_as(&shape),
+            spatial: SpatialBundle {
+                transform: Transform::from_translation(translation),
                 ..default()
-            })
-            .insert(default_stroke(GREEN_HDR))
-            .insert(GameMarker)
-            .add_child(player);
-    }
-    // let player = if spawn_info.spawn_player_ship {
-    //     let ship_shape = shapes::Polygon {
-    //         points: SHIP_VERTICES.into(),
-    //         closed: true,
-    //     };
+            },
+            ..default()
+        })
+        .insert(default_stroke(RED_HDR))
+        .insert(Team::Hostile)
+        .insert(GameMarker)
+        .id()
+}
 
-    //     let ship_shape_builder = GeometryBuilder::build_as(&ship_shape);
+pub(crate) fn spawn_droid_ai(commands: &mut Commands, droid: Entity, enemy: Entity) {
+    let droid_ai = commands
+        .spawn((AiDroidBundle::with_enemy(enemy), new_shooting_droid_ai()))
+        .id();
+    commands.entity(droid).add_child(droid_ai);
+}
 
-    //     commands
-    //         .spawn(ShipBundle::new("ship"))
-    //         .insert(ShapeBundle {
-    //             path: ship_shape_builder,
-    //             spatial: SpatialBundle {
-    //                 transform: Transform::from_translation(Vec3::new(100.0, 100.0, 0.0)),
-    //                 ..default()
-    //             },
-    //             ..default()
-    //         })
-    //         .insert(default_stroke(YELLOW_HDR))
-    //         .insert(InputTarget)
-    //         .insert(CameraTarget)
-    //         .insert(GameMarker)
-    //         .id()
-    // } else if spawn_info.spawn_player_jnr {
-    //     let hexton_shape = shapes::Polygon {
-    //         points: HEXTON_VERTICES.into(),
-    //         closed: true,
-    //     };
+fn game_setup(
+    mut commands: Commands,
+    spawn_info: Res<GameSpawnInfo>,
+    level: Res<Level>,
+    mut pending_load: ResMut<PendingLoad>,
+    tiles_state: Res<TilesState>,
+    mut tile_cache: ResMut<TileCache>,
+) {
+    if let Some(save_game) = pending_load.0.take() {
+        save_game.spawn(&mut commands, &tiles_state, &mut tile_cache);
+        return;
+    }
 
-    //     let hexton_shape_builder = GeometryBuilder::build_as(&hexton_shape);
+    let mut players = Vec::new();
+    for index in 0..spawn_info.players.clamp(1, MAX_PLAYERS) {
+        let player = spawn_player(&mut commands, PlayerIndex(index), PlayerScore::default());
+        info!(
+            "spawn {} as {}",
+            PlayerIndex(index).name(),
+            spawn_info.player.name()
+        );
+        let vessel = spawn_info.player.spawn(
+            &mut commands,
+            spawn_info.gravity,
+            PLAYER_START + PLAYER_START_OFFSET * index as f32,
+        );
+        commands.entity(vessel).add_child(player);
+        players.push(player);
+    }
 
-    //     commands
-    //         .spawn(HextonBundle::new("hexton"))
-    //         .insert(ShapeBundle {
-    //             path: hexton_shape_builder,
-    //             spatial: SpatialBundle {
-    //                 transform: Transform::from_translation(Vec3::new(100.0, 142.0, 0.0)),
-    //                 ..default()
-    //             },
-    //             ..default()
-    //         })
-    //         .insert(default_stroke(BLUE_HDR))
-    //         .insert(InputTarget)
-    //         .insert(CameraTarget)
-    //         .insert(GameMarker)
-    //         .id()
-    // } else if spawn_info.spawn_benchmark {
-    //     commands
-    //         .spawn(SpatialBundle {
-    //             transform: Transform::from_translation(Vec3::new(100.0, 142.0, 0.0)),
-    //             ..default()
-    //         })
-    //         .insert(ParticleSource {
-    //             rate: 50,
-    //             direction: ParticleDirection::Uniform,
-    //             speed_distr: Normal::new(200.0, 90.0).unwrap(),
-    //             lifetime_distr: Normal::new(0.8, 0.5).unwrap(),
-    //             velocity_offset: Vec2::default(),
-    //             damping: default(),
-    //             initial_offset: 0.0,
-    //             color_generator: ColorGenerator::Static(7),
-    //         })
-    //         .insert(CameraTarget)
-    //         .insert(GameMarker)
-    //         .id()
-    //     //
-    // } else if spawn_info.spawn_player_droid {
-    //     let my_shape_builder = GeometryBuilder::build_as(&shape);
+    commands.spawn((
+        RechargeTile {
+            tile_pos: TilePos(Hex::new(-2, 3)),
+        },
+        GameMarker,
+    ));
 
-    //     commands
-    //         .spawn(DroidBundle::new("player", spawn_info.gravity))
-    //         .insert(PlayerDroidBundle::default())
-    //         .insert(ShapeBundle {
-    //             path: my_shape_builder,
-    //             spatial: SpatialBundle {
-    //                 transform: Transform::from_translation(Vec3::new(100.0, 100.0, 0.0)),
-    //                 ..default()
-    //             },
-    //             ..default()
-    //         })
-    //         .insert(default_stroke(GREEN_HDR))
-    //         .insert(GameMarker)
-    //         // .insert(ParticleSource {
-    //         //     rate: 1000,
-    //         //     direction: ParticleDirection::Uniform,
-    //         //     speed: 100.0,
-    //         //     speed_spread: 50.0,
-    //         //     lifetime: 1.0,
-    //         //     lifetime_spread: 0.5,
-    //         // })
-    //         .id()
-    // } else {
-    //     panic!("dont know what to spawn");
-    // };
+    for (tile_pos, kind) in [
+        (Hex::new(-3, 0), PickupKind::Weapon(WeaponKind::Kinetic)),
+        (Hex::new(0, 3), PickupKind::Repair(40.0)),
+        (Hex::new(3, 0), PickupKind::RapidFire(8.0)),
+    ] {
+        commands.spawn(PickupSpawn {
+            tile_pos: TilePos(tile_pos),
+            kind,
+        });
+    }
 
+    // each level adds one droid per enemy droid of the first level, in all droid classes
     let mut enemy_offset = Vec3::new(-100.0, 100.0, 0.0);
-    for _ in 0..spawn_info.spawn_enemy_droids {
-        let enemy_shape_builder = GeometryBuilder::build_as(&shape);
-
-        let droid_ai = commands
-            .spawn((AiDroidBundle::with_enemy(player), new_shooting_droid_ai()))
-            .id();
-        commands
-            .spawn(DroidBundle::new("r2d2", spawn_info.gravity))
-            // .insert_bundle(AiDroidBundle::with_enemy(enemy))
-            // .insert(AiDroidBundle::with_enemy(player))
-            .insert(ShapeBundle {
-                path: enemy_shape_builder,
-                spatial: SpatialBundle {
-                    transform: Transform::from_translation(enemy_offset),
-                    ..default()
-                },
-                ..default()
-            })
-            .insert(default_stroke(RED_HDR))
-            .insert(GameMarker)
-            .add_child(droid_ai);
-        // .insert(new_shooting_droid_ai())
+    let enemy_droids = spawn_info.spawn_enemy_droids * level.number;
+    for i in 0..enemy_droids as usize {
+        let class = [
+            DroidClass::Standard,
+            DroidClass::Shielded,
+            DroidClass::Hardened,
+        ][i % 3];
+        let droid = spawn_hostile_droid(&mut commands, spawn_info.gravity, class, enemy_offset);
+        // initial pick, enemy_select_system goes for the closest player
+        spawn_droid_ai(&mut commands, droid, players[i % players.len()]);
 
         enemy_offset.x += 100.0;
-        commands.spawn_empty().insert(Portal {
-            tile_pos: TilePos(Hex::new(5, -1)),
-            timer: Timer::from_seconds(2.0, TimerMode::Repeating),
+    }
+    // one turret and portal for all droids, stacked copies on the same tile would toggle the
+    // portal tile several times per period
+    if spawn_info.spawn_enemy_droids > 0 {
+        commands.spawn(TurretSpawn {
+            tile_pos: TilePos(Hex::new(3, -3)),
+            team: Team::Hostile,
         });
+        commands.spawn((
+            Portal {
+                tile_pos: TilePos(Hex::new(5, -1)),
+                timer: Timer::from_seconds(2.0, TimerMode::Repeating),
+            },
+            GameMarker,
+        ));
     }
 }
 
 // Generic system that takes a component as a parameter, and will despawn all entities with that component
-fn despawn_screen<T: Component>(to_despawn: Query<Entity, With<T>>, mut commands: Commands) {
+pub(crate) fn despawn_screen<T: Component>(
+    to_despawn: Query<Entity, With<T>>,
+    mut commands: Commands,
+) {
     for entity in &to_despawn {
         commands.entity(entity).despawn_recursive();
     }
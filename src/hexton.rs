@@ -1,11 +1,11 @@
 // commander Chuck Hexton
 
-use std::{borrow::Cow, time::Duration};
+use std::borrow::Cow;
 
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
-use crate::{collision_groups, droid::AttackRequest};
+use crate::{collision_groups, droid::AttackRequest, input::LevelRotation, prelude::*};
 
 #[derive(Component, Default)]
 pub struct HextonInput {
@@ -13,24 +13,69 @@ pub struct HextonInput {
     pub jump: bool,
 }
 
+/// vertical motion and jump state of the platformer controller. Speeds are in pixels per second,
+/// 'vertical' is along the level's up (see LevelRotation::up). The hexton falls with its own
+/// gravity, independent of the physics gravity.
 #[derive(Component)]
 pub struct HextonGravity {
+    // vertical speed, positive is up
     pub speed: f32,
+    // rising from a jump while the jump button is held (see JUMP_CUT)
     pub jump: bool,
-    pub jump_timer: Timer,
-    // pub jump: bool,
+    // runs after leaving the ground. While running, a jump is still possible
+    pub coyote_timer: Timer,
+    // runs after the jump button was pressed. A landing within this window triggers the jump
+    pub jump_buffer: Timer,
+    // horizontal control is locked for a moment after a wall jump
+    pub wall_jump_timer: Timer,
+    pub wall_jump_direction: f32,
+    jump_held: bool,
+}
+
+fn expired_timer(seconds: f32) -> Timer {
+    let mut timer = Timer::from_seconds(seconds, TimerMode::Once);
+    expire(&mut timer);
+    timer
+}
+
+fn expire(timer: &mut Timer) {
+    let duration = timer.duration();
+    timer.tick(duration);
 }
 
 impl Default for HextonGravity {
     fn default() -> Self {
         Self {
-            speed: Default::default(),
-            jump: Default::default(),
-            jump_timer: Timer::new(Duration::from_millis(300), TimerMode::Once),
+            speed: 0.0,
+            jump: false,
+            coyote_timer: expired_timer(COYOTE_TIME),
+            jump_buffer: expired_timer(JUMP_BUFFER_TIME),
+            wall_jump_timer: expired_timer(WALL_JUMP_LOCK_TIME),
+            wall_jump_direction: 0.0,
+            jump_held: false,
         }
     }
 }
 
+pub const RUN_SPEED: f32 = 150.0;
+pub const GRAVITY: f32 = 1800.0;
+pub const MAX_FALL_SPEED: f32 = 900.0;
+pub const JUMP_SPEED: f32 = 600.0;
+// releasing jump while rising scales the vertical speed by this (variable jump height)
+pub const JUMP_CUT: f32 = 0.4;
+pub const COYOTE_TIME: f32 = 0.1;
+pub const JUMP_BUFFER_TIME: f32 = 0.12;
+pub const WALL_SLIDE_SPEED: f32 = 120.0;
+pub const WALL_JUMP_SPEED: Vec2 = Vec2::new(250.0, 550.0);
+pub const WALL_JUMP_LOCK_TIME: f32 = 0.15;
+// contacts with normals flatter than this (|normal.y|) count as walls
+pub const WALL_NORMAL_MAX_Y: f32 = 0.3;
+// the top edges of pointy hex tiles are inclined by 30 degrees. They have to be walkable
+// without sliding, the vertical edges are walls.
+pub const HEX_SLOPE_ANGLE: f32 = std::f32::consts::PI / 6.0;
+pub const SLOPE_TOLERANCE: f32 = 5.0 * std::f32::consts::PI / 180.0;
+pub const GROUND_SNAP: f32 = 4.0;
+
 #[derive(Bundle)]
 pub struct HextonBundle {
     pub collider: Collider,
@@ -78,7 +123,10 @@ impl HextonBundle {
             ),
             collision_groups: CollisionGroups::new(
                 collision_groups::DROIDS,
-                collision_groups::DROIDS | collision_groups::LEVEL, /*  | collision_groups::PROJECTILES*/
+                collision_groups::DROIDS
+                    | collision_groups::LEVEL
+                    | collision_groups::PICKUPS
+                    | collision_groups::PROJECTILES,
             ),
             rigid_body: RigidBody::KinematicVelocityBased,
             locked_axes: LockedAxes::ROTATION_LOCKED,
@@ -95,76 +143,131 @@ impl HextonBundle {
             mass_properties: ColliderMassProperties::Density(1.0),
             spatial_bundle: default(),
             hexton_input: default(),
-            character_controller: default(),
+            character_controller: KinematicCharacterController {
+                snap_to_ground: Some(CharacterLength::Absolute(GROUND_SNAP)),
+                max_slope_climb_angle: HEX_SLOPE_ANGLE + SLOPE_TOLERANCE,
+                min_slope_slide_angle: HEX_SLOPE_ANGLE + SLOPE_TOLERANCE,
+                autostep: Some(CharacterAutostep {
+                    max_height: CharacterLength::Absolute(1.0),
+                    min_width: CharacterLength::Relative(0.0),
+                    include_dynamic_bodies: false,
+                }),
+                ..default()
+            },
             controller_output: default(),
             gravity: default(),
         }
     }
 }
 
-fn apply_hexton_input_system(
+/// side of a wall the character touches (-1 left, 1 right, relative to `up`)
+fn wall_side(output: &KinematicCharacterControllerOutput, up: Vec2) -> Option<f32> {
+    let right = -up.perp();
+    output
+        .collisions
+        .iter()
+        // normal2 points from the obstacle towards the character
+        .map(|collision| collision.toi.normal2)
+        .find(|normal| normal.dot(up).abs() < WALL_NORMAL_MAX_Y)
+        .map(|normal| -normal.dot(right).signum())
+}
+
+fn hexton_controller_system(
     time: Res<Time>,
+    level_rotation: Res<LevelRotation>,
     mut query: Query<(
         &HextonInput,
         &mut KinematicCharacterController,
         &mut HextonGravity,
-        &KinematicCharacterControllerOutput,
+        &mut Transform,
+        Option<&KinematicCharacterControllerOutput>,
     )>,
 ) {
-    for (input, mut controller, mut gravity, output) in &mut query {
-        // let y = if !output.grounded { -1.0 } else { 0.0 };
-        gravity.jump_timer.tick(time.delta());
-        if input.jump && !gravity.jump && output.grounded {
-            gravity.jump = true;
-            gravity.jump_timer.reset();
-            gravity.speed = 0.0;
+    let dt = time.delta_seconds();
+    // all motion is expressed in the local frame (right, up), which turns with the level
+    let up = level_rotation.up();
+    let right = -up.perp();
+    for (input, mut controller, mut gravity, mut transform, output) in &mut query {
+        controller.up = up;
+        transform.rotation = Quat::from_rotation_arc_2d(Vec2::Y, up);
+
+        // output is from the last physics step
+        let grounded = output.is_some_and(|output| output.grounded);
+        let wall = output.and_then(|output| wall_side(output, up));
+
+        gravity.coyote_timer.tick(time.delta());
+        gravity.jump_buffer.tick(time.delta());
+        gravity.wall_jump_timer.tick(time.delta());
+
+        if grounded {
+            gravity.coyote_timer.reset();
+        }
+        if input.jump && !gravity.jump_held {
+            gravity.jump_buffer.reset();
         }
+        gravity.jump_held = input.jump;
 
-        let mut jump = 0.0;
-        if gravity.jump && !gravity.jump_timer.finished() {
-            jump = 1.0;
-            info!("jump");
+        let mut forward = input.forward * RUN_SPEED;
+        if !gravity.wall_jump_timer.finished() {
+            forward = gravity.wall_jump_direction * WALL_JUMP_SPEED.x;
         }
-        if gravity.jump && gravity.jump_timer.finished() {
+
+        let jump_requested = !gravity.jump_buffer.finished();
+        if jump_requested && !gravity.coyote_timer.finished() {
+            gravity.speed = JUMP_SPEED;
+            gravity.jump = true;
+            expire(&mut gravity.jump_buffer);
+            expire(&mut gravity.coyote_timer);
+        } else if let Some(side) = wall.filter(|_| jump_requested && !grounded) {
+            // kick off away from the wall
+            gravity.speed = WALL_JUMP_SPEED.y;
+            gravity.jump = true;
+            gravity.wall_jump_direction = -side;
+            gravity.wall_jump_timer.reset();
+            expire(&mut gravity.jump_buffer);
+            forward = -side * WALL_JUMP_SPEED.x;
+        }
+
+        if gravity.jump && (!input.jump || gravity.speed <= 0.0) {
+            if gravity.speed > 0.0 {
+                gravity.speed *= JUMP_CUT;
+            }
             gravity.jump = false;
         }
 
-        controller.translation = Some(Vec2::new(input.forward * 2.0, jump + gravity.speed));
-        controller.autostep = Some(CharacterAutostep {
-            max_height: CharacterLength::Absolute(1.0),
-            min_width: CharacterLength::Relative(0.0),
-            include_dynamic_bodies: false,
-        })
-        // controller.snap_to_ground = Some(CharacterLength::Relative(0.1));
-        // controller.
-        // info!("forward: {}", input.forward);
-    }
-}
+        if let Some(output) = output {
+            // bumped into a ceiling
+            let desired_up = output.desired_translation.dot(up);
+            if gravity.speed > 0.0
+                && desired_up > 0.0
+                && output.effective_translation.dot(up) < 0.5 * desired_up
+            {
+                gravity.speed = 0.0;
+            }
+        }
 
-fn debug_hexton_input_system(
-    mut query: Query<(&KinematicCharacterControllerOutput, &mut HextonGravity)>,
-) {
-    for (output, mut gravity) in &mut query {
-        // info!("output: {:?}", output);
-        if !output.grounded && !gravity.jump {
-            gravity.speed -= 0.5;
-            // external_force.force = Vec2::new(0.0, -9.81);
-        } else {
+        if grounded && gravity.speed <= 0.0 {
             gravity.speed = 0.0;
-            // external_force.force = Vec2::new(0.0, 0.0);
+        } else {
+            let pushing_into_wall = wall.is_some_and(|side| side * input.forward > 0.0);
+            let max_fall_speed = if pushing_into_wall {
+                WALL_SLIDE_SPEED
+            } else {
+                MAX_FALL_SPEED
+            };
+            gravity.speed = (gravity.speed - GRAVITY * dt).max(-max_fall_speed);
         }
+
+        controller.translation = Some((right * forward + up * gravity.speed) * dt);
     }
 }
 
 pub struct HextonPlugin;
 impl Plugin for HextonPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, apply_hexton_input_system)
-        .add_systems(Update, debug_hexton_input_system)
-            // .add_system(ship_brake_maneuver_system.after(apply_ship_input_system))
-            // .add_system(ship_thruster_system.after(ship_brake_maneuver_system))
-            // // .add_system(ship_kinetic_debug_system.after(ship_thruster_system)) // can override other input
-            // .add_system(ship_attack_system)
-            ;
+        app.add_systems(
+            Update,
+            hexton_controller_system.run_if(in_state(GameState::Game)),
+        );
     }
 }
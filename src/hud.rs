@@ -0,0 +1,612 @@
+use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy_prototype_lyon::{prelude::*, shapes};
+use bevy_rapier2d::prelude::RapierContext;
+
+use crate::{
+    camera::CameraRigState,
+    droid::{DroidHealth, DroidMarker, DroidOverloadMarker, WeaponState},
+    game::{GameMarker, GameSpawnInfo},
+    pickup::SHIELD_AMOUNT,
+    player::{PlayerIndex, PlayerMarker, PlayerScore, MAX_PLAYERS},
+    prelude::*,
+    ship::ShipMarker,
+    weapon::{WeaponEnergy, WeaponKind},
+};
+
+// all hud elements of the player columns carry the PlayerIndex they show
+
+#[derive(Component)]
+struct EnergyBar;
+
+#[derive(Component)]
+struct HeatBar;
+
+#[derive(Component)]
+struct ReloadBar;
+
+#[derive(Component)]
+struct HealthBar;
+
+#[derive(Component)]
+struct ShieldBar;
+
+#[derive(Component)]
+struct ScoreText;
+
+#[derive(Component)]
+struct WeaponText;
+
+#[derive(Component)]
+struct TakeoverPrompt;
+
+#[derive(Component)]
+struct OffscreenIndicator;
+
+/// EMP load bar floating above a droid. Not a child of the droid, so it does not rotate with it.
+#[derive(Component)]
+struct EmpBar {
+    droid: Entity,
+}
+
+#[derive(Component)]
+struct EmpBarFill;
+
+const BAR_WIDTH: f32 = 200.0;
+const BAR_HEIGHT: f32 = 12.0;
+const ENERGY_COLOR: Color = Color::rgb(0.2, 0.6, 1.0);
+const HEAT_COLOR: Color = Color::rgb(1.0, 0.6, 0.1);
+const JAMMED_COLOR: Color = Color::rgb(1.0, 0.1, 0.1);
+const RELOAD_COLOR: Color = Color::rgb(0.8, 0.8, 0.8);
+const HEALTH_COLOR: Color = Color::rgb(0.2, 0.9, 0.3);
+const SHIELD_COLOR: Color = Color::rgb(0.4, 0.9, 1.0);
+const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+const PROMPT_COLOR: Color = Color::rgb(1.0, 0.9, 0.2);
+
+// world space EMP bars
+const EMP_BAR_SIZE: Vec2 = Vec2::new(60.0, 6.0);
+const EMP_BAR_OFFSET: Vec2 = Vec2::new(0.0, 45.0);
+const EMP_BAR_Z: f32 = 50.0;
+const EMP_BAR_BACKGROUND: Color = Color::rgba(0.2, 0.2, 0.2, 0.6);
+const EMP_COLOR: Color = Color::rgb(0.3, 0.6, 1.0);
+// overloaded droids can be taken over
+const OVERLOAD_COLOR: Color = Color::rgb(2.0, 1.8, 0.3);
+
+// off-screen indicators point at hostile droids up to this distance from the view center
+const INDICATOR_RANGE: f32 = 2500.0;
+const MAX_INDICATORS: usize = 8;
+const INDICATOR_SIZE: Vec2 = Vec2::new(24.0, 8.0);
+const INDICATOR_MARGIN: f32 = 16.0;
+const INDICATOR_COLOR: Color = Color::rgb(1.0, 0.2, 0.2);
+
+fn weapon_name(kind: WeaponKind) -> &'static str {
+    match kind {
+        WeaponKind::Kinetic => "kinetic",
+        WeaponKind::Wave => "wave",
+    }
+}
+
+fn spawn_bar(
+    parent: &mut ChildBuilder,
+    label: &str,
+    text_style: TextStyle,
+    color: Color,
+    marker: impl Bundle,
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                margin: UiRect::bottom(Val::Px(4.0)),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn(
+                TextBundle::from_section(label, text_style).with_style(Style {
+                    width: Val::Px(80.0),
+                    ..default()
+                }),
+            );
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(BAR_WIDTH),
+                        height: Val::Px(BAR_HEIGHT),
+                        ..default()
+                    },
+                    background_color: Color::DARK_GRAY.with_a(0.5).into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Percent(100.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: color.into(),
+                            ..default()
+                        },
+                        marker,
+                    ));
+                });
+        });
+}
+
+fn hud_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    spawn_info: Res<GameSpawnInfo>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        font_size: 20.0,
+        color: TEXT_COLOR,
+    };
+    // first player bottom left, second player bottom right
+    for index in 0..spawn_info.players.clamp(1, MAX_PLAYERS) {
+        let player = PlayerIndex(index);
+        let (left, right) = if index == 0 {
+            (Val::Px(10.0), Val::Auto)
+        } else {
+            (Val::Auto, Val::Px(10.0))
+        };
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        left,
+                        right,
+                        bottom: Val::Px(10.0),
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+                GameMarker,
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    TextBundle::from_section("", text_style.clone()).with_style(Style {
+                        margin: UiRect::bottom(Val::Px(4.0)),
+                        ..default()
+                    }),
+                    ScoreText,
+                    player,
+                ));
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Column,
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        spawn_bar(
+                            parent,
+                            "health",
+                            text_style.clone(),
+                            HEALTH_COLOR,
+                            (HealthBar, player),
+                        );
+                        spawn_bar(
+                            parent,
+                            "shield",
+                            text_style.clone(),
+                            SHIELD_COLOR,
+                            (ShieldBar, player),
+                        );
+                    });
+                parent.spawn((
+                    TextBundle::from_section("", text_style.clone()).with_style(Style {
+                        margin: UiRect::bottom(Val::Px(4.0)),
+                        ..default()
+                    }),
+                    WeaponText,
+                    player,
+                ));
+                spawn_bar(
+                    parent,
+                    "reload",
+                    text_style.clone(),
+                    RELOAD_COLOR,
+                    (ReloadBar, player),
+                );
+                spawn_bar(
+                    parent,
+                    "energy",
+                    text_style.clone(),
+                    ENERGY_COLOR,
+                    (EnergyBar, player),
+                );
+                spawn_bar(
+                    parent,
+                    "heat",
+                    text_style.clone(),
+                    HEAT_COLOR,
+                    (HeatBar, player),
+                );
+            });
+    }
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    bottom: Val::Percent(30.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            GameMarker,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        color: PROMPT_COLOR,
+                        ..text_style
+                    },
+                ),
+                TakeoverPrompt,
+            ));
+        });
+
+    // a fixed pool of indicators, assigned to the closest off-screen droids each frame
+    for _ in 0..MAX_INDICATORS {
+        commands.spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Px(INDICATOR_SIZE.x),
+                    height: Val::Px(INDICATOR_SIZE.y),
+                    display: Display::None,
+                    ..default()
+                },
+                background_color: INDICATOR_COLOR.into(),
+                ..default()
+            },
+            OffscreenIndicator,
+            GameMarker,
+        ));
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn hud_energy_system(
+    player_query: Query<(&Parent, &PlayerIndex), With<PlayerMarker>>,
+    energy_query: Query<&WeaponEnergy>,
+    mut energy_bar_query: Query<(&mut Style, &PlayerIndex), (With<EnergyBar>, Without<HeatBar>)>,
+    mut heat_bar_query: Query<
+        (&mut Style, &mut BackgroundColor, &PlayerIndex),
+        (With<HeatBar>, Without<EnergyBar>),
+    >,
+) {
+    let weapon_energy = |index: &PlayerIndex| {
+        player_query
+            .iter()
+            .find(|(_, player)| *player == index)
+            .and_then(|(parent, _)| energy_query.get(parent.get()).ok())
+    };
+    for (mut style, player) in &mut energy_bar_query {
+        let Some(weapon_energy) = weapon_energy(player) else {
+            continue;
+        };
+        style.width = Val::Percent(weapon_energy.energy_fraction() * 100.0);
+    }
+    for (mut style, mut background_color, player) in &mut heat_bar_query {
+        let Some(weapon_energy) = weapon_energy(player) else {
+            continue;
+        };
+        style.width = Val::Percent(weapon_energy.heat.clamp(0.0, 1.0) * 100.0);
+        *background_color = if weapon_energy.jammed {
+            JAMMED_COLOR
+        } else {
+            HEAT_COLOR
+        }
+        .into();
+    }
+}
+
+/// health and shield of the controlled vessel, weapon kind and reload state
+#[allow(clippy::type_complexity)]
+fn hud_vessel_system(
+    player_query: Query<(&Parent, &PlayerIndex), With<PlayerMarker>>,
+    vessel_query: Query<(&DroidHealth, &WeaponState)>,
+    mut bar_query: Query<(
+        &mut Style,
+        &PlayerIndex,
+        Has<HealthBar>,
+        Has<ShieldBar>,
+        Has<ReloadBar>,
+    )>,
+    mut text_query: Query<(&mut Text, &PlayerIndex), With<WeaponText>>,
+) {
+    let vessel = |index: &PlayerIndex| {
+        player_query
+            .iter()
+            .find(|(_, player)| *player == index)
+            .and_then(|(parent, _)| vessel_query.get(parent.get()).ok())
+    };
+    for (mut style, player, health_bar, shield_bar, reload_bar) in &mut bar_query {
+        let Some((health, weapon_state)) = vessel(player) else {
+            continue;
+        };
+        let fraction = if reload_bar {
+            if weapon_state.reload_time > 0.0 {
+                1.0 - weapon_state.reload_timeout / weapon_state.reload_time
+            } else {
+                1.0
+            }
+        } else if health_bar {
+            health.hitpoints / health.max_hitpoints
+        } else if shield_bar {
+            health.shield / SHIELD_AMOUNT
+        } else {
+            continue;
+        };
+        style.width = Val::Percent(fraction.clamp(0.0, 1.0) * 100.0);
+    }
+    for (mut text, player) in &mut text_query {
+        let Some((_, weapon_state)) = vessel(player) else {
+            continue;
+        };
+        let value = if weapon_state.reload_timeout > f32::EPSILON {
+            format!(
+                "{} {:.1}s",
+                weapon_name(weapon_state.kind),
+                weapon_state.reload_timeout
+            )
+        } else {
+            format!("{} ready", weapon_name(weapon_state.kind))
+        };
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+fn hud_score_system(
+    spawn_info: Res<GameSpawnInfo>,
+    score_query: Query<(&PlayerIndex, &PlayerScore), Changed<PlayerScore>>,
+    mut text_query: Query<(&mut Text, &PlayerIndex), With<ScoreText>>,
+) {
+    for (player, score) in &score_query {
+        for (mut text, _) in text_query.iter_mut().filter(|(_, index)| *index == player) {
+            text.sections[0].value = if spawn_info.players > 1 {
+                format!("{} kills: {}", player.name(), score.kills)
+            } else {
+                format!("kills: {}", score.kills)
+            };
+        }
+    }
+}
+
+/// shown while a player's ship touches an overloaded droid, e.g. when the droid got overloaded
+/// while already in contact and the ship has to back off and ram it again
+fn hud_takeover_prompt_system(
+    spawn_info: Res<GameSpawnInfo>,
+    rapier_context: Res<RapierContext>,
+    player_query: Query<(&Parent, &PlayerIndex), With<PlayerMarker>>,
+    ship_query: Query<(), With<ShipMarker>>,
+    droid_query: Query<(), (With<DroidMarker>, With<DroidOverloadMarker>)>,
+    mut prompt_query: Query<&mut Text, With<TakeoverPrompt>>,
+) {
+    let mut players: Vec<_> = player_query
+        .iter()
+        .filter(|(parent, _)| {
+            let ship = parent.get();
+            ship_query.contains(ship)
+                && rapier_context.contacts_with(ship).any(|contact_pair| {
+                    let other = if contact_pair.collider1() == ship {
+                        contact_pair.collider2()
+                    } else {
+                        contact_pair.collider1()
+                    };
+                    contact_pair.has_any_active_contacts() && droid_query.contains(other)
+                })
+        })
+        .map(|(_, player)| *player)
+        .collect();
+    players.sort();
+    let value = match players.as_slice() {
+        [] => String::new(),
+        [player] if spawn_info.players > 1 => {
+            format!(
+                "{}: ram the overloaded droid to take it over",
+                player.name()
+            )
+        }
+        _ => "ram the overloaded droid to take it over".to_string(),
+    };
+    for mut text in &mut prompt_query {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+/// point at hostile droids near the view but outside of it, for each active game camera
+#[allow(clippy::type_complexity)]
+fn hud_offscreen_indicator_system(
+    camera_query: Query<(&Camera, &GlobalTransform), With<CameraRigState>>,
+    droid_query: Query<(&GlobalTransform, &Team, &Visibility), With<DroidMarker>>,
+    mut indicator_query: Query<
+        (&mut Style, &mut Transform, &mut BackgroundColor),
+        With<OffscreenIndicator>,
+    >,
+) {
+    let mut indicators = Vec::new();
+    for (camera, camera_transform) in &camera_query {
+        if !camera.is_active {
+            continue;
+        }
+        let Some(rect) = camera.logical_viewport_rect() else {
+            continue;
+        };
+        let view_center = camera_transform.translation().xy();
+        let viewport_center = rect.size() * 0.5;
+        let inner = Rect::from_center_size(
+            viewport_center,
+            (rect.size() - Vec2::splat(2.0 * INDICATOR_MARGIN)).max(Vec2::ZERO),
+        );
+        for (droid_transform, team, visibility) in &droid_query {
+            if *team != Team::Hostile || *visibility == Visibility::Hidden {
+                continue;
+            }
+            let droid_pos = droid_transform.translation();
+            let distance = droid_pos.xy().distance(view_center);
+            if distance > INDICATOR_RANGE {
+                continue;
+            }
+            let Some(pos) = camera.world_to_viewport(camera_transform, droid_pos) else {
+                continue;
+            };
+            if Rect::from_corners(Vec2::ZERO, rect.size()).contains(pos) {
+                continue;
+            }
+            // move the point towards the viewport center until it is inside the margin
+            let direction = pos - viewport_center;
+            let scale = (inner.half_size() / direction.abs().max(Vec2::splat(f32::EPSILON)))
+                .min_element()
+                .min(1.0);
+            let pos = rect.min + viewport_center + direction * scale;
+            indicators.push((distance, pos, direction.y.atan2(direction.x)));
+        }
+    }
+    indicators.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut indicators = indicators.into_iter();
+    for (mut style, mut transform, mut background_color) in &mut indicator_query {
+        let Some((distance, pos, angle)) = indicators.next() else {
+            if style.display != Display::None {
+                style.display = Display::None;
+            }
+            continue;
+        };
+        style.display = Display::Flex;
+        style.left = Val::Px(pos.x - INDICATOR_SIZE.x * 0.5);
+        style.top = Val::Px(pos.y - INDICATOR_SIZE.y * 0.5);
+        // ui y points down, so the angle is already in screen orientation
+        transform.rotation = Quat::from_rotation_z(angle);
+        let alpha = 1.0 - 0.7 * (distance / INDICATOR_RANGE);
+        *background_color = INDICATOR_COLOR.with_a(alpha).into();
+    }
+}
+
+fn emp_bar_spawn_system(
+    mut commands: Commands,
+    droid_query: Query<Entity, (Added<DroidHealth>, With<DroidMarker>)>,
+) {
+    // left aligned, so that scaling the fill along x keeps the left end in place
+    let rectangle = shapes::Rectangle {
+        extents: EMP_BAR_SIZE,
+        origin: RectangleOrigin::BottomLeft,
+    };
+    for droid in &droid_query {
+        commands
+            .spawn((
+                ShapeBundle {
+                    path: GeometryBuilder::build_as(&rectangle),
+                    spatial: SpatialBundle {
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    ..default()
+                },
+                Fill::color(EMP_BAR_BACKGROUND),
+                EmpBar { droid },
+                GameMarker,
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    ShapeBundle {
+                        path: GeometryBuilder::build_as(&rectangle),
+                        spatial: SpatialBundle::from_transform(Transform::from_xyz(0.0, 0.0, 0.1)),
+                        ..default()
+                    },
+                    Fill::color(EMP_COLOR),
+                    EmpBarFill,
+                ));
+            });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn emp_bar_update_system(
+    mut commands: Commands,
+    droid_query: Query<(
+        &GlobalTransform,
+        &DroidHealth,
+        &Visibility,
+        Has<DroidOverloadMarker>,
+    )>,
+    mut bar_query: Query<
+        (Entity, &EmpBar, &mut Transform, &mut Visibility, &Children),
+        (Without<EmpBarFill>, Without<DroidHealth>),
+    >,
+    mut fill_query: Query<(&mut Transform, &mut Fill), With<EmpBarFill>>,
+) {
+    for (entity, bar, mut transform, mut visibility, children) in &mut bar_query {
+        let Ok((droid_transform, health, droid_visibility, overloaded)) =
+            droid_query.get(bar.droid)
+        else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        let shown = health.emp_load > 0.01 && *droid_visibility != Visibility::Hidden;
+        let new_visibility = if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != new_visibility {
+            *visibility = new_visibility;
+        }
+        if !shown {
+            continue;
+        }
+        let pos = droid_transform.translation().xy() + EMP_BAR_OFFSET - EMP_BAR_SIZE * 0.5;
+        transform.translation = pos.extend(EMP_BAR_Z);
+        for child in children.iter() {
+            let Ok((mut fill_transform, mut fill)) = fill_query.get_mut(*child) else {
+                continue;
+            };
+            fill_transform.scale.x = health.emp_load.clamp(0.0, 1.0);
+            fill.color = if overloaded {
+                OVERLOAD_COLOR
+            } else {
+                EMP_COLOR
+            };
+        }
+    }
+}
+
+pub struct HudPlugin;
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnExit(GameState::None), hud_setup)
+            .add_systems(
+                Update,
+                (
+                    hud_energy_system,
+                    hud_vessel_system,
+                    hud_score_system,
+                    hud_takeover_prompt_system,
+                    hud_offscreen_indicator_system,
+                    emp_bar_spawn_system,
+                    emp_bar_update_system,
+                )
+                    .run_if(in_state(GameState::Game)),
+            );
+    }
+}
@@ -1,35 +1,271 @@
 use crate::{
-    droid::{AttackRequest, TargetDirection},
+    action::{ActionInput, InputAction},
+    archetype::PlayerArchetype,
+    camera::CameraRigState,
+    droid::{AttackRequest, TargetDirection, WeaponDirection},
+    game::GameSpawnInfo,
     hexton::HextonInput,
+    player::{PlayerIndex, MAX_PLAYERS},
     portal::PortalToggleRequest,
     prelude::*,
-    ship::ShipInput,
+    settings::Settings,
+    ship::{controller, FlightAssist, ShipInput},
     HEX_LAYOUT,
 };
 
-use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy::{
+    ecs::system::SystemParam,
+    input::gamepad::{GamepadConnection, GamepadEvent},
+    math::Vec3Swizzles,
+    prelude::*,
+};
 // use bevy_mouse_tracking_plugin::prelude::*;
-// use bevy_mouse_tracking_plugin::MousePosWorld;
+use bevy_mouse_tracking_plugin::MousePosWorld;
 use bevy_rapier2d::prelude::*;
 use hexagon_tiles::{hexagon::HexRound, layout::LayoutTool, point::Point};
 
 #[derive(Component, Default)]
 pub struct InputTarget;
 
+/// ordering of the player input systems
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputSet {
+    // read the input devices and write the player's input components
+    Read,
+    // derive further state from the input components (e.g. weapon direction from aim)
+    Apply,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum AimMode {
+    // weapons point along the movement direction (droid) or the nose (ship)
+    #[default]
+    Movement,
+    // aim at the mouse cursor
+    Cursor,
+    // aim with the right stick of the gamepad
+    Stick,
+}
+
+impl AimMode {
+    pub fn next(self) -> Self {
+        match self {
+            AimMode::Movement => AimMode::Cursor,
+            AimMode::Cursor => AimMode::Stick,
+            AimMode::Stick => AimMode::Movement,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct AimSettings {
+    pub mode: AimMode,
+    // ships rotate towards the aim direction
+    pub ship_follows_aim: bool,
+}
+
+pub const AIM_STICK_DEADZONE: f32 = 0.3;
+pub const MOVE_STICK_DEADZONE: f32 = 0.15;
+
+/// the gamepads assigned to the players. Pads are handed out in order of connection, see
+/// gamepad_connection_system
+#[derive(Resource, Default)]
+pub struct PlayerGamepads(pub [Option<Gamepad>; MAX_PLAYERS]);
+
+impl PlayerGamepads {
+    pub fn get(&self, player: PlayerIndex) -> Option<Gamepad> {
+        self.0.get(player.0).copied().flatten()
+    }
+
+    fn assign(&mut self, gamepad: Gamepad) {
+        if self.0.contains(&Some(gamepad)) {
+            return;
+        }
+        if let Some(slot) = self.0.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(gamepad);
+        }
+    }
+}
+
+/// read access to the players' gamepads. All accessors return neutral values if no pad is
+/// assigned to the player.
+#[derive(SystemParam)]
+pub struct GamepadInput<'w> {
+    player_gamepads: Res<'w, PlayerGamepads>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    button_axes: Res<'w, Axis<GamepadButton>>,
+    buttons: Res<'w, Input<GamepadButton>>,
+}
+
+impl GamepadInput<'_> {
+    fn axis(&self, player: PlayerIndex, axis_type: GamepadAxisType) -> f32 {
+        self.player_gamepads
+            .get(player)
+            .and_then(|gamepad| self.axes.get(GamepadAxis::new(gamepad, axis_type)))
+            .unwrap_or_default()
+    }
+    fn stick(
+        &self,
+        player: PlayerIndex,
+        x: GamepadAxisType,
+        y: GamepadAxisType,
+        deadzone: f32,
+    ) -> Option<Vec2> {
+        let stick = Vec2::new(self.axis(player, x), self.axis(player, y));
+        (stick.length() > deadzone).then(|| stick.clamp_length_max(1.0))
+    }
+    pub fn left_stick(&self, player: PlayerIndex) -> Option<Vec2> {
+        self.stick(
+            player,
+            GamepadAxisType::LeftStickX,
+            GamepadAxisType::LeftStickY,
+            MOVE_STICK_DEADZONE,
+        )
+    }
+    pub fn right_stick(&self, player: PlayerIndex) -> Option<Vec2> {
+        self.stick(
+            player,
+            GamepadAxisType::RightStickX,
+            GamepadAxisType::RightStickY,
+            AIM_STICK_DEADZONE,
+        )
+    }
+    /// analog value of a button (0..1), e.g. for the triggers
+    pub fn button_value(&self, player: PlayerIndex, button_type: GamepadButtonType) -> f32 {
+        self.player_gamepads
+            .get(player)
+            .and_then(|gamepad| {
+                self.button_axes
+                    .get(GamepadButton::new(gamepad, button_type))
+            })
+            .unwrap_or_default()
+    }
+    pub fn pressed(&self, player: PlayerIndex, button_type: GamepadButtonType) -> bool {
+        self.player_gamepads.get(player).is_some_and(|gamepad| {
+            self.buttons
+                .pressed(GamepadButton::new(gamepad, button_type))
+        })
+    }
+    pub fn just_pressed(&self, player: PlayerIndex, button_type: GamepadButtonType) -> bool {
+        self.player_gamepads.get(player).is_some_and(|gamepad| {
+            self.buttons
+                .just_pressed(GamepadButton::new(gamepad, button_type))
+        })
+    }
+}
+
+fn gamepad_connection_system(
+    mut gamepad_events: EventReader<GamepadEvent>,
+    gamepads: Res<Gamepads>,
+    mut player_gamepads: ResMut<PlayerGamepads>,
+) {
+    for event in gamepad_events.read() {
+        let GamepadEvent::Connection(connection_event) = event else {
+            continue;
+        };
+        match &connection_event.connection {
+            GamepadConnection::Connected(info) => {
+                info!(
+                    "gamepad connected: {:?} {}",
+                    connection_event.gamepad, info.name
+                );
+                player_gamepads.assign(connection_event.gamepad);
+            }
+            GamepadConnection::Disconnected => {
+                info!("gamepad disconnected: {:?}", connection_event.gamepad);
+                for slot in &mut player_gamepads.0 {
+                    if *slot == Some(connection_event.gamepad) {
+                        *slot = None;
+                    }
+                }
+                // fall back to other connected pads
+                for gamepad in gamepads.iter() {
+                    if gamepad != connection_event.gamepad {
+                        player_gamepads.assign(gamepad);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// aim direction requested by the player, independent of movement. None means
+/// 'no explicit aim', i.e. weapons follow the movement.
+#[derive(Component, Default)]
+pub struct AimInput {
+    pub direction: Option<Vec2>,
+}
+
+fn aim_settings_input_system(action_input: ActionInput, mut aim_settings: ResMut<AimSettings>) {
+    if action_input.just_pressed(InputAction::CycleAimMode) {
+        aim_settings.mode = aim_settings.mode.next();
+        info!("aim mode: {:?}", aim_settings.mode);
+    }
+    if action_input.just_pressed(InputAction::ToggleShipFollowsAim) {
+        aim_settings.ship_follows_aim = !aim_settings.ship_follows_aim;
+        info!("ship follows aim: {}", aim_settings.ship_follows_aim);
+    }
+}
+
+fn aim_input_system(
+    aim_settings: Res<AimSettings>,
+    mouse_pos: Res<MousePosWorld>,
+    gamepad_input: GamepadInput,
+    mut query: Query<(&GlobalTransform, &PlayerIndex, &mut AimInput), With<InputTarget>>,
+) {
+    for (transform, player, mut aim_input) in &mut query {
+        aim_input.direction = match aim_settings.mode {
+            AimMode::Movement => None,
+            // there is only one mouse, it belongs to the first player
+            AimMode::Cursor if *player == PlayerIndex(0) => {
+                (Vec2::new(mouse_pos.x, mouse_pos.y) - transform.translation().xy()).try_normalize()
+            }
+            AimMode::Cursor => None,
+            AimMode::Stick => gamepad_input
+                .right_stick(*player)
+                .and_then(|stick| stick.try_normalize()),
+        };
+    }
+}
+
+fn apply_aim_system(
+    aim_settings: Res<AimSettings>,
+    player_query: Query<(&Parent, &AimInput), With<InputTarget>>,
+    mut weapon_direction_query: Query<&mut WeaponDirection>,
+    mut flight_assist_query: Query<&mut FlightAssist>,
+) {
+    for (parent, aim_input) in &player_query {
+        if let Ok(mut weapon_direction) = weapon_direction_query.get_mut(parent.get()) {
+            weapon_direction.follow_movement = aim_input.direction.is_none();
+            if let Some(direction) = aim_input.direction {
+                weapon_direction.direction = direction;
+            }
+        }
+        if let Ok(mut flight_assist) = flight_assist_query.get_mut(parent.get()) {
+            flight_assist.aim_heading = if aim_settings.ship_follows_aim {
+                aim_input.direction.map(controller::heading_of)
+            } else {
+                None
+            };
+        }
+    }
+}
+
 fn apply_input_system_8dir(
-    player_query: Query<&Parent, With<InputTarget>>,
+    player_query: Query<(&Parent, &PlayerIndex), With<InputTarget>>,
     mut query: Query<(&mut TargetDirection, &Transform, &mut AttackRequest)>,
-    keyboard_input: Res<Input<KeyCode>>,
+    action_input: ActionInput,
 ) {
-    for parent in &player_query {
+    for (parent, player) in &player_query {
         let Ok((mut input_target, _transform, mut attack_request)) = query.get_mut(parent.get())
         else {
             continue;
         };
-        let w = keyboard_input.pressed(KeyCode::W);
-        let a = keyboard_input.pressed(KeyCode::A);
-        let s = keyboard_input.pressed(KeyCode::S);
-        let d = keyboard_input.pressed(KeyCode::D);
+        let player = *player;
+        let w = action_input.player_pressed(player, InputAction::MoveUp);
+        let a = action_input.player_pressed(player, InputAction::MoveLeft);
+        let s = action_input.player_pressed(player, InputAction::MoveDown);
+        let d = action_input.player_pressed(player, InputAction::MoveRight);
 
         let mut dir = Vec2::ZERO;
         if w {
@@ -45,17 +281,22 @@ fn apply_input_system_8dir(
             dir -= Vec2::X;
         }
 
-        input_target.direction = dir.normalize_or_zero();
-        attack_request.primary_attack = keyboard_input.pressed(KeyCode::J);
+        // analog stick overrides the digital direction
+        input_target.direction = action_input
+            .move_stick(player)
+            .unwrap_or_else(|| dir.normalize_or_zero());
+        attack_request.primary_attack = action_input.player_pressed(player, InputAction::Fire);
+        attack_request.secondary_attack =
+            action_input.player_pressed(player, InputAction::SecondaryFire);
     }
 }
 fn apply_input_system_portal_toggle(
     mut commands: Commands,
-    query: Query<&GlobalTransform, With<InputTarget>>,
-    keyboard_input: Res<Input<KeyCode>>,
+    query: Query<(&GlobalTransform, &PlayerIndex), With<InputTarget>>,
+    action_input: ActionInput,
 ) {
-    for transform in &query {
-        if keyboard_input.just_pressed(KeyCode::H) {
+    for (transform, player) in &query {
+        if action_input.player_just_pressed(*player, InputAction::TogglePortal) {
             let hex_pos = LayoutTool::pixel_to_hex(
                 HEX_LAYOUT,
                 Point {
@@ -72,19 +313,21 @@ fn apply_input_system_portal_toggle(
     }
 }
 fn apply_input_system_2_1_dof(
-    player_query: Query<&Parent, With<InputTarget>>,
+    player_query: Query<(&Parent, &PlayerIndex), With<InputTarget>>,
     mut query: Query<(&mut ShipInput, &Transform, &mut AttackRequest)>,
-    keyboard_input: Res<Input<KeyCode>>,
+    action_input: ActionInput,
 ) {
-    for parent in &player_query {
+    let gamepad_input = &action_input.gamepad;
+    for (parent, player) in &player_query {
         let Ok((mut input_target, _transform, mut attack_request)) = query.get_mut(parent.get())
         else {
             continue;
         };
-        let w = keyboard_input.pressed(KeyCode::W);
-        let a = keyboard_input.pressed(KeyCode::A);
-        let s = keyboard_input.pressed(KeyCode::S);
-        let d = keyboard_input.pressed(KeyCode::D);
+        let player = *player;
+        let w = action_input.player_pressed(player, InputAction::MoveUp);
+        let a = action_input.player_pressed(player, InputAction::MoveLeft);
+        let s = action_input.player_pressed(player, InputAction::MoveDown);
+        let d = action_input.player_pressed(player, InputAction::MoveRight);
 
         let mut rot = 0.0;
         let mut thrust = 0.0;
@@ -102,24 +345,61 @@ fn apply_input_system_2_1_dof(
             rot -= 1.0;
         }
 
+        // analog: stick x rotates, right trigger (or stick forward) thrusts, left trigger brakes
+        if let Some(stick) = action_input.move_stick(player) {
+            rot = stick.x;
+            thrust = f32::max(thrust, stick.y.max(0.0));
+        }
+        thrust = f32::max(
+            thrust,
+            gamepad_input.button_value(player, GamepadButtonType::RightTrigger2),
+        );
+        brake = f32::max(
+            brake,
+            gamepad_input.button_value(player, GamepadButtonType::LeftTrigger2),
+        );
+
         input_target.rot = rot;
         input_target.thrust = thrust;
         input_target.brake = brake;
 
-        attack_request.primary_attack = keyboard_input.pressed(KeyCode::J);
+        attack_request.primary_attack = action_input.player_pressed(player, InputAction::Fire);
+    }
+}
+
+fn apply_input_system_flight_assist(
+    player_query: Query<(&Parent, &PlayerIndex), With<InputTarget>>,
+    mut query: Query<&mut FlightAssist>,
+    action_input: ActionInput,
+) {
+    for (parent, player) in &player_query {
+        if !action_input.player_just_pressed(*player, InputAction::CycleFlightAssist) {
+            continue;
+        }
+        let Ok(mut flight_assist) = query.get_mut(parent.get()) else {
+            continue;
+        };
+        flight_assist.mode = flight_assist.mode.next();
+        flight_assist.target_heading = None;
+        info!("flight assist: {:?}", flight_assist.mode);
     }
 }
 
 fn apply_input_system_jnr(
-    mut query: Query<(&mut HextonInput, &mut AttackRequest), With<InputTarget>>,
-    keyboard_input: Res<Input<KeyCode>>,
+    player_query: Query<(&Parent, &PlayerIndex), With<InputTarget>>,
+    mut query: Query<(&mut HextonInput, &mut AttackRequest)>,
+    action_input: ActionInput,
 ) {
-    for (mut input_target, mut attack_request) in query.iter_mut() {
+    for (parent, player) in &player_query {
+        let Ok((mut input_target, mut attack_request)) = query.get_mut(parent.get()) else {
+            continue;
+        };
+        let player = *player;
         let mut forward = 0.0;
-        let w = keyboard_input.pressed(KeyCode::W);
-        let a = keyboard_input.pressed(KeyCode::A);
-        // let s = keyboard_input.pressed(KeyCode::S);
-        let d = keyboard_input.pressed(KeyCode::D);
+        let w = action_input.player_pressed(player, InputAction::MoveUp);
+        let a = action_input.player_pressed(player, InputAction::MoveLeft);
+        // let s = action_input.player_pressed(player, InputAction::MoveDown);
+        let d = action_input.player_pressed(player, InputAction::MoveRight);
 
         if d {
             forward += 1.0;
@@ -127,45 +407,92 @@ fn apply_input_system_jnr(
         if a {
             forward -= 1.0;
         }
+        if let Some(stick) = action_input.move_stick(player) {
+            forward = stick.x;
+        }
         input_target.forward = forward;
-        input_target.jump = w;
-        attack_request.primary_attack = keyboard_input.pressed(KeyCode::J);
+        input_target.jump = w || action_input.player_pressed(player, InputAction::Jump);
+        attack_request.primary_attack = action_input.player_pressed(player, InputAction::Fire);
     }
 }
 
 fn camera_zoom_system(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut camera_query: Query<&mut Transform, With<Camera2d>>,
+    action_input: ActionInput,
+    mut settings: ResMut<Settings>,
+    mut camera_query: Query<&mut CameraRigState, With<Camera2d>>,
 ) {
-    for mut transform in camera_query.iter_mut() {
-        if keyboard_input.pressed(KeyCode::I) && transform.scale.x > 0.5 {
-            transform.scale /= 1.05
-        } else if keyboard_input.pressed(KeyCode::P) && transform.scale.x < 10.0 {
-            transform.scale *= 1.05
-        } else if keyboard_input.pressed(KeyCode::O) {
-            transform.scale = Vec3::ONE
+    let zoom_in = action_input.pressed(InputAction::ZoomIn);
+    let zoom_out = action_input.pressed(InputAction::ZoomOut);
+    let zoom_reset = action_input.pressed(InputAction::ZoomReset);
+    if !zoom_in && !zoom_out && !zoom_reset {
+        return;
+    }
+    for mut rig_state in camera_query.iter_mut() {
+        if zoom_in && rig_state.zoom > 0.5 {
+            rig_state.zoom /= 1.05
+        } else if zoom_out && rig_state.zoom < 10.0 {
+            rig_state.zoom *= 1.05
+        } else if zoom_reset {
+            rig_state.zoom = 1.0
         }
+        settings.camera_zoom = rig_state.zoom;
     }
 }
 
+/// rotation of the camera and gravity. The level turns towards `target`.
+#[derive(Resource, Default)]
+pub struct LevelRotation {
+    pub angle: f32,
+    pub target: f32,
+}
+
+impl LevelRotation {
+    /// world up turned by the level rotation, i.e. against gravity. Derived from the angle, so
+    /// that no float errors accumulate while turning.
+    pub fn up(&self) -> Vec2 {
+        Vec2::from_angle(self.angle).rotate(Vec2::Y)
+    }
+}
+
+// the platformer rotates the level in hex steps
+pub const ROTATION_STEP: f32 = std::f32::consts::PI / 3.0;
+pub const ROTATION_STEP_SPEED: f32 = std::f32::consts::PI;
+pub const ROTATION_CONTINUOUS_SPEED: f32 = std::f32::consts::FRAC_PI_2;
+
 fn camera_rotate_system(
-    keyboard_input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    action_input: ActionInput,
+    spawn_info: Res<GameSpawnInfo>,
+    mut level_rotation: ResMut<LevelRotation>,
     mut camera_query: Query<&mut Transform, With<Camera2d>>,
     mut rapier_config: ResMut<RapierConfiguration>,
 ) {
     // info!("scale: {:?}", rapier_config);
+    let dt = time.delta_seconds();
+    if spawn_info.player == PlayerArchetype::Hexton {
+        if action_input.just_pressed(InputAction::RotateCameraLeft) {
+            level_rotation.target += ROTATION_STEP;
+        } else if action_input.just_pressed(InputAction::RotateCameraRight) {
+            level_rotation.target -= ROTATION_STEP;
+        }
+    } else if action_input.pressed(InputAction::RotateCameraLeft) {
+        level_rotation.target += ROTATION_CONTINUOUS_SPEED * dt;
+    } else if action_input.pressed(InputAction::RotateCameraRight) {
+        level_rotation.target -= ROTATION_CONTINUOUS_SPEED * dt;
+    }
 
-    for mut transform in camera_query.iter_mut() {
-        let angle = 3.0 * std::f32::consts::PI / 360.0;
-
-        if keyboard_input.pressed(KeyCode::K) {
-            let rot = Quat::from_rotation_z(angle);
-            transform.rotation *= rot;
-            rapier_config.gravity = rot.mul_vec3(rapier_config.gravity.extend(0.0)).xy();
-        } else if keyboard_input.pressed(KeyCode::L) {
-            let rot = Quat::from_rotation_z(-angle);
-            transform.rotation *= rot;
-            rapier_config.gravity = rot.mul_vec3(rapier_config.gravity.extend(0.0)).xy();
+    let max_delta = ROTATION_STEP_SPEED * dt;
+    let delta = (level_rotation.target - level_rotation.angle).clamp(-max_delta, max_delta);
+    if delta != 0.0 {
+        level_rotation.angle += delta;
+        // only touch gravity while rotating, a replay may be driving it
+        rapier_config.gravity = -level_rotation.up() * rapier_config.gravity.length();
+    }
+    // also follows a replay setting the angle
+    if level_rotation.is_changed() {
+        let rotation = Quat::from_rotation_z(level_rotation.angle);
+        for mut transform in camera_query.iter_mut() {
+            transform.rotation = rotation;
         }
     }
 }
@@ -260,18 +587,33 @@ pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (
-                apply_input_system_8dir,
-                apply_input_system_2_1_dof,
-                apply_input_system_jnr,
-                background_on_click_system,
-                camera_zoom_system,
-                camera_rotate_system,
-                apply_input_system_portal_toggle,
-            ),
-        )
+        app.configure_sets(Update, InputSet::Read.before(InputSet::Apply))
+            .add_systems(
+                Update,
+                (
+                    apply_input_system_8dir,
+                    apply_input_system_2_1_dof,
+                    apply_input_system_flight_assist,
+                    apply_input_system_jnr,
+                    apply_input_system_portal_toggle,
+                    aim_settings_input_system,
+                    aim_input_system,
+                )
+                    .in_set(InputSet::Read),
+            )
+            .add_systems(
+                Update,
+                (
+                    background_on_click_system,
+                    camera_zoom_system,
+                    camera_rotate_system,
+                    gamepad_connection_system,
+                    apply_aim_system.in_set(InputSet::Apply),
+                ),
+            )
+        .init_resource::<AimSettings>()
+        .init_resource::<LevelRotation>()
+        .init_resource::<PlayerGamepads>()
         // .add_plugins(MousePosPlugin)
         ;
     }
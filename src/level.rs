@@ -0,0 +1,320 @@
+//! game flow of a level: intro, playing, player destroyed and level cleared. A level is cleared
+//! once all hostile droids are destroyed or taken over, the next level restarts the game with
+//! more droids (see `game_setup`). The world is paused while one of the screens is shown.
+
+use bevy::prelude::*;
+
+use crate::{
+    droid::DroidMarker,
+    game::despawn_screen,
+    menu::{menu_button_style, menu_text_style, spawn_menu_button, spawn_menu_screen, MenuState},
+    player::PlayerMarker,
+    prelude::*,
+    replay::ReplayMode,
+    state::AutoStart,
+};
+
+// seconds the intro is shown before the level starts by itself
+const INTRO_TIME: f32 = 3.0;
+// seconds between the player being destroyed / the level being cleared and the screen, so
+// that the final explosion can be seen
+const OUTCOME_DELAY: f32 = 1.5;
+
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
+pub enum LevelState {
+    // no game running
+    #[default]
+    None,
+    Intro,
+    Playing,
+    PlayerDestroyed,
+    Cleared,
+}
+
+/// the current level, starting at 1. Kept on restarts and loads, reset when going back to the
+/// title screen.
+#[derive(Resource)]
+pub struct Level {
+    pub number: u32,
+    // a level without hostile droids (e.g. with enemy droids disabled) is never cleared
+    hostiles_seen: bool,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            number: 1,
+            hostiles_seen: false,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct IntroTimer(Timer);
+
+// Tag component used to tag entities added on the level intro screen
+#[derive(Component)]
+struct OnLevelIntroScreen;
+
+// Tag component used to tag entities added on the player destroyed screen
+#[derive(Component)]
+struct OnPlayerDestroyedScreen;
+
+// Tag component used to tag entities added on the level cleared screen
+#[derive(Component)]
+struct OnLevelClearedScreen;
+
+#[derive(Component, Clone, Copy)]
+enum LevelButtonAction {
+    Start,
+    Retry,
+    NextLevel,
+    QuitToTitle,
+}
+
+fn level_start_system(
+    mut level: ResMut<Level>,
+    replay_mode: Res<ReplayMode>,
+    mut level_state: ResMut<NextState<LevelState>>,
+) {
+    level.hostiles_seen = false;
+    // replays run without the intro, it would shift the recorded frames
+    if replay_mode.is_replaying() {
+        level_state.set(LevelState::Playing);
+    } else {
+        level_state.set(LevelState::Intro);
+    }
+}
+
+fn level_stop_system(
+    auto_start: Res<AutoStart>,
+    mut level: ResMut<Level>,
+    mut level_state: ResMut<NextState<LevelState>>,
+) {
+    // restarts and loads keep the level
+    if !auto_start.0 {
+        level.number = 1;
+    }
+    level_state.set(LevelState::None);
+}
+
+fn pause_game_system(mut game_state: ResMut<NextState<GameState>>) {
+    game_state.set(GameState::Paused);
+}
+
+fn level_intro_setup(mut commands: Commands, asset_server: Res<AssetServer>, level: Res<Level>) {
+    commands.insert_resource(IntroTimer(Timer::from_seconds(INTRO_TIME, TimerMode::Once)));
+
+    let font = asset_server.load("fonts/MonaspaceKrypton-Bold.otf");
+    let button_style = menu_button_style();
+    let text_style = menu_text_style(font.clone());
+    spawn_menu_screen(
+        &mut commands,
+        OnLevelIntroScreen,
+        &format!("Level {}", level.number),
+        font,
+        |parent| {
+            parent.spawn(
+                TextBundle::from_section(
+                    "Destroy or take over all hostile droids",
+                    text_style.clone(),
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(20.0)),
+                    ..default()
+                }),
+            );
+            spawn_menu_button(
+                parent,
+                &button_style,
+                &text_style,
+                "Start",
+                LevelButtonAction::Start,
+            );
+        },
+    );
+}
+
+fn level_intro_system(
+    time: Res<Time>,
+    mut intro_timer: ResMut<IntroTimer>,
+    mut level_state: ResMut<NextState<LevelState>>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if intro_timer.0.tick(time.delta()).just_finished() {
+        level_state.set(LevelState::Playing);
+        game_state.set(GameState::Game);
+    }
+}
+
+fn player_destroyed_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    level: Res<Level>,
+) {
+    let font = asset_server.load("fonts/MonaspaceKrypton-Bold.otf");
+    let button_style = menu_button_style();
+    let text_style = menu_text_style(font.clone());
+    spawn_menu_screen(
+        &mut commands,
+        OnPlayerDestroyedScreen,
+        "Destroyed",
+        font,
+        |parent| {
+            parent.spawn(
+                TextBundle::from_section(
+                    format!("Lost in level {}", level.number),
+                    text_style.clone(),
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(20.0)),
+                    ..default()
+                }),
+            );
+            for (label, action) in [
+                ("Retry level", LevelButtonAction::Retry),
+                ("Quit to title", LevelButtonAction::QuitToTitle),
+            ] {
+                spawn_menu_button(parent, &button_style, &text_style, label, action);
+            }
+        },
+    );
+}
+
+fn level_cleared_setup(mut commands: Commands, asset_server: Res<AssetServer>, level: Res<Level>) {
+    let font = asset_server.load("fonts/MonaspaceKrypton-Bold.otf");
+    let button_style = menu_button_style();
+    let text_style = menu_text_style(font.clone());
+    spawn_menu_screen(
+        &mut commands,
+        OnLevelClearedScreen,
+        &format!("Level {} cleared", level.number),
+        font,
+        |parent| {
+            for (label, action) in [
+                ("Next level", LevelButtonAction::NextLevel),
+                ("Quit to title", LevelButtonAction::QuitToTitle),
+            ] {
+                spawn_menu_button(parent, &button_style, &text_style, label, action);
+            }
+        },
+    );
+}
+
+/// the level ends once all players are gone (destroyed) or no hostile droid is left (cleared)
+fn level_outcome_system(
+    time: Res<Time>,
+    mut level: ResMut<Level>,
+    mut outcome_delay: Local<f32>,
+    player_query: Query<(), With<PlayerMarker>>,
+    droid_query: Query<Option<&Team>, With<DroidMarker>>,
+    mut level_state: ResMut<NextState<LevelState>>,
+) {
+    let hostiles = droid_query
+        .iter()
+        .filter(|team| !matches!(team, Some(Team::Player)))
+        .count();
+    if hostiles > 0 && !level.hostiles_seen {
+        level.hostiles_seen = true;
+    }
+
+    let outcome = if player_query.is_empty() {
+        Some(LevelState::PlayerDestroyed)
+    } else if hostiles == 0 && level.hostiles_seen {
+        Some(LevelState::Cleared)
+    } else {
+        None
+    };
+    let Some(outcome) = outcome else {
+        *outcome_delay = 0.0;
+        return;
+    };
+    *outcome_delay += time.delta_seconds();
+    if *outcome_delay >= OUTCOME_DELAY {
+        *outcome_delay = 0.0;
+        info!("level {} ended: {:?}", level.number, outcome);
+        level_state.set(outcome);
+    }
+}
+
+fn level_button_system(
+    interaction_query: Query<
+        (&Interaction, &LevelButtonAction),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut level: ResMut<Level>,
+    mut auto_start: ResMut<AutoStart>,
+    mut level_state: ResMut<NextState<LevelState>>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut menu_state: ResMut<NextState<MenuState>>,
+) {
+    for (interaction, action) in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match action {
+            LevelButtonAction::Start => {
+                level_state.set(LevelState::Playing);
+                game_state.set(GameState::Game);
+            }
+            // restart through GameState::None, which despawns the GameMarker entities
+            LevelButtonAction::Retry => {
+                auto_start.0 = true;
+                game_state.set(GameState::None);
+            }
+            LevelButtonAction::NextLevel => {
+                level.number += 1;
+                auto_start.0 = true;
+                game_state.set(GameState::None);
+            }
+            LevelButtonAction::QuitToTitle => {
+                game_state.set(GameState::None);
+                menu_state.set(MenuState::Main);
+            }
+        }
+    }
+}
+
+pub struct LevelPlugin;
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<LevelState>()
+            .init_resource::<Level>()
+            .init_resource::<IntroTimer>()
+            .add_systems(OnExit(GameState::None), level_start_system)
+            .add_systems(OnEnter(GameState::None), level_stop_system)
+            .add_systems(
+                OnEnter(LevelState::Intro),
+                (pause_game_system, level_intro_setup),
+            )
+            .add_systems(
+                OnExit(LevelState::Intro),
+                despawn_screen::<OnLevelIntroScreen>,
+            )
+            .add_systems(
+                OnEnter(LevelState::PlayerDestroyed),
+                (pause_game_system, player_destroyed_setup),
+            )
+            .add_systems(
+                OnExit(LevelState::PlayerDestroyed),
+                despawn_screen::<OnPlayerDestroyedScreen>,
+            )
+            .add_systems(
+                OnEnter(LevelState::Cleared),
+                (pause_game_system, level_cleared_setup),
+            )
+            .add_systems(
+                OnExit(LevelState::Cleared),
+                despawn_screen::<OnLevelClearedScreen>,
+            )
+            .add_systems(
+                Update,
+                (
+                    level_intro_system.run_if(in_state(LevelState::Intro)),
+                    level_outcome_system
+                        .run_if(in_state(LevelState::Playing).and_then(in_state(GameState::Game))),
+                    level_button_system,
+                ),
+            );
+    }
+}
@@ -15,13 +15,27 @@ use hexagon_tiles::{
     point::Point,
 };
 use menu::MenuState;
+use std::path::PathBuf;
 
 use crate::{
-    debug_ui::DebugUiPlugin, edit::EditPlugin, game::GamePlugin, hexton::HextonPlugin,
-    menu::MenuPlugin, particle::ParticlePlugin, player::PlayerPlugin, portal::PortalPlugin,
-    prelude::*, ship::ShipPlugin, state::StatePlugin, weapon::WeaponPlugin,
+    action::{ActionInput, ActionPlugin, InputAction},
+    debug_ui::DebugUiPlugin,
+    edit::EditPlugin,
+    game::GamePlugin,
+    hexton::HextonPlugin,
+    hud::HudPlugin,
+    menu::MenuPlugin,
+    particle::ParticlePlugin,
+    player::PlayerPlugin,
+    portal::PortalPlugin,
+    prelude::*,
+    ship::ShipPlugin,
+    state::StatePlugin,
+    weapon::WeaponPlugin,
 };
 
+pub mod action;
+pub mod archetype;
 pub mod collision;
 pub mod droid;
 pub mod input;
@@ -71,13 +85,26 @@ pub mod collision_groups {
     pub const DROIDS: Group = Group::GROUP_1;
     pub const PROJECTILES: Group = Group::GROUP_2;
     pub const LEVEL: Group = Group::GROUP_3;
+    pub const PICKUPS: Group = Group::GROUP_4;
 }
 pub mod debug_ui;
+pub mod deployable;
 pub mod edit;
+pub mod feedback;
+pub mod fog;
 pub mod game;
+pub mod hud;
+pub mod level;
 pub mod menu;
+pub mod minimap;
+pub mod pickup;
 pub mod player;
+pub mod replay;
+pub mod rng;
+pub mod savegame;
+pub mod settings;
 pub mod state;
+pub mod touch;
 
 #[derive(Parser, Debug, Resource, Clone)]
 #[clap(author, version, about, long_about = None)]
@@ -111,6 +138,53 @@ pub struct CmdlineArgs {
 
     #[clap(short = 'p', long)]
     pub benchmark: bool,
+
+    /// record the player input to the given file
+    #[clap(long)]
+    pub record: Option<PathBuf>,
+
+    /// replay a recorded run instead of live input
+    #[clap(long)]
+    pub replay: Option<PathBuf>,
+
+    /// seed of the game rng (random by default)
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// two local players (second player on the arrow keys or the second gamepad)
+    #[clap(long)]
+    pub coop: bool,
+
+    /// reveal the whole level instead of exploring it
+    #[clap(long)]
+    pub no_fog: bool,
+
+    /// disable the screen shake on impacts
+    #[clap(long)]
+    pub no_screen_shake: bool,
+}
+
+impl CmdlineArgs {
+    /// settings forced by the flags. Flags that are not given leave the stored settings alone.
+    pub fn settings_overrides(&self) -> settings::SettingsOverrides {
+        let player = if self.benchmark {
+            Some(archetype::PlayerArchetype::Benchmark)
+        } else if self.ship {
+            Some(archetype::PlayerArchetype::Ship)
+        } else if self.hexton {
+            Some(archetype::PlayerArchetype::Hexton)
+        } else {
+            None
+        };
+        settings::SettingsOverrides {
+            player,
+            players: self.coop.then_some(2),
+            gravity: self.gravity.then_some(true),
+            enemy_droids: self.no_droid.then_some(false),
+            screen_shake: self.no_screen_shake.then_some(false),
+            fog: self.no_fog.then_some(false),
+        }
+    }
 }
 
 pub const HEX_LAYOUT: Layout = Layout {
@@ -175,8 +249,14 @@ pub fn despawn_reaper_system(mut commands: Commands, mut query: Query<(Entity, &
 pub struct DefaultPlugin;
 impl Plugin for DefaultPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, toggle_on_esc_system)
-            .add_systems(Last, despawn_reaper_system);
+        app.add_systems(
+            Update,
+            // the level flow screens are left through their buttons
+            toggle_on_esc_system.run_if(
+                not(in_state(MenuState::Controls)).and_then(in_state(level::LevelState::Playing)),
+            ),
+        )
+        .add_systems(Last, despawn_reaper_system);
     }
 }
 
@@ -203,14 +283,21 @@ impl PluginGroup for DefaultPlugins {
 
         let group = PluginGroupBuilder::start::<Self>()
             .add(DefaultPlugin)
+            .add(ActionPlugin)
             // bevy_rapier plugins
-            .add(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
+            .add(RapierPhysicsPlugin::<weapon::ProjectileHooks>::pixels_per_meter(100.0))
             .add(MousePosPlugin)
             .add(input::InputPlugin)
             .add(droid::DroidPlugin)
             .add(droid::ai::AiPlugin)
             .add(collision::CollisionPlugin)
             .add(camera::CameraPlugin)
+            .add(feedback::FeedbackPlugin)
+            .add(fog::FogPlugin)
+            .add(minimap::MinimapPlugin)
+            .add(settings::SettingsPlugin {
+                overrides: self.args.settings_overrides(),
+            })
             .add(tiles::TilesPlugin)
             .add(ShapePlugin)
             .add(waypoint::WaypointPlugin)
@@ -219,8 +306,16 @@ impl PluginGroup for DefaultPlugins {
             .add(HextonPlugin)
             .add(ParticlePlugin)
             .add(WeaponPlugin)
+            .add(deployable::DeployablePlugin)
+            .add(pickup::PickupPlugin)
+            .add(touch::TouchPlugin)
+            .add(rng::RngPlugin)
+            .add(replay::ReplayPlugin)
+            .add(savegame::SaveGamePlugin)
             .add(DebugUiPlugin)
+            .add(HudPlugin)
             .add(MenuPlugin)
+            .add(level::LevelPlugin)
             .add(GamePlugin)
             .add(PlayerPlugin)
             .add(StatePlugin)
@@ -259,22 +354,19 @@ impl PluginGroup for DefaultPlugins {
     }
 }
 
-pub fn exit_on_esc_system(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut app_exit_events: EventWriter<AppExit>,
-) {
-    if keyboard_input.just_pressed(KeyCode::Escape) {
+pub fn exit_on_esc_system(action_input: ActionInput, mut app_exit_events: EventWriter<AppExit>) {
+    if action_input.just_pressed(InputAction::Pause) {
         app_exit_events.send_default();
     }
 }
 pub fn toggle_on_esc_system(
-    keyboard_input: Res<Input<KeyCode>>,
+    action_input: ActionInput,
     _app_exit_events: EventWriter<AppExit>,
     cur_game_state: Res<State<GameState>>,
     mut menu_state: ResMut<NextState<MenuState>>,
     mut game_state: ResMut<NextState<GameState>>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Escape) {
+    if action_input.just_pressed(InputAction::Pause) {
         // app_exit_events.send_default();
         match cur_game_state.get() {
             GameState::None => {}
@@ -283,7 +375,7 @@ pub fn toggle_on_esc_system(
                 game_state.set(GameState::Game);
             }
             GameState::Game => {
-                menu_state.set(MenuState::Main);
+                menu_state.set(MenuState::Pause);
                 game_state.set(GameState::Paused);
             }
         }
@@ -299,5 +391,6 @@ pub mod prelude {
     pub use crate::state::{GameDespawn, GameState};
     pub use crate::tiles::{TileCache, TilePos, TileType, TilesState};
     pub use crate::tunables::default_stroke;
+    pub use crate::weapon::Team;
     pub use crate::Despawn;
 }
@@ -3,37 +3,79 @@ use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 use clap::Parser;
 
-use hexadroid::{game::GameSpawnInfo, CmdlineArgs};
+use hexadroid::{
+    game::GameSpawnInfo,
+    player::MAX_PLAYERS,
+    replay::{self, Recording, ReplayMode},
+    rng::GameSeed,
+    settings::Settings,
+    CmdlineArgs,
+};
 
 fn main() {
     let args = CmdlineArgs::parse();
 
-    let spawn_info = GameSpawnInfo {
-        spawn_player_ship: args.ship,
-        spawn_player_droid: !args.ship && !args.hexton,
-        spawn_player_jnr: args.hexton,
-        spawn_enemy_droids: if args.no_droid { 0 } else { 1 },
-        spawn_benchmark: args.benchmark,
-        gravity: args.gravity,
-    };
+    let recording = args.replay.as_ref().map(|path| {
+        Recording::load(path)
+            .unwrap_or_else(|err| panic!("failed to load replay {}: {}", path.display(), err))
+    });
+
     let mut app = App::new();
     // bevy plugins
-    app.add_plugins(DefaultPlugins)
-        .insert_resource(ClearColor(Color::BLACK))
-        .insert_resource(spawn_info)
-        .insert_resource(Msaa::default());
+    app.add_plugins(DefaultPlugins);
 
+    // game plugins. Loads the settings, with the command line flags applied.
     app.add_plugins(hexadroid::DefaultPlugins::new(args.clone())); //default().with_debug_draw(args.debug_draw));
 
-    let gravity = if args.gravity {
+    let settings = app.world.resource::<Settings>();
+    let spawn_info = match &recording {
+        Some(recording) => recording.spawn_info(),
+        None => GameSpawnInfo {
+            player: settings.player,
+            spawn_enemy_droids: if settings.enemy_droids { 1 } else { 0 },
+            gravity: settings.gravity,
+            players: settings.players.clamp(1, MAX_PLAYERS),
+        },
+    };
+    let seed = recording
+        .as_ref()
+        .map(|recording| recording.seed)
+        .or(args.seed)
+        .map(GameSeed)
+        .unwrap_or_default();
+    let fixed_timestep = recording.is_some() || args.record.is_some();
+    let replay_mode = match (recording, &args.record) {
+        (Some(recording), _) => ReplayMode::Replay {
+            recording,
+            frame: 0,
+        },
+        (None, Some(path)) => ReplayMode::Record {
+            path: path.clone(),
+            recording: Recording::new(seed.0, &spawn_info),
+        },
+        (None, None) => ReplayMode::Off,
+    };
+    let gravity = spawn_info.gravity;
+    app.insert_resource(ClearColor(Color::BLACK))
+        .insert_resource(spawn_info)
+        .insert_resource(Msaa::default());
+
+    let gravity = if gravity {
         Vec2::Y * -9.81 * 10.0
     } else {
         Vec2::ZERO
     };
-    app.insert_resource(RapierConfiguration {
+    let mut rapier_config = RapierConfiguration {
         gravity,
         ..default()
-    });
+    };
+    if fixed_timestep {
+        rapier_config.timestep_mode = replay::fixed_timestep_mode();
+        app.insert_resource(replay::fixed_time_update());
+    }
+    app.insert_resource(rapier_config)
+        .insert_resource(seed)
+        .insert_resource(replay_mode);
 
     app.insert_resource(args);
 
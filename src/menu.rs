@@ -1,10 +1,23 @@
 use bevy::{app::AppExit, prelude::*};
 
-use crate::{game::GameSpawnInfo, prelude::*, state::AutoStart};
+use crate::{
+    action::{ActionMap, InputAction},
+    archetype::PlayerArchetype,
+    game::GameSpawnInfo,
+    player::{PlayerIndex, MAX_PLAYERS},
+    prelude::*,
+    savegame::SaveGameRequest,
+    settings::{step_volume, Settings},
+    state::AutoStart,
+};
 // Tag component used to tag entities added on the main menu screen
 #[derive(Component)]
 struct OnMainMenuScreen;
 
+// Tag component used to tag entities added on the pause menu screen
+#[derive(Component)]
+struct OnPauseMenuScreen;
+
 // Tag component used to tag entities added on the settings menu screen
 #[derive(Component)]
 struct OnSettingsMenuScreen;
@@ -17,6 +30,69 @@ struct OnDisplaySettingsMenuScreen;
 #[derive(Component)]
 struct OnSoundSettingsMenuScreen;
 
+// Tag component used to tag entities added on the controls menu screen
+#[derive(Component)]
+struct OnControlsMenuScreen;
+
+// Button changing a setting. `steps` is the volume change for the volume settings.
+#[derive(Component)]
+struct SettingButton {
+    kind: SettingKind,
+    steps: i32,
+}
+
+// Text showing the current value of a setting
+#[derive(Component)]
+struct SettingText(SettingKind);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SettingKind {
+    WindowMode,
+    Vsync,
+    Bloom,
+    ScreenShake,
+    Minimap,
+    Fog,
+    MasterVolume,
+    MusicVolume,
+    EffectsVolume,
+}
+
+impl SettingKind {
+    const DISPLAY: [SettingKind; 6] = [
+        SettingKind::WindowMode,
+        SettingKind::Vsync,
+        SettingKind::Bloom,
+        SettingKind::ScreenShake,
+        SettingKind::Minimap,
+        SettingKind::Fog,
+    ];
+    const SOUND: [SettingKind; 3] = [
+        SettingKind::MasterVolume,
+        SettingKind::MusicVolume,
+        SettingKind::EffectsVolume,
+    ];
+}
+
+// Button on the controls screen that starts rebinding an action of a player
+#[derive(Component)]
+struct RebindButton(PlayerIndex, InputAction);
+
+// Text showing the binding of an action of a player
+#[derive(Component)]
+struct BindingText(PlayerIndex, InputAction);
+
+// Text showing the rebinding status (waiting for key, conflicts)
+#[derive(Component)]
+struct RebindStatusText;
+
+/// player and action currently waiting for a new key on the controls screen
+#[derive(Resource, Default)]
+struct Rebinding {
+    action: Option<(PlayerIndex, InputAction)>,
+    message: String,
+}
+
 const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
 // const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
 // const HOVERED_PRESSED_BUTTON: Color = Color::rgb(0.25, 0.65, 0.25);
@@ -24,18 +100,25 @@ const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
 const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
 // All actions that can be triggered from a button click
 
-#[allow(dead_code)]
 #[derive(Component)]
 enum MenuButtonAction {
     PlayDroid,
     PlayShip,
     PlayHexton,
+    Resume,
+    Restart,
+    SaveGame,
+    LoadGame,
     DropGame,
-    // Settings,
-    // SettingsDisplay,
-    // SettingsSound,
-    // BackToMainMenu,
-    // BackToSettings,
+    TogglePlayers,
+    Controls,
+    ResetBindings,
+    Settings,
+    SettingsDisplay,
+    SettingsSound,
+    // back to the main or the pause menu, depending on the game state
+    BackFromSettings,
+    BackToSettings,
     Quit,
 }
 
@@ -43,13 +126,29 @@ enum MenuButtonAction {
 pub enum MenuState {
     #[default]
     Main,
+    Pause,
+    Settings,
+    SettingsDisplay,
+    SettingsSound,
+    Controls,
     Disabled,
 }
 // fn menu_setup(mut menu_state: ResMut<NextState<MenuState>>) {
 //     menu_state.set(MenuState::Main);
 // }
 
-fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+#[derive(Component)]
+struct PlayerCountText;
+
+fn player_count_label(players: usize) -> String {
+    format!("Players: {}", players)
+}
+
+fn main_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    spawn_info: Res<GameSpawnInfo>,
+) {
     // Common style for all buttons on the screen
     let button_style = Style {
         width: Val::Px(250.0),
@@ -145,48 +244,6 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                                 button_text_style.clone(),
                             ));
                         });
-                    // parent
-                    //     .spawn((
-                    //         ButtonBundle {
-                    //             style: button_style.clone(),
-                    //             background_color: NORMAL_BUTTON.into(),
-                    //             ..default()
-                    //         },
-                    //         MenuButtonAction::PlayShip,
-                    //     ))
-                    //     .with_children(|parent| {
-                    //         let icon = asset_server.load("textures/Game Icons/right.png");
-                    //         parent.spawn(ImageBundle {
-                    //             style: button_icon_style.clone(),
-                    //             image: UiImage::new(icon),
-                    //             ..default()
-                    //         });
-                    //         parent.spawn(TextBundle::from_section(
-                    //             "Play Ship",
-                    //             button_text_style.clone(),
-                    //         ));
-                    //     });
-                    // parent
-                    //     .spawn((
-                    //         ButtonBundle {
-                    //             style: button_style.clone(),
-                    //             background_color: NORMAL_BUTTON.into(),
-                    //             ..default()
-                    //         },
-                    //         MenuButtonAction::PlayHexton,
-                    //     ))
-                    //     .with_children(|parent| {
-                    //         let icon = asset_server.load("textures/Game Icons/right.png");
-                    //         parent.spawn(ImageBundle {
-                    //             style: button_icon_style.clone(),
-                    //             image: UiImage::new(icon),
-                    //             ..default()
-                    //         });
-                    //         parent.spawn(TextBundle::from_section(
-                    //             "Play Cmdr",
-                    //             button_text_style.clone(),
-                    //         ));
-                    //     });
                     parent
                         .spawn((
                             ButtonBundle {
@@ -194,7 +251,7 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                                 background_color: NORMAL_BUTTON.into(),
                                 ..default()
                             },
-                            MenuButtonAction::DropGame,
+                            MenuButtonAction::PlayShip,
                         ))
                         .with_children(|parent| {
                             let icon = asset_server.load("textures/Game Icons/right.png");
@@ -204,31 +261,76 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                                 ..default()
                             });
                             parent.spawn(TextBundle::from_section(
-                                "Drop game",
+                                "Play Ship",
+                                button_text_style.clone(),
+                            ));
+                        });
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            MenuButtonAction::PlayHexton,
+                        ))
+                        .with_children(|parent| {
+                            let icon = asset_server.load("textures/Game Icons/right.png");
+                            parent.spawn(ImageBundle {
+                                style: button_icon_style.clone(),
+                                image: UiImage::new(icon),
+                                ..default()
+                            });
+                            parent.spawn(TextBundle::from_section(
+                                "Play Cmdr",
+                                button_text_style.clone(),
+                            ));
+                        });
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            MenuButtonAction::TogglePlayers,
+                        ))
+                        .with_children(|parent| {
+                            let icon = asset_server.load("textures/Game Icons/right.png");
+                            parent.spawn(ImageBundle {
+                                style: button_icon_style.clone(),
+                                image: UiImage::new(icon),
+                                ..default()
+                            });
+                            parent.spawn((
+                                TextBundle::from_section(
+                                    player_count_label(spawn_info.players),
+                                    button_text_style.clone(),
+                                ),
+                                PlayerCountText,
+                            ));
+                        });
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            MenuButtonAction::Settings,
+                        ))
+                        .with_children(|parent| {
+                            let icon = asset_server.load("textures/Game Icons/wrench.png");
+                            parent.spawn(ImageBundle {
+                                style: button_icon_style.clone(),
+                                image: UiImage::new(icon),
+                                ..default()
+                            });
+                            parent.spawn(TextBundle::from_section(
+                                "Settings",
                                 button_text_style.clone(),
                             ));
                         });
-                    // parent
-                    //     .spawn((
-                    //         ButtonBundle {
-                    //             style: button_style.clone(),
-                    //             background_color: NORMAL_BUTTON.into(),
-                    //             ..default()
-                    //         },
-                    //         MenuButtonAction::Settings,
-                    //     ))
-                    //     .with_children(|parent| {
-                    //         let icon = asset_server.load("textures/Game Icons/wrench.png");
-                    //         parent.spawn(ImageBundle {
-                    //             style: button_icon_style.clone(),
-                    //             image: UiImage::new(icon),
-                    //             ..default()
-                    //         });
-                    //         parent.spawn(TextBundle::from_section(
-                    //             "Settings",
-                    //             button_text_style.clone(),
-                    //         ));
-                    //     });
                     parent
                         .spawn((
                             ButtonBundle {
@@ -251,7 +353,578 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         });
 }
 
-#[allow(clippy::type_complexity)]
+// Common layout of the pause, settings and level flow screens: a bordered column with a title, filled by
+// `content`
+pub(crate) fn spawn_menu_screen(
+    commands: &mut Commands,
+    marker: impl Component,
+    title: &str,
+    font: Handle<Font>,
+    content: impl FnOnce(&mut ChildBuilder),
+) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            marker,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        border: UiRect::px(10., 10., 10., 10.),
+                        ..default()
+                    },
+                    background_color: Color::BLACK.with_a(0.5).into(),
+                    border_color: Color::GREEN.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(
+                        TextBundle::from_section(
+                            title,
+                            TextStyle {
+                                font_size: 60.0,
+                                color: TEXT_COLOR,
+                                font,
+                            },
+                        )
+                        .with_style(Style {
+                            margin: UiRect::all(Val::Px(20.0)),
+                            ..default()
+                        }),
+                    );
+                    content(parent);
+                });
+        });
+}
+
+pub(crate) fn spawn_menu_button(
+    parent: &mut ChildBuilder,
+    style: &Style,
+    text_style: &TextStyle,
+    label: &str,
+    action: impl Bundle,
+) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: style.clone(),
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            },
+            action,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(label, text_style.clone()));
+        });
+}
+
+pub(crate) fn menu_button_style() -> Style {
+    Style {
+        width: Val::Px(300.0),
+        height: Val::Px(50.0),
+        margin: UiRect::all(Val::Px(10.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    }
+}
+
+pub(crate) fn menu_text_style(font: Handle<Font>) -> TextStyle {
+    TextStyle {
+        font_size: 30.0,
+        color: TEXT_COLOR,
+        font,
+    }
+}
+
+fn pause_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/MonaspaceKrypton-Bold.otf");
+    let button_style = menu_button_style();
+    let text_style = menu_text_style(font.clone());
+    spawn_menu_screen(&mut commands, OnPauseMenuScreen, "Paused", font, |parent| {
+        for (label, action) in [
+            ("Resume", MenuButtonAction::Resume),
+            ("Restart", MenuButtonAction::Restart),
+            ("Save", MenuButtonAction::SaveGame),
+            ("Load", MenuButtonAction::LoadGame),
+            ("Settings", MenuButtonAction::Settings),
+            ("Quit to title", MenuButtonAction::DropGame),
+        ] {
+            spawn_menu_button(parent, &button_style, &text_style, label, action);
+        }
+    });
+}
+
+fn settings_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/MonaspaceKrypton-Bold.otf");
+    let button_style = menu_button_style();
+    let text_style = menu_text_style(font.clone());
+    spawn_menu_screen(
+        &mut commands,
+        OnSettingsMenuScreen,
+        "Settings",
+        font,
+        |parent| {
+            for (label, action) in [
+                ("Display", MenuButtonAction::SettingsDisplay),
+                ("Sound", MenuButtonAction::SettingsSound),
+                ("Controls", MenuButtonAction::Controls),
+                ("Back", MenuButtonAction::BackFromSettings),
+            ] {
+                spawn_menu_button(parent, &button_style, &text_style, label, action);
+            }
+        },
+    );
+}
+
+fn setting_label(settings: &Settings, kind: SettingKind) -> String {
+    let on_off = |value: bool| if value { "on" } else { "off" };
+    let percent = |volume: f32| format!("{:.0}%", volume * 100.0);
+    match kind {
+        SettingKind::WindowMode => format!("Window: {}", settings.window_mode.name()),
+        SettingKind::Vsync => format!("VSync: {}", on_off(settings.vsync)),
+        SettingKind::Bloom => format!("Bloom: {}", on_off(settings.bloom)),
+        SettingKind::ScreenShake => format!("Screen shake: {}", on_off(settings.screen_shake)),
+        SettingKind::Minimap => format!("Minimap: {}", on_off(settings.minimap)),
+        SettingKind::Fog => format!("Fog of war: {}", on_off(settings.fog)),
+        SettingKind::MasterVolume => format!("Master: {}", percent(settings.master_volume)),
+        SettingKind::MusicVolume => format!("Music: {}", percent(settings.music_volume)),
+        SettingKind::EffectsVolume => format!("Effects: {}", percent(settings.effects_volume)),
+    }
+}
+
+fn display_settings_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+) {
+    let font = asset_server.load("fonts/MonaspaceKrypton-Bold.otf");
+    let button_style = Style {
+        width: Val::Px(450.0),
+        ..menu_button_style()
+    };
+    let text_style = menu_text_style(font.clone());
+    spawn_menu_screen(
+        &mut commands,
+        OnDisplaySettingsMenuScreen,
+        "Display",
+        font,
+        |parent| {
+            for kind in SettingKind::DISPLAY {
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: button_style.clone(),
+                            background_color: NORMAL_BUTTON.into(),
+                            ..default()
+                        },
+                        SettingButton { kind, steps: 1 },
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            TextBundle::from_section(
+                                setting_label(&settings, kind),
+                                text_style.clone(),
+                            ),
+                            SettingText(kind),
+                        ));
+                    });
+            }
+            spawn_menu_button(
+                parent,
+                &menu_button_style(),
+                &text_style,
+                "Back",
+                MenuButtonAction::BackToSettings,
+            );
+        },
+    );
+}
+
+fn sound_settings_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+) {
+    let font = asset_server.load("fonts/MonaspaceKrypton-Bold.otf");
+    let step_button_style = Style {
+        width: Val::Px(50.0),
+        ..menu_button_style()
+    };
+    let text_style = menu_text_style(font.clone());
+    spawn_menu_screen(
+        &mut commands,
+        OnSoundSettingsMenuScreen,
+        "Sound",
+        font,
+        |parent| {
+            for kind in SettingKind::SOUND {
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Row,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        spawn_menu_button(
+                            parent,
+                            &step_button_style,
+                            &text_style,
+                            "-",
+                            SettingButton { kind, steps: -1 },
+                        );
+                        parent.spawn((
+                            TextBundle::from_section(
+                                setting_label(&settings, kind),
+                                text_style.clone(),
+                            )
+                            .with_style(Style {
+                                width: Val::Px(300.0),
+                                ..default()
+                            }),
+                            SettingText(kind),
+                        ));
+                        spawn_menu_button(
+                            parent,
+                            &step_button_style,
+                            &text_style,
+                            "+",
+                            SettingButton { kind, steps: 1 },
+                        );
+                    });
+            }
+            spawn_menu_button(
+                parent,
+                &menu_button_style(),
+                &text_style,
+                "Back",
+                MenuButtonAction::BackToSettings,
+            );
+        },
+    );
+}
+
+fn setting_button_system(
+    interaction_query: Query<(&Interaction, &SettingButton), (Changed<Interaction>, With<Button>)>,
+    mut settings: ResMut<Settings>,
+) {
+    for (interaction, button) in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button.kind {
+            SettingKind::WindowMode => settings.window_mode = settings.window_mode.next(),
+            SettingKind::Vsync => settings.vsync = !settings.vsync,
+            SettingKind::Bloom => settings.bloom = !settings.bloom,
+            SettingKind::ScreenShake => settings.screen_shake = !settings.screen_shake,
+            SettingKind::Minimap => settings.minimap = !settings.minimap,
+            SettingKind::Fog => settings.fog = !settings.fog,
+            SettingKind::MasterVolume => {
+                settings.master_volume = step_volume(settings.master_volume, button.steps);
+            }
+            SettingKind::MusicVolume => {
+                settings.music_volume = step_volume(settings.music_volume, button.steps);
+            }
+            SettingKind::EffectsVolume => {
+                settings.effects_volume = step_volume(settings.effects_volume, button.steps);
+            }
+        }
+    }
+}
+
+fn setting_text_system(settings: Res<Settings>, mut text_query: Query<(&mut Text, &SettingText)>) {
+    if !settings.is_changed() {
+        return;
+    }
+    for (mut text, setting_text) in &mut text_query {
+        text.sections[0].value = setting_label(&settings, setting_text.0);
+    }
+}
+
+fn controls_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    action_map: Res<ActionMap>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    *rebinding = Rebinding::default();
+
+    let binding_button_style = Style {
+        width: Val::Px(380.0),
+        height: Val::Px(32.0),
+        margin: UiRect::all(Val::Px(4.0)),
+        padding: UiRect::horizontal(Val::Px(10.0)),
+        justify_content: JustifyContent::FlexStart,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let button_style = Style {
+        width: Val::Px(250.0),
+        height: Val::Px(50.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let font = asset_server.load("fonts/MonaspaceKrypton-Bold.otf");
+    let binding_text_style = TextStyle {
+        font_size: 20.0,
+        color: TEXT_COLOR,
+        font: font.clone(),
+    };
+    let button_text_style = TextStyle {
+        font_size: 30.0,
+        color: TEXT_COLOR,
+        font: font.clone(),
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnControlsMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        border: UiRect::px(10., 10., 10., 10.),
+                        ..default()
+                    },
+                    background_color: Color::BLACK.with_a(0.5).into(),
+                    border_color: Color::GREEN.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(
+                        TextBundle::from_section(
+                            "Controls",
+                            TextStyle {
+                                font_size: 60.0,
+                                color: TEXT_COLOR,
+                                font,
+                            },
+                        )
+                        .with_style(Style {
+                            margin: UiRect::all(Val::Px(20.0)),
+                            ..default()
+                        }),
+                    );
+                    // three columns of bindings, the first player's followed by the second
+                    // player's, who only has the player actions
+                    parent
+                        .spawn(NodeBundle {
+                            style: Style {
+                                width: Val::Px(1200.0),
+                                flex_direction: FlexDirection::Row,
+                                flex_wrap: FlexWrap::Wrap,
+                                justify_content: JustifyContent::Center,
+                                ..default()
+                            },
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            let bindings = InputAction::ALL
+                                .map(|action| (PlayerIndex(0), action))
+                                .into_iter()
+                                .chain(
+                                    InputAction::PLAYER_ACTIONS
+                                        .map(|action| (PlayerIndex(1), action)),
+                                );
+                            for (player, action) in bindings {
+                                parent
+                                    .spawn((
+                                        ButtonBundle {
+                                            style: binding_button_style.clone(),
+                                            background_color: NORMAL_BUTTON.into(),
+                                            ..default()
+                                        },
+                                        RebindButton(player, action),
+                                    ))
+                                    .with_children(|parent| {
+                                        parent.spawn((
+                                            TextBundle::from_section(
+                                                binding_label(
+                                                    &action_map,
+                                                    &rebinding,
+                                                    player,
+                                                    action,
+                                                ),
+                                                binding_text_style.clone(),
+                                            ),
+                                            BindingText(player, action),
+                                        ));
+                                    });
+                            }
+                        });
+                    parent.spawn((
+                        TextBundle::from_section("", binding_text_style.clone()).with_style(
+                            Style {
+                                margin: UiRect::all(Val::Px(10.0)),
+                                ..default()
+                            },
+                        ),
+                        RebindStatusText,
+                    ));
+                    parent
+                        .spawn(NodeBundle {
+                            style: Style {
+                                flex_direction: FlexDirection::Row,
+                                ..default()
+                            },
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            for (label, action) in [
+                                ("Defaults", MenuButtonAction::ResetBindings),
+                                ("Back", MenuButtonAction::BackToSettings),
+                            ] {
+                                parent
+                                    .spawn((
+                                        ButtonBundle {
+                                            style: button_style.clone(),
+                                            background_color: NORMAL_BUTTON.into(),
+                                            ..default()
+                                        },
+                                        action,
+                                    ))
+                                    .with_children(|parent| {
+                                        parent.spawn(TextBundle::from_section(
+                                            label,
+                                            button_text_style.clone(),
+                                        ));
+                                    });
+                            }
+                        });
+                });
+        });
+}
+
+/// name of the action, prefixed with the player for the per player actions
+fn binding_name(player: PlayerIndex, action: InputAction) -> String {
+    if InputAction::PLAYER_ACTIONS.contains(&action) {
+        format!("{} {}", player.name(), action.name())
+    } else {
+        action.name().to_string()
+    }
+}
+
+fn binding_label(
+    action_map: &ActionMap,
+    rebinding: &Rebinding,
+    player: PlayerIndex,
+    action: InputAction,
+) -> String {
+    if rebinding.action == Some((player, action)) {
+        format!("{}: <press key>", binding_name(player, action))
+    } else {
+        format!(
+            "{}: {}",
+            binding_name(player, action),
+            action_map.describe(player, action)
+        )
+    }
+}
+
+fn rebind_button_system(
+    interaction_query: Query<(&Interaction, &RebindButton), (Changed<Interaction>, With<Button>)>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    for (interaction, rebind_button) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            rebinding.action = Some((rebind_button.0, rebind_button.1));
+            rebinding.message = "press a key (Escape to cancel)".into();
+        }
+    }
+}
+
+fn rebind_key_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut action_map: ResMut<ActionMap>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    let Some((player, action)) = rebinding.action else {
+        return;
+    };
+    let Some(key) = keyboard_input.get_just_pressed().next().copied() else {
+        return;
+    };
+    rebinding.action = None;
+    if key == KeyCode::Escape {
+        rebinding.message.clear();
+        return;
+    }
+    match action_map.rebind(player, action, key) {
+        Ok(()) => {
+            action_map.save();
+            rebinding.message = format!("{} bound to {:?}", binding_name(player, action), key);
+        }
+        Err((other_player, other)) => {
+            rebinding.message = format!(
+                "{:?} is already used by '{}'",
+                key,
+                binding_name(other_player, other)
+            );
+        }
+    }
+}
+
+fn controls_text_system(
+    action_map: Res<ActionMap>,
+    rebinding: Res<Rebinding>,
+    mut binding_text_query: Query<(&mut Text, &BindingText), Without<RebindStatusText>>,
+    mut status_text_query: Query<&mut Text, With<RebindStatusText>>,
+) {
+    if !action_map.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+    for (mut text, binding_text) in &mut binding_text_query {
+        text.sections[0].value =
+            binding_label(&action_map, &rebinding, binding_text.0, binding_text.1);
+    }
+    for mut text in &mut status_text_query {
+        text.sections[0].value = rebinding.message.clone();
+    }
+}
+
+fn player_count_text_system(
+    spawn_info: Res<GameSpawnInfo>,
+    mut text_query: Query<&mut Text, With<PlayerCountText>>,
+) {
+    if !spawn_info.is_changed() {
+        return;
+    }
+    for mut text in &mut text_query {
+        text.sections[0].value = player_count_label(spawn_info.players);
+    }
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 fn menu_action(
     interaction_query: Query<
         (&Interaction, &MenuButtonAction),
@@ -263,6 +936,9 @@ fn menu_action(
     mut menu_state: ResMut<NextState<MenuState>>,
     mut game_state: ResMut<NextState<GameState>>,
     mut spawn_info: ResMut<GameSpawnInfo>,
+    mut action_map: ResMut<ActionMap>,
+    mut settings: ResMut<Settings>,
+    mut savegame_requests: EventWriter<SaveGameRequest>,
 ) {
     let mut start = false;
     for (interaction, menu_button_action) in &interaction_query {
@@ -270,41 +946,62 @@ fn menu_action(
             match menu_button_action {
                 MenuButtonAction::Quit => app_exit_events.send(AppExit),
                 MenuButtonAction::PlayDroid => {
-                    spawn_info.spawn_player_droid = true;
-                    spawn_info.spawn_player_ship = false;
-                    spawn_info.spawn_player_jnr = false;
+                    spawn_info.player = PlayerArchetype::Droid;
                     start = true;
                 }
                 MenuButtonAction::PlayShip => {
-                    spawn_info.spawn_player_droid = false;
-                    spawn_info.spawn_player_ship = true;
-                    spawn_info.spawn_player_jnr = false;
+                    spawn_info.player = PlayerArchetype::Ship;
                     start = true;
                 }
                 MenuButtonAction::PlayHexton => {
-                    spawn_info.spawn_player_droid = false;
-                    spawn_info.spawn_player_ship = false;
-                    spawn_info.spawn_player_jnr = true;
+                    spawn_info.player = PlayerArchetype::Hexton;
                     start = true;
                 }
+                MenuButtonAction::Resume => {
+                    game_state.set(GameState::Game);
+                    menu_state.set(MenuState::Disabled);
+                }
+                // same player setup as the running game
+                MenuButtonAction::Restart => start = true,
+                MenuButtonAction::SaveGame => savegame_requests.send(SaveGameRequest::Save),
+                // restarts the game with the saved state
+                MenuButtonAction::LoadGame => savegame_requests.send(SaveGameRequest::Load),
                 MenuButtonAction::DropGame => {
                     game_state.set(GameState::None);
                     menu_state.set(MenuState::Main);
-                } // MenuButtonAction::Settings => menu_state.set(MenuState::Settings),
-                  // MenuButtonAction::SettingsDisplay => {
-                  //     menu_state.set(MenuState::SettingsDisplay);
-                  // }
-                  // MenuButtonAction::SettingsSound => {
-                  //     menu_state.set(MenuState::SettingsSound);
-                  // }
-                  // MenuButtonAction::BackToMainMenu => menu_state.set(MenuState::Main),
-                  // MenuButtonAction::BackToSettings => {
-                  //     menu_state.set(MenuState::Settings);
-                  // }
+                }
+                MenuButtonAction::TogglePlayers => {
+                    // applies to the next game
+                    spawn_info.players = spawn_info.players % MAX_PLAYERS + 1;
+                    settings.players = spawn_info.players;
+                }
+                MenuButtonAction::Controls => menu_state.set(MenuState::Controls),
+                MenuButtonAction::ResetBindings => {
+                    *action_map = ActionMap::default();
+                    action_map.save();
+                }
+                MenuButtonAction::Settings => menu_state.set(MenuState::Settings),
+                MenuButtonAction::SettingsDisplay => {
+                    menu_state.set(MenuState::SettingsDisplay);
+                }
+                MenuButtonAction::SettingsSound => {
+                    menu_state.set(MenuState::SettingsSound);
+                }
+                MenuButtonAction::BackFromSettings => {
+                    if *cur_game_state.get() == GameState::Paused {
+                        menu_state.set(MenuState::Pause);
+                    } else {
+                        menu_state.set(MenuState::Main);
+                    }
+                }
+                MenuButtonAction::BackToSettings => {
+                    menu_state.set(MenuState::Settings);
+                }
             }
         }
     }
     if start {
+        settings.player = spawn_info.player;
         if *cur_game_state.get() != GameState::None {
             auto_start.0 = true;
             game_state.set(GameState::None);
@@ -328,6 +1025,50 @@ impl Plugin for MenuPlugin {
             // Systems to handle the main menu screen
             .add_systems(OnEnter(MenuState::Main), main_menu_setup)
             .add_systems(OnExit(MenuState::Main), despawn_screen::<OnMainMenuScreen>)
+            .add_systems(
+                Update,
+                player_count_text_system.run_if(in_state(MenuState::Main)),
+            )
+            .add_systems(OnEnter(MenuState::Pause), pause_menu_setup)
+            .add_systems(
+                OnExit(MenuState::Pause),
+                despawn_screen::<OnPauseMenuScreen>,
+            )
+            .add_systems(OnEnter(MenuState::Settings), settings_menu_setup)
+            .add_systems(
+                OnExit(MenuState::Settings),
+                despawn_screen::<OnSettingsMenuScreen>,
+            )
+            .add_systems(
+                OnEnter(MenuState::SettingsDisplay),
+                display_settings_menu_setup,
+            )
+            .add_systems(
+                OnExit(MenuState::SettingsDisplay),
+                despawn_screen::<OnDisplaySettingsMenuScreen>,
+            )
+            .add_systems(OnEnter(MenuState::SettingsSound), sound_settings_menu_setup)
+            .add_systems(
+                OnExit(MenuState::SettingsSound),
+                despawn_screen::<OnSoundSettingsMenuScreen>,
+            )
+            .add_systems(Update, (setting_button_system, setting_text_system).chain())
+            .init_resource::<Rebinding>()
+            .add_systems(OnEnter(MenuState::Controls), controls_menu_setup)
+            .add_systems(
+                OnExit(MenuState::Controls),
+                despawn_screen::<OnControlsMenuScreen>,
+            )
+            .add_systems(
+                Update,
+                (
+                    rebind_button_system,
+                    rebind_key_system,
+                    controls_text_system,
+                )
+                    .chain()
+                    .run_if(in_state(MenuState::Controls)),
+            )
             .add_systems(
                 Update,
                 menu_action,
@@ -0,0 +1,372 @@
+//! minimap overlay. A small camera in the top right corner renders a separate render layer,
+//! which holds one hexagon per tile of TileCache plus markers for players, droids and portals.
+//! The tile shapes are updated incrementally from `TileCache::dirty_positions`.
+
+use bevy::{
+    core_pipeline::{clear_color::ClearColorConfig, tonemapping::Tonemapping},
+    math::Vec3Swizzles,
+    prelude::*,
+    render::view::RenderLayers,
+    utils::HashMap,
+    window::PrimaryWindow,
+};
+use bevy_prototype_lyon::{prelude::*, shapes};
+use hexagon_tiles::layout::LayoutTool;
+
+use crate::{
+    action::{ActionInput, InputAction},
+    camera::{set_viewport, CameraTarget},
+    droid::DroidMarker,
+    fog::{FogOfWar, HexVisibility},
+    hex_point_to_vec2,
+    player::{PlayerIndex, PlayerMarker},
+    portal::Portal,
+    prelude::*,
+    HEX_LAYOUT,
+};
+
+pub const MINIMAP_LAYER: u8 = 1;
+
+// z order within the minimap layer. The minimap camera sees world z from -0.1 upwards.
+const BACKGROUND_Z: f32 = 0.5;
+const TILE_Z: f32 = 1.0;
+const PORTAL_Z: f32 = 2.0;
+const DROID_Z: f32 = 3.0;
+const PLAYER_Z: f32 = 4.0;
+
+const BACKGROUND_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.7);
+const TILE_COLOR: Color = Color::rgb(0.3, 0.3, 0.4);
+const PORTAL_COLOR: Color = Color::rgb(0.8, 0.2, 1.0);
+const DROID_COLOR: Color = Color::rgb(1.0, 0.2, 0.2);
+const PLAYER_COLORS: [Color; 2] = [Color::rgb(0.2, 1.0, 0.2), Color::rgb(0.2, 0.8, 1.0)];
+
+#[derive(Resource)]
+pub struct MinimapSettings {
+    pub enabled: bool,
+    // size (logical pixels) of the minimap in the window corner
+    pub size: f32,
+    // world extent (pixels) shown by the minimap
+    pub range: f32,
+    // only show droids visible through the fog of war
+    pub only_seen_droids: bool,
+}
+
+impl Default for MinimapSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            size: 200.0,
+            range: 4000.0,
+            only_seen_droids: true,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct MinimapCamera;
+
+#[derive(Component)]
+struct MinimapBackground;
+
+#[derive(Component)]
+struct DroidMinimapMarker;
+
+/// minimap shapes of things that have no transform of their own (tiles, portals)
+#[derive(Resource, Default)]
+struct MinimapState {
+    tiles: HashMap<TilePos, Entity>,
+    portals: HashMap<Entity, Entity>,
+}
+
+fn minimap_layer() -> RenderLayers {
+    RenderLayers::layer(MINIMAP_LAYER)
+}
+
+fn hexagon(radius: f32) -> shapes::RegularPolygon {
+    shapes::RegularPolygon {
+        sides: 6,
+        feature: shapes::RegularPolygonFeature::Radius(radius),
+        ..default()
+    }
+}
+
+fn tile_center(tile_pos: TilePos) -> Vec2 {
+    hex_point_to_vec2(LayoutTool::hex_to_pixel(HEX_LAYOUT, tile_pos.0))
+}
+
+fn tile_visibility(fog: &FogOfWar, tile_pos: TilePos) -> Visibility {
+    if fog.state(tile_pos) == HexVisibility::Unseen {
+        Visibility::Hidden
+    } else {
+        Visibility::Inherited
+    }
+}
+
+fn spawn_shape(
+    commands: &mut Commands,
+    shape: &impl Geometry,
+    color: Color,
+    transform: Transform,
+) -> Entity {
+    commands
+        .spawn((
+            ShapeBundle {
+                path: GeometryBuilder::build_as(shape),
+                spatial: SpatialBundle::from_transform(transform),
+                ..default()
+            },
+            Fill::color(color),
+            minimap_layer(),
+        ))
+        .id()
+}
+
+fn setup_minimap_system(mut commands: Commands) {
+    commands
+        .spawn((
+            Camera2dBundle {
+                camera: Camera {
+                    hdr: true,
+                    is_active: false,
+                    // on top of the main and split view
+                    order: 2,
+                    ..default()
+                },
+                camera_2d: Camera2d {
+                    clear_color: ClearColorConfig::None,
+                },
+                tonemapping: Tonemapping::TonyMcMapface,
+                ..default()
+            },
+            UiCameraConfig { show_ui: false },
+            minimap_layer(),
+            MinimapCamera,
+        ))
+        .with_children(|parent| {
+            // the camera sits at z=999.9, the background goes behind everything else
+            parent.spawn((
+                ShapeBundle {
+                    path: GeometryBuilder::build_as(&shapes::Rectangle {
+                        extents: Vec2::splat(1.0),
+                        ..default()
+                    }),
+                    spatial: SpatialBundle::from_transform(Transform::from_xyz(
+                        0.0,
+                        0.0,
+                        BACKGROUND_Z - 999.9,
+                    )),
+                    ..default()
+                },
+                Fill::color(BACKGROUND_COLOR),
+                minimap_layer(),
+                MinimapBackground,
+            ));
+        });
+}
+
+/// add and remove tile hexagons for the tiles changed since the last update
+fn minimap_tiles_system(
+    mut commands: Commands,
+    mut tile_cache: ResMut<TileCache>,
+    mut state: ResMut<MinimapState>,
+    fog: Res<FogOfWar>,
+    mut visibility_query: Query<&mut Visibility>,
+) {
+    // unexplored tiles are not shown
+    if fog.is_changed() {
+        for (tile_pos, entity) in &state.tiles {
+            if let Ok(mut visibility) = visibility_query.get_mut(*entity) {
+                let new_visibility = tile_visibility(&fog, *tile_pos);
+                if *visibility != new_visibility {
+                    *visibility = new_visibility;
+                }
+            }
+        }
+    }
+    if tile_cache.dirty_positions.is_empty() {
+        return;
+    }
+    let shape = hexagon(HEX_LAYOUT.size.x as f32);
+    for tile_pos in std::mem::take(&mut tile_cache.dirty_positions) {
+        let exists = tile_cache.tiles.contains_key(&tile_pos);
+        match (exists, state.tiles.get(&tile_pos)) {
+            (true, None) => {
+                let entity = spawn_shape(
+                    &mut commands,
+                    &shape,
+                    TILE_COLOR,
+                    Transform::from_translation(tile_center(tile_pos).extend(TILE_Z)),
+                );
+                commands
+                    .entity(entity)
+                    .insert(tile_visibility(&fog, tile_pos));
+                state.tiles.insert(tile_pos, entity);
+            }
+            (false, Some(entity)) => {
+                commands.entity(*entity).despawn();
+                state.tiles.remove(&tile_pos);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// markers for players and droids are children of the respective entity, so they move along
+fn minimap_marker_system(
+    mut commands: Commands,
+    player_query: Query<(Entity, &PlayerIndex), Added<PlayerMarker>>,
+    droid_query: Query<Entity, Added<DroidMarker>>,
+    orphan_query: Query<Entity, (With<DroidMinimapMarker>, Without<Parent>)>,
+) {
+    // a takeover clears the children of the droid
+    for entity in &orphan_query {
+        commands.entity(entity).despawn();
+    }
+    for (entity, player) in &player_query {
+        let color = PLAYER_COLORS[player.0 % PLAYER_COLORS.len()];
+        let marker = spawn_shape(
+            &mut commands,
+            &shapes::Circle {
+                radius: 80.0,
+                ..default()
+            },
+            color,
+            Transform::from_xyz(0.0, 0.0, PLAYER_Z),
+        );
+        commands.entity(entity).add_child(marker);
+    }
+    for entity in &droid_query {
+        let marker = spawn_shape(
+            &mut commands,
+            &shapes::Circle {
+                radius: 50.0,
+                ..default()
+            },
+            DROID_COLOR,
+            Transform::from_xyz(0.0, 0.0, DROID_Z),
+        );
+        commands
+            .entity(marker)
+            .insert(DroidMinimapMarker)
+            .insert(Visibility::Hidden);
+        commands.entity(entity).add_child(marker);
+    }
+}
+
+fn minimap_portal_system(
+    mut commands: Commands,
+    mut state: ResMut<MinimapState>,
+    portal_query: Query<(Entity, &Portal), Added<Portal>>,
+    mut removed_portals: RemovedComponents<Portal>,
+) {
+    for (entity, portal) in &portal_query {
+        let marker = spawn_shape(
+            &mut commands,
+            &hexagon(HEX_LAYOUT.size.x as f32 * 0.8),
+            PORTAL_COLOR,
+            Transform::from_translation(tile_center(portal.tile_pos).extend(PORTAL_Z)),
+        );
+        state.portals.insert(entity, marker);
+    }
+    for entity in removed_portals.read() {
+        if let Some(marker) = state.portals.remove(&entity) {
+            commands.entity(marker).despawn();
+        }
+    }
+}
+
+fn minimap_droid_visibility_system(
+    settings: Res<MinimapSettings>,
+    fog: Res<FogOfWar>,
+    mut marker_query: Query<(&GlobalTransform, &mut Visibility), With<DroidMinimapMarker>>,
+) {
+    for (transform, mut visibility) in &mut marker_query {
+        let seen = !settings.only_seen_droids || fog.is_visible_at(transform.translation().xy());
+        // the droid itself may be hidden by the fog, the marker does not inherit that
+        let new_visibility = if seen {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != new_visibility {
+            *visibility = new_visibility;
+        }
+    }
+}
+
+fn minimap_toggle_system(action_input: ActionInput, mut settings: ResMut<MinimapSettings>) {
+    if action_input.just_pressed(InputAction::ToggleMinimap) {
+        settings.enabled = !settings.enabled;
+    }
+}
+
+/// place the minimap in the top right corner and center it on the camera targets
+fn minimap_camera_system(
+    settings: Res<MinimapSettings>,
+    game_state: Res<State<GameState>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    target_query: Query<&GlobalTransform, With<CameraTarget>>,
+    mut camera_query: Query<(&mut Camera, &mut Transform), With<MinimapCamera>>,
+    mut background_query: Query<&mut Transform, (With<MinimapBackground>, Without<MinimapCamera>)>,
+) {
+    let (Ok(window), Ok((mut camera, mut transform))) =
+        (window_query.get_single(), camera_query.get_single_mut())
+    else {
+        return;
+    };
+    let active = settings.enabled && *game_state.get() != GameState::None;
+    if camera.is_active != active {
+        camera.is_active = active;
+    }
+    if !active {
+        return;
+    }
+    let scale_factor = window.scale_factor() as f32;
+    let size = settings.size.min(window.width().min(window.height()) * 0.5);
+    let physical_size = (size * scale_factor) as u32;
+    let margin = (10.0 * scale_factor) as u32;
+    let position = UVec2::new(
+        window
+            .physical_width()
+            .saturating_sub(physical_size + margin),
+        margin,
+    );
+    set_viewport(&mut camera, Some((position, UVec2::splat(physical_size))));
+
+    let (sum, count) = target_query
+        .iter()
+        .fold((Vec2::ZERO, 0), |(sum, count), target| {
+            (sum + target.translation().xy(), count + 1)
+        });
+    if count > 0 {
+        let center = sum / count as f32;
+        transform.translation = center.extend(transform.translation.z);
+    }
+    let scale = settings.range / size.max(1.0);
+    transform.scale = Vec3::new(scale, scale, 1.0);
+
+    // the background covers the whole viewport (in camera space, i.e. before scaling)
+    for mut background_transform in &mut background_query {
+        background_transform.scale = Vec3::new(size * 1.5, size * 1.5, 1.0);
+    }
+}
+
+pub struct MinimapPlugin;
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MinimapSettings>()
+            .init_resource::<MinimapState>()
+            .add_systems(Startup, setup_minimap_system)
+            .add_systems(
+                Update,
+                (
+                    minimap_toggle_system,
+                    minimap_tiles_system,
+                    minimap_marker_system,
+                    minimap_portal_system,
+                    minimap_droid_visibility_system,
+                    minimap_camera_system,
+                ),
+            );
+    }
+}
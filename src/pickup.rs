@@ -0,0 +1,300 @@
+// collectible pickups: weapons, energy, repair and temporary buffs
+
+use bevy::prelude::*;
+use bevy_prototype_lyon::{prelude::*, shapes};
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collision_groups,
+    droid::{DroidHealth, WeaponState, RELOAD_TIMEOUT},
+    game::GameMarker,
+    hex_point_to_vec2,
+    particle::ColorGenerator,
+    player::PlayerMarker,
+    prelude::*,
+    weapon::{WeaponEnergy, WeaponKind},
+    HEX_LAYOUT,
+};
+use hexagon_tiles::layout::LayoutTool;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PickupKind {
+    Weapon(WeaponKind),
+    Energy(f32),
+    Repair(f32),
+    // buffs, value is the duration in seconds
+    RapidFire(f32),
+    Shield(f32),
+}
+
+impl PickupKind {
+    pub fn color(&self) -> Color {
+        match self {
+            PickupKind::Weapon(_) => YELLOW_HDR,
+            PickupKind::Energy(_) => BLUE_HDR,
+            PickupKind::Repair(_) => GREEN_HDR,
+            PickupKind::RapidFire(_) => RED_HDR,
+            PickupKind::Shield(_) => COLORS[7],
+        }
+    }
+    fn sides(&self) -> usize {
+        match self {
+            PickupKind::Weapon(_) => 3,
+            PickupKind::Energy(_) | PickupKind::Repair(_) => 4,
+            PickupKind::RapidFire(_) | PickupKind::Shield(_) => 5,
+        }
+    }
+    // random pickup, e.g. dropped by destroyed droids
+    pub fn random(rng: &mut impl Rng) -> Self {
+        match rng.gen_range(0..6) {
+            0 => PickupKind::Weapon(WeaponKind::Kinetic),
+            1 => PickupKind::Weapon(WeaponKind::Wave),
+            2 => PickupKind::Energy(50.0),
+            3 => PickupKind::Repair(40.0),
+            4 => PickupKind::RapidFire(8.0),
+            _ => PickupKind::Shield(8.0),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Pickup {
+    pub kind: PickupKind,
+}
+
+/// spawn request for a pickup on a tile (e.g. from level data)
+#[derive(Component)]
+pub struct PickupSpawn {
+    pub tile_pos: TilePos,
+    pub kind: PickupKind,
+}
+
+/// timed buffs granted by pickups (remaining duration in seconds)
+#[derive(Component, Default)]
+pub struct ActiveBuffs {
+    pub rapid_fire: f32,
+    pub shield: f32,
+}
+
+pub const RAPID_FIRE_FACTOR: f32 = 0.3;
+pub const SHIELD_AMOUNT: f32 = 50.0;
+pub const DROP_CHANCE: f64 = 0.3;
+
+pub fn spawn_pickup(commands: &mut Commands, kind: PickupKind, translation: Vec3) -> Entity {
+    let shape = shapes::RegularPolygon {
+        sides: kind.sides(),
+        feature: shapes::RegularPolygonFeature::Radius(14.0),
+        ..default()
+    };
+    commands
+        .spawn(ShapeBundle {
+            path: GeometryBuilder::build_as(&shape),
+            spatial: SpatialBundle::from_transform(Transform::from_translation(translation)),
+            ..default()
+        })
+        .insert(default_stroke(kind.color()))
+        .insert((
+            Collider::ball(16.0),
+            Sensor,
+            ActiveEvents::COLLISION_EVENTS,
+            // the pickup has no rigid body, events with the kinematic hexton need to be enabled
+            ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_STATIC,
+            CollisionGroups::new(collision_groups::PICKUPS, collision_groups::DROIDS),
+        ))
+        .insert(Pickup { kind })
+        .insert(Name::new("pickup"))
+        .insert(GameMarker)
+        .id()
+}
+
+fn pickup_spawn_system(mut commands: Commands, query: Query<(Entity, &PickupSpawn)>) {
+    for (entity, pickup_spawn) in &query {
+        let center = hex_point_to_vec2(LayoutTool::hex_to_pixel(
+            HEX_LAYOUT,
+            pickup_spawn.tile_pos.0,
+        ));
+        spawn_pickup(&mut commands, pickup_spawn.kind, center.extend(0.0));
+        commands.entity(entity).despawn();
+    }
+}
+
+fn pickup_animate_system(time: Res<Time>, mut query: Query<&mut Transform, With<Pickup>>) {
+    for mut transform in &mut query {
+        transform.rotate_z(time.delta_seconds());
+        transform.scale = Vec3::splat(1.0 + 0.15 * (time.elapsed_seconds() * 4.0).sin());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn pickup_collect_system(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    pickup_query: Query<(&Pickup, &Transform)>,
+    children_query: Query<&Children>,
+    player_query: Query<(), With<PlayerMarker>>,
+    mut weapon_query: Query<(&mut WeaponState, &mut WeaponEnergy)>,
+    mut health_query: Query<&mut DroidHealth>,
+    mut buffs_query: Query<&mut ActiveBuffs>,
+) {
+    for collision_event in collision_events.read() {
+        let CollisionEvent::Started(a, b, _) = collision_event else {
+            continue;
+        };
+        let (pickup_entity, collector) = if pickup_query.contains(*a) {
+            (*a, *b)
+        } else if pickup_query.contains(*b) {
+            (*b, *a)
+        } else {
+            continue;
+        };
+        // only vessels controlled by a player can collect pickups
+        let Ok(children) = children_query.get(collector) else {
+            continue;
+        };
+        if !children.iter().any(|child| player_query.contains(*child)) {
+            continue;
+        }
+        let Ok((pickup, transform)) = pickup_query.get(pickup_entity) else {
+            continue;
+        };
+
+        match pickup.kind {
+            PickupKind::Weapon(kind) => {
+                if let Ok((mut weapon_state, _)) = weapon_query.get_mut(collector) {
+                    weapon_state.kind = kind;
+                }
+            }
+            PickupKind::Energy(amount) => {
+                if let Ok((_, mut weapon_energy)) = weapon_query.get_mut(collector) {
+                    weapon_energy.energy =
+                        (weapon_energy.energy + amount).min(weapon_energy.max_energy);
+                }
+            }
+            PickupKind::Repair(amount) => {
+                if let Ok(mut health) = health_query.get_mut(collector) {
+                    health.hitpoints = (health.hitpoints + amount).min(health.max_hitpoints);
+                }
+            }
+            PickupKind::RapidFire(duration) => {
+                if let Ok(mut buffs) = buffs_query.get_mut(collector) {
+                    buffs.rapid_fire = buffs.rapid_fire.max(duration);
+                } else {
+                    commands.entity(collector).insert(ActiveBuffs {
+                        rapid_fire: duration,
+                        ..default()
+                    });
+                }
+            }
+            PickupKind::Shield(duration) => {
+                if let Ok(mut buffs) = buffs_query.get_mut(collector) {
+                    buffs.shield = buffs.shield.max(duration);
+                } else {
+                    commands.entity(collector).insert(ActiveBuffs {
+                        shield: duration,
+                        ..default()
+                    });
+                }
+                if let Ok(mut health) = health_query.get_mut(collector) {
+                    health.shield = SHIELD_AMOUNT;
+                }
+            }
+        }
+        info!("pickup {:?} collected by {:?}", pickup.kind, collector);
+
+        commands
+            .spawn(SpatialBundle::from_transform(*transform))
+            .insert(ParticleSource {
+                rate: 100,
+                direction: ParticleDirection::Uniform,
+                speed_distr: Normal::new(150.0, 30.0).unwrap(),
+                lifetime_distr: Normal::new(0.3, 0.1).unwrap(),
+                velocity_offset: Vec2::default(),
+                damping: default(),
+                initial_offset: 0.0,
+                color_generator: ColorGenerator::Random,
+            })
+            .insert(GameDespawn::frames_to_live(1));
+        commands.entity(pickup_entity).despawn_recursive();
+    }
+}
+
+fn buff_system(
+    time: Res<Time>,
+    mut query: Query<(
+        &mut ActiveBuffs,
+        Option<&mut WeaponState>,
+        Option<&mut DroidHealth>,
+    )>,
+) {
+    for (mut buffs, weapon_state, health) in &mut query {
+        buffs.rapid_fire = (buffs.rapid_fire - time.delta_seconds()).max(0.0);
+        buffs.shield = (buffs.shield - time.delta_seconds()).max(0.0);
+
+        if let Some(mut weapon_state) = weapon_state {
+            weapon_state.reload_time = if buffs.rapid_fire > 0.0 {
+                RELOAD_TIMEOUT * RAPID_FIRE_FACTOR
+            } else {
+                RELOAD_TIMEOUT
+            };
+        }
+        if let Some(mut health) = health {
+            if buffs.shield <= 0.0 && health.shield > 0.0 {
+                health.shield = 0.0;
+            }
+        }
+    }
+}
+
+pub struct PickupPlugin;
+impl Plugin for PickupPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (pickup_collect_system, pickup_animate_system, buff_system)
+                .run_if(in_state(GameState::Game)),
+        )
+        .add_systems(PostUpdate, pickup_spawn_system);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::CommandQueue;
+
+    use super::*;
+    use crate::archetype::PlayerArchetype;
+
+    #[test]
+    fn hexton_collects_pickup() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            HierarchyPlugin,
+            RapierPhysicsPlugin::<NoUserData>::default(),
+        ))
+        .add_systems(Update, pickup_collect_system);
+
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &app.world);
+        let hexton = PlayerArchetype::Hexton.spawn(&mut commands, false, Vec3::ZERO);
+        let player = commands.spawn(PlayerMarker).id();
+        commands.entity(hexton).add_child(player);
+        let pickup = spawn_pickup(
+            &mut commands,
+            PickupKind::RapidFire(5.0),
+            Vec3::new(5.0, 0.0, 0.0),
+        );
+        queue.apply(&mut app.world);
+
+        for _ in 0..5 {
+            app.update();
+        }
+        assert!(app.world.get_entity(pickup).is_none());
+        let buffs = app.world.get::<ActiveBuffs>(hexton).unwrap();
+        assert_eq!(buffs.rapid_fire, 5.0);
+    }
+}
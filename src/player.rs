@@ -1,9 +1,28 @@
-use crate::{prelude::*, ship::ShipMarker};
+use crate::{droid::DroidDestroyed, prelude::*, ship::ShipMarker};
 use bevy::prelude::*;
 
+pub const MAX_PLAYERS: usize = 2;
+
 #[derive(Component)]
 pub struct PlayerMarker;
 
+/// index of a local player. Selects the binding set, the gamepad and the HUD of the player.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PlayerIndex(pub usize);
+
+impl PlayerIndex {
+    pub const ALL: [PlayerIndex; MAX_PLAYERS] = [PlayerIndex(0), PlayerIndex(1)];
+
+    pub fn name(&self) -> String {
+        format!("P{}", self.0 + 1)
+    }
+}
+
+#[derive(Component, Default)]
+pub struct PlayerScore {
+    pub kills: u32,
+}
+
 #[derive(Event)]
 pub struct PlayerTakeover {
     pub player: Entity,
@@ -41,7 +60,36 @@ fn player_takeover_system(
             }
             commands.entity(*child).despawn_recursive();
         }
-        commands.entity(*target).clear_children().add_child(*player);
+        commands
+            .entity(*target)
+            .clear_children()
+            .add_child(*player)
+            .insert(Team::Player);
+    }
+}
+
+/// credit destroyed droids to the player controlling the vessel that destroyed them
+fn player_score_system(
+    mut events: EventReader<DroidDestroyed>,
+    children_query: Query<&Children>,
+    mut score_query: Query<(&PlayerIndex, &mut PlayerScore)>,
+) {
+    for event in events.read() {
+        let Some(destroyed_by) = event.destroyed_by else {
+            continue;
+        };
+        if destroyed_by == event.droid {
+            continue;
+        }
+        let Ok(children) = children_query.get(destroyed_by) else {
+            continue;
+        };
+        for child in children.iter() {
+            if let Ok((player_index, mut score)) = score_query.get_mut(*child) {
+                score.kills += 1;
+                info!("{} kills: {}", player_index.name(), score.kills);
+            }
+        }
     }
 }
 
@@ -49,6 +97,7 @@ pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<PlayerTakeover>()
-            .add_systems(PostUpdate, player_takeover_system);
+            .add_systems(PostUpdate, player_takeover_system)
+            .add_systems(Update, player_score_system);
     }
 }
@@ -0,0 +1,417 @@
+//! recording of the per-frame player input and deterministic replay. Recording and replay
+//! both run with a fixed timestep (see fixed_timestep_mode/fixed_time_update) and a seeded
+//! GameRng, so a replayed run comes out identical to the recorded one.
+
+use std::{
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{app::AppExit, prelude::*, time::TimeUpdateStrategy};
+use bevy_rapier2d::prelude::*;
+use hexagon_tiles::hexagon::Hex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archetype::PlayerArchetype,
+    droid::TargetDirection,
+    game::GameSpawnInfo,
+    hexton::HextonInput,
+    input::{AimInput, InputSet, InputTarget, LevelRotation},
+    menu::MenuState,
+    player::PlayerIndex,
+    portal::PortalToggleRequest,
+    prelude::*,
+    rng::GameSeed,
+    ship::{FlightAssist, FlightAssistMode},
+};
+
+pub const REPLAY_TIMESTEP: f32 = 1.0 / 60.0;
+
+/// input of one player in one frame, as written into the input components
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlayerFrameInput {
+    #[serde(default)]
+    pub direction: Option<[f32; 2]>,
+    // rot, thrust, brake
+    #[serde(default)]
+    pub ship: Option<[f32; 3]>,
+    // forward, jump
+    #[serde(default)]
+    pub hexton: Option<(f32, bool)>,
+    #[serde(default)]
+    pub flight_assist: Option<FlightAssistMode>,
+    #[serde(default)]
+    pub aim: Option<[f32; 2]>,
+    #[serde(default)]
+    pub primary_attack: bool,
+    #[serde(default)]
+    pub secondary_attack: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FrameInput {
+    // indexed by PlayerIndex
+    pub players: Vec<PlayerFrameInput>,
+    // q, r of toggled tiles
+    #[serde(default)]
+    pub portal_toggles: Vec<[i32; 2]>,
+    // the camera rotation also rotates gravity
+    pub gravity: [f32; 2],
+    // LevelRotation::angle, which turns the hexton's up
+    pub level_rotation: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Recording {
+    pub seed: u64,
+    pub player: PlayerArchetype,
+    pub gravity: bool,
+    pub spawn_enemy_droids: u32,
+    pub players: usize,
+    pub frames: Vec<FrameInput>,
+}
+
+impl Recording {
+    pub fn new(seed: u64, spawn_info: &GameSpawnInfo) -> Self {
+        Self {
+            seed,
+            player: spawn_info.player,
+            gravity: spawn_info.gravity,
+            spawn_enemy_droids: spawn_info.spawn_enemy_droids,
+            players: spawn_info.players,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|err| err.to_string())?;
+        serde_yaml::from_reader(file).map_err(|err| err.to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = std::fs::File::create(path).map_err(|err| err.to_string())?;
+        serde_yaml::to_writer(BufWriter::new(file), self).map_err(|err| err.to_string())
+    }
+
+    pub fn spawn_info(&self) -> GameSpawnInfo {
+        GameSpawnInfo {
+            player: self.player,
+            spawn_enemy_droids: self.spawn_enemy_droids,
+            gravity: self.gravity,
+            players: self.players,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    Record {
+        path: PathBuf,
+        recording: Recording,
+    },
+    Replay {
+        recording: Recording,
+        frame: usize,
+    },
+}
+
+impl ReplayMode {
+    pub fn is_replaying(&self) -> bool {
+        matches!(self, ReplayMode::Replay { .. })
+    }
+}
+
+pub fn fixed_timestep_mode() -> TimestepMode {
+    TimestepMode::Fixed {
+        dt: REPLAY_TIMESTEP,
+        substeps: 1,
+    }
+}
+
+/// advance game time by REPLAY_TIMESTEP per frame, independent of the wall clock
+pub fn fixed_time_update() -> TimeUpdateStrategy {
+    TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(REPLAY_TIMESTEP))
+}
+
+fn replay_start_system(
+    mut replay_mode: ResMut<ReplayMode>,
+    seed: Res<GameSeed>,
+    spawn_info: Res<GameSpawnInfo>,
+) {
+    match &mut *replay_mode {
+        ReplayMode::Off => (),
+        ReplayMode::Record { recording, .. } => {
+            // a new game (e.g. started from the menu) starts a new recording
+            *recording = Recording::new(seed.0, &spawn_info);
+        }
+        ReplayMode::Replay { frame, .. } => *frame = 0,
+    }
+}
+
+fn replay_autostart_system(
+    replay_mode: Res<ReplayMode>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut menu_state: ResMut<NextState<MenuState>>,
+) {
+    if replay_mode.is_replaying() {
+        game_state.set(GameState::Game);
+        menu_state.set(MenuState::Disabled);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn record_system(
+    mut replay_mode: ResMut<ReplayMode>,
+    rapier_config: Res<RapierConfiguration>,
+    level_rotation: Res<LevelRotation>,
+    player_query: Query<(&Parent, &PlayerIndex, &AimInput), With<InputTarget>>,
+    input_query: Query<(
+        Option<&TargetDirection>,
+        Option<&ShipInput>,
+        Option<&HextonInput>,
+        Option<&FlightAssist>,
+        Option<&AttackRequest>,
+    )>,
+    portal_query: Query<&TilePos, Added<PortalToggleRequest>>,
+) {
+    let ReplayMode::Record { recording, .. } = &mut *replay_mode else {
+        return;
+    };
+    let mut frame = FrameInput {
+        gravity: rapier_config.gravity.into(),
+        level_rotation: level_rotation.angle,
+        portal_toggles: portal_query
+            .iter()
+            .map(|tile_pos| [tile_pos.0.q(), tile_pos.0.r()])
+            .collect(),
+        ..default()
+    };
+    for (parent, player, aim_input) in &player_query {
+        if frame.players.len() <= player.0 {
+            frame.players.resize_with(player.0 + 1, default);
+        }
+        let player_frame = &mut frame.players[player.0];
+        player_frame.aim = aim_input.direction.map(Into::into);
+        let Ok((target_direction, ship_input, hexton_input, flight_assist, attack_request)) =
+            input_query.get(parent.get())
+        else {
+            continue;
+        };
+        player_frame.direction = target_direction.map(|input| input.direction.into());
+        player_frame.ship = ship_input.map(|input| [input.rot, input.thrust, input.brake]);
+        player_frame.hexton = hexton_input.map(|input| (input.forward, input.jump));
+        player_frame.flight_assist = flight_assist.map(|flight_assist| flight_assist.mode);
+        if let Some(attack_request) = attack_request {
+            player_frame.primary_attack = attack_request.primary_attack;
+            player_frame.secondary_attack = attack_request.secondary_attack;
+        }
+    }
+    recording.frames.push(frame);
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn replay_system(
+    mut commands: Commands,
+    mut replay_mode: ResMut<ReplayMode>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    mut level_rotation: ResMut<LevelRotation>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut menu_state: ResMut<NextState<MenuState>>,
+    mut player_query: Query<(&Parent, &PlayerIndex, &mut AimInput), With<InputTarget>>,
+    mut input_query: Query<(
+        Option<&mut TargetDirection>,
+        Option<&mut ShipInput>,
+        Option<&mut HextonInput>,
+        Option<&mut FlightAssist>,
+        Option<&mut AttackRequest>,
+    )>,
+) {
+    let ReplayMode::Replay { recording, frame } = &mut *replay_mode else {
+        return;
+    };
+    let Some(input) = recording.frames.get(*frame) else {
+        if *frame == recording.frames.len() {
+            info!("replay finished after {} frames", frame);
+            *frame += 1;
+            game_state.set(GameState::Paused);
+            menu_state.set(MenuState::Pause);
+        }
+        return;
+    };
+    *frame += 1;
+
+    rapier_config.gravity = input.gravity.into();
+    level_rotation.angle = input.level_rotation;
+    level_rotation.target = input.level_rotation;
+    for [q, r] in &input.portal_toggles {
+        commands.spawn((TilePos(Hex::new(*q, *r)), PortalToggleRequest::default()));
+    }
+    for (parent, player, mut aim_input) in &mut player_query {
+        let Some(player_input) = input.players.get(player.0) else {
+            continue;
+        };
+        aim_input.direction = player_input.aim.map(Vec2::from);
+        let Ok((target_direction, ship_input, hexton_input, flight_assist, attack_request)) =
+            input_query.get_mut(parent.get())
+        else {
+            continue;
+        };
+        if let (Some(mut target_direction), Some(direction)) =
+            (target_direction, player_input.direction)
+        {
+            target_direction.direction = direction.into();
+        }
+        if let (Some(mut ship_input), Some([rot, thrust, brake])) = (ship_input, player_input.ship)
+        {
+            ship_input.rot = rot;
+            ship_input.thrust = thrust;
+            ship_input.brake = brake;
+        }
+        if let (Some(mut hexton_input), Some((forward, jump))) = (hexton_input, player_input.hexton)
+        {
+            hexton_input.forward = forward;
+            hexton_input.jump = jump;
+        }
+        if let (Some(mut flight_assist), Some(mode)) = (flight_assist, player_input.flight_assist) {
+            if flight_assist.mode != mode {
+                flight_assist.mode = mode;
+                flight_assist.target_heading = None;
+            }
+        }
+        if let Some(mut attack_request) = attack_request {
+            attack_request.primary_attack = player_input.primary_attack;
+            attack_request.secondary_attack = player_input.secondary_attack;
+        }
+    }
+}
+
+fn save_recording(replay_mode: &ReplayMode) {
+    let ReplayMode::Record { path, recording } = replay_mode else {
+        return;
+    };
+    match recording.save(path) {
+        Ok(()) => info!(
+            "saved {} frames to {}",
+            recording.frames.len(),
+            path.display()
+        ),
+        Err(err) => warn!("failed to save recording {}: {}", path.display(), err),
+    }
+}
+
+fn record_save_system(replay_mode: Res<ReplayMode>) {
+    save_recording(&replay_mode);
+}
+
+fn record_save_on_exit_system(
+    mut app_exit_events: EventReader<AppExit>,
+    replay_mode: Res<ReplayMode>,
+) {
+    if app_exit_events.read().count() > 0 {
+        save_recording(&replay_mode);
+    }
+}
+
+fn live_input(replay_mode: Res<ReplayMode>) -> bool {
+    !replay_mode.is_replaying()
+}
+
+pub struct ReplayPlugin;
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayMode>()
+            // replayed input replaces the live input
+            .configure_sets(Update, InputSet::Read.run_if(live_input))
+            .add_systems(Startup, replay_autostart_system)
+            .add_systems(OnExit(GameState::None), replay_start_system)
+            .add_systems(OnExit(GameState::Game), record_save_system)
+            .add_systems(
+                Update,
+                (replay_system, record_system)
+                    .chain()
+                    .after(InputSet::Read)
+                    .before(InputSet::Apply)
+                    .run_if(in_state(GameState::Game)),
+            )
+            .add_systems(Last, record_save_on_exit_system);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_recording() -> Recording {
+        Recording {
+            seed: 1234,
+            player: PlayerArchetype::Ship,
+            gravity: true,
+            spawn_enemy_droids: 1,
+            players: 2,
+            frames: vec![
+                FrameInput {
+                    players: vec![
+                        PlayerFrameInput {
+                            ship: Some([0.5, 1.0, 0.0]),
+                            flight_assist: Some(FlightAssistMode::VelocityHold),
+                            aim: Some([0.0, 1.0]),
+                            primary_attack: true,
+                            ..default()
+                        },
+                        PlayerFrameInput {
+                            direction: Some([-1.0, 0.25]),
+                            hexton: Some((0.75, true)),
+                            secondary_attack: true,
+                            ..default()
+                        },
+                    ],
+                    portal_toggles: vec![[5, -1], [-3, 2]],
+                    gravity: [0.0, -98.1],
+                    level_rotation: std::f32::consts::FRAC_PI_3,
+                },
+                FrameInput::default(),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let recording = test_recording();
+        let path = std::env::temp_dir().join(format!(
+            "hexadroid-replay-round-trip-{}.yaml",
+            std::process::id()
+        ));
+        recording.save(&path).unwrap();
+        let loaded = Recording::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(
+            serde_yaml::to_string(&loaded).unwrap(),
+            serde_yaml::to_string(&recording).unwrap()
+        );
+        assert_eq!(loaded.seed, 1234);
+        assert_eq!(loaded.frames.len(), 2);
+        let player = &loaded.frames[0].players[1];
+        assert_eq!(player.direction, Some([-1.0, 0.25]));
+        assert_eq!(player.hexton, Some((0.75, true)));
+        assert_eq!(loaded.frames[0].portal_toggles, vec![[5, -1], [-3, 2]]);
+        assert_eq!(loaded.frames[0].level_rotation, std::f32::consts::FRAC_PI_3);
+    }
+
+    #[test]
+    fn spawn_info_round_trip() {
+        let recording = test_recording();
+        let spawn_info = recording.spawn_info();
+        let restarted = Recording::new(recording.seed, &spawn_info);
+        assert_eq!(restarted.player, PlayerArchetype::Ship);
+        assert!(restarted.gravity);
+        assert_eq!(restarted.spawn_enemy_droids, 1);
+        assert_eq!(restarted.players, 2);
+        assert!(restarted.frames.is_empty());
+    }
+}
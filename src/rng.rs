@@ -0,0 +1,42 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::prelude::*;
+
+/// random source for everything that influences the simulation. Seeded, so that replays
+/// come out identical. Systems drawing from it have to be ordered against each other, the
+/// order of unordered systems may change between runs. Purely cosmetic randomness (particles)
+/// may keep using thread_rng.
+#[derive(Resource, Deref, DerefMut)]
+pub struct GameRng(pub StdRng);
+
+impl GameRng {
+    pub fn from_seed(seed: u64) -> Self {
+        Self(StdRng::seed_from_u64(seed))
+    }
+}
+
+/// seed of the current run. GameRng is reset to it whenever a game starts.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct GameSeed(pub u64);
+
+impl Default for GameSeed {
+    fn default() -> Self {
+        Self(rand::thread_rng().gen())
+    }
+}
+
+fn reset_rng_system(seed: Res<GameSeed>, mut rng: ResMut<GameRng>) {
+    info!("game seed: {}", seed.0);
+    *rng = GameRng::from_seed(seed.0);
+}
+
+pub struct RngPlugin;
+impl Plugin for RngPlugin {
+    fn build(&self, app: &mut App) {
+        // GameRng is (re-)seeded from GameSeed on game start
+        app.init_resource::<GameSeed>()
+            .insert_resource(GameRng::from_seed(0))
+            .add_systems(OnExit(GameState::None), reset_rng_system);
+    }
+}
@@ -0,0 +1,663 @@
+//! save and load of a running game. The world (tiles, droids, players with their weapons and
+//! buffs, pickups, turrets, mines, portals, the explored area and the level) is written as yaml
+//! next to the settings. Loading
+//! restarts the game with the saved state instead of the default setup, see `game_setup`.
+//! Triggered from the pause menu or with the quicksave / quickload keys.
+
+use std::time::Duration;
+
+use bevy::{
+    math::Vec3Swizzles,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_rapier2d::prelude::{RapierConfiguration, Velocity};
+use hexagon_tiles::hexagon::Hex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    action::{ActionInput, InputAction},
+    archetype::{PlayerArchetype, VesselArchetype},
+    deployable::{spawn_mine, spawn_turret, Deployer, ProximityMine, TurretMarker},
+    droid::{DroidClass, DroidHealth, DroidMarker, WeaponState},
+    fog::{ExploredTiles, FogOfWar},
+    game::{spawn_droid_ai, spawn_hostile_droid, spawn_player, GameMarker, GameSpawnInfo},
+    level::Level,
+    menu::MenuState,
+    pickup::{spawn_pickup, ActiveBuffs, Pickup, PickupKind},
+    player::{PlayerIndex, PlayerScore},
+    portal::Portal,
+    prelude::*,
+    settings::{read_config, write_config},
+    state::AutoStart,
+    weapon::{RechargeTile, WeaponEnergy, WeaponKind},
+};
+
+pub const SAVEGAME_FILE: &str = "savegame.yaml";
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SavedBody {
+    pub position: [f32; 2],
+    // rotation around z in radians
+    pub rotation: f32,
+    pub velocity: [f32; 2],
+    pub angular_velocity: f32,
+}
+
+impl SavedBody {
+    fn new(transform: &Transform, velocity: Option<&Velocity>) -> Self {
+        let velocity = velocity.copied().unwrap_or_default();
+        Self {
+            position: transform.translation.xy().into(),
+            rotation: transform.rotation.to_euler(EulerRot::XYZ).2,
+            velocity: velocity.linvel.into(),
+            angular_velocity: velocity.angvel,
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        Transform::from_translation(Vec2::from(self.position).extend(0.0))
+            .with_rotation(Quat::from_rotation_z(self.rotation))
+    }
+
+    fn velocity(&self) -> Velocity {
+        Velocity {
+            linvel: self.velocity.into(),
+            angvel: self.angular_velocity,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SavedHealth {
+    pub emp_load: f32,
+    pub hitpoints: f32,
+    pub max_hitpoints: f32,
+    pub shield: f32,
+}
+
+impl From<&DroidHealth> for SavedHealth {
+    fn from(health: &DroidHealth) -> Self {
+        Self {
+            emp_load: health.emp_load,
+            hitpoints: health.hitpoints,
+            max_hitpoints: health.max_hitpoints,
+            shield: health.shield,
+        }
+    }
+}
+
+impl From<SavedHealth> for DroidHealth {
+    fn from(health: SavedHealth) -> Self {
+        Self {
+            emp_load: health.emp_load,
+            hitpoints: health.hitpoints,
+            max_hitpoints: health.max_hitpoints,
+            shield: health.shield,
+            last_hit_by: None,
+        }
+    }
+}
+
+/// droid that is not the vessel a player started with, i.e. hostile or taken over
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedDroid {
+    pub class: DroidClass,
+    pub team: Team,
+    pub body: SavedBody,
+    pub health: SavedHealth,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SavedVessel {
+    // the vessel the player started with. Health is None for vessels without DroidHealth.
+    Archetype {
+        archetype: PlayerArchetype,
+        body: SavedBody,
+        health: Option<SavedHealth>,
+    },
+    // a taken over droid, index into SaveGame::droids
+    Droid(usize),
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SavedWeapon {
+    pub kind: WeaponKind,
+    pub energy: f32,
+    pub heat: f32,
+    pub jammed: bool,
+}
+
+/// weapon, buffs and deployables of a player's vessel
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SavedLoadout {
+    // None for vessels without a weapon (the hexton)
+    pub weapon: Option<SavedWeapon>,
+    // remaining seconds of the pickup buffs
+    pub rapid_fire: f32,
+    pub shield: f32,
+    // None for vessels without a Deployer
+    pub deployables: Option<u32>,
+}
+
+impl SavedLoadout {
+    fn new(
+        weapon_state: Option<&WeaponState>,
+        weapon_energy: Option<&WeaponEnergy>,
+        buffs: Option<&ActiveBuffs>,
+        deployer: Option<&Deployer>,
+    ) -> Self {
+        Self {
+            weapon: weapon_state
+                .zip(weapon_energy)
+                .map(|(weapon_state, weapon_energy)| SavedWeapon {
+                    kind: weapon_state.kind,
+                    energy: weapon_energy.energy,
+                    heat: weapon_energy.heat,
+                    jammed: weapon_energy.jammed,
+                }),
+            rapid_fire: buffs.map_or(0.0, |buffs| buffs.rapid_fire),
+            shield: buffs.map_or(0.0, |buffs| buffs.shield),
+            deployables: deployer.map(|deployer| deployer.count),
+        }
+    }
+
+    // applied to the spawned vessel, whose weapon components depend on the archetype / class
+    fn restore(self, entity: Entity, world: &mut World) {
+        let Some(mut entity) = world.get_entity_mut(entity) else {
+            return;
+        };
+        if let Some(weapon) = self.weapon {
+            if let Some(mut weapon_state) = entity.get_mut::<WeaponState>() {
+                weapon_state.kind = weapon.kind;
+            }
+            if let Some(mut weapon_energy) = entity.get_mut::<WeaponEnergy>() {
+                weapon_energy.energy = weapon.energy;
+                weapon_energy.heat = weapon.heat;
+                weapon_energy.jammed = weapon.jammed;
+            }
+        }
+        if let (Some(count), Some(mut deployer)) = (self.deployables, entity.get_mut::<Deployer>())
+        {
+            deployer.count = count;
+        }
+        if self.rapid_fire > 0.0 || self.shield > 0.0 {
+            entity.insert(ActiveBuffs {
+                rapid_fire: self.rapid_fire,
+                shield: self.shield,
+            });
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedPlayer {
+    pub index: usize,
+    pub kills: u32,
+    pub vessel: SavedVessel,
+    pub loadout: SavedLoadout,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedPickup {
+    pub kind: PickupKind,
+    pub position: [f32; 2],
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedTurret {
+    pub team: Team,
+    pub position: [f32; 2],
+    pub health: SavedHealth,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedMine {
+    pub team: Team,
+    pub position: [f32; 2],
+    pub arm_timeout: f32,
+    // index of the player whose vessel deployed the mine
+    pub owner: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedPortal {
+    pub tile: [i32; 2],
+    pub period: f32,
+    pub elapsed: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub player: PlayerArchetype,
+    pub players: usize,
+    pub gravity: bool,
+    pub spawn_enemy_droids: u32,
+    pub level: u32,
+    // wall tiles (q, r)
+    pub tiles: Vec<[i32; 2]>,
+    pub droids: Vec<SavedDroid>,
+    pub players_state: Vec<SavedPlayer>,
+    pub pickups: Vec<SavedPickup>,
+    pub turrets: Vec<SavedTurret>,
+    pub mines: Vec<SavedMine>,
+    pub portals: Vec<SavedPortal>,
+    pub recharge_tiles: Vec<[i32; 2]>,
+    pub explored: ExploredTiles,
+}
+
+/// save game picked up by `game_setup` on the next game start
+#[derive(Resource, Default)]
+pub struct PendingLoad(pub Option<SaveGame>);
+
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveGameRequest {
+    Save,
+    Load,
+}
+
+fn tile_pos([q, r]: [i32; 2]) -> TilePos {
+    TilePos(Hex::new(q, r))
+}
+
+fn tile_coords(tile_pos: TilePos) -> [i32; 2] {
+    [tile_pos.0.q(), tile_pos.0.r()]
+}
+
+impl SaveGame {
+    pub fn load() -> Option<Self> {
+        let Some(contents) = read_config(SAVEGAME_FILE) else {
+            warn!("no saved game");
+            return None;
+        };
+        match serde_yaml::from_str(&contents) {
+            Ok(save_game) => Some(save_game),
+            Err(err) => {
+                warn!("failed to load {}: {}", SAVEGAME_FILE, err);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let result = serde_yaml::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| write_config(SAVEGAME_FILE, &contents));
+        match result {
+            Ok(()) => info!("game saved"),
+            Err(err) => warn!("failed to save {}: {}", SAVEGAME_FILE, err),
+        }
+    }
+
+    /// spawn the saved world, replacing the default game setup. Tiles that differ from the
+    /// current level are despawned / spawned.
+    pub fn spawn(
+        self,
+        commands: &mut Commands,
+        tiles_state: &TilesState,
+        tile_cache: &mut TileCache,
+    ) {
+        let tiles: HashSet<_> = self.tiles.iter().copied().map(tile_pos).collect();
+        let extra_tiles: Vec<_> = tile_cache
+            .tiles
+            .keys()
+            .filter(|tile_pos| !tiles.contains(tile_pos))
+            .copied()
+            .collect();
+        for tile_pos in extra_tiles {
+            if let Some(entity) = tile_cache.tiles.remove(&tile_pos) {
+                commands.entity(entity).insert(Despawn::ThisFrame);
+            }
+        }
+        for tile_pos in &tiles {
+            if tile_cache.tiles.contains_key(tile_pos) {
+                continue;
+            }
+            let entity = commands
+                .spawn(SpatialBundle::default())
+                .insert(TileType {
+                    wall: true,
+                    immediate_collider: true,
+                })
+                .insert(*tile_pos)
+                .id();
+            commands.entity(tiles_state.tile_root).add_child(entity);
+        }
+
+        let droids: Vec<_> = self
+            .droids
+            .iter()
+            .map(|saved| {
+                let droid = spawn_hostile_droid(
+                    commands,
+                    self.gravity,
+                    saved.class,
+                    saved.body.transform().translation,
+                );
+                commands.entity(droid).insert((
+                    saved.body.transform(),
+                    saved.body.velocity(),
+                    DroidHealth::from(saved.health),
+                    saved.team,
+                ));
+                droid
+            })
+            .collect();
+
+        let mut players = Vec::new();
+        let mut controlled = Vec::new();
+        let mut vessels = HashMap::new();
+        for saved in &self.players_state {
+            let player = spawn_player(
+                commands,
+                PlayerIndex(saved.index),
+                PlayerScore { kills: saved.kills },
+            );
+            let vessel = match &saved.vessel {
+                SavedVessel::Archetype {
+                    archetype,
+                    body,
+                    health,
+                } => {
+                    let vessel =
+                        archetype.spawn(commands, self.gravity, body.transform().translation);
+                    commands
+                        .entity(vessel)
+                        .insert((body.transform(), body.velocity()));
+                    if let Some(health) = health {
+                        commands.entity(vessel).insert(DroidHealth::from(*health));
+                    }
+                    vessel
+                }
+                SavedVessel::Droid(index) => {
+                    let Some(droid) = droids.get(*index) else {
+                        warn!("saved player vessel {} does not exist", index);
+                        commands.entity(player).despawn_recursive();
+                        continue;
+                    };
+                    controlled.push(*droid);
+                    *droid
+                }
+            };
+            let loadout = saved.loadout;
+            commands
+                .entity(vessel)
+                .add(move |entity: Entity, world: &mut World| loadout.restore(entity, world));
+            commands.entity(vessel).add_child(player);
+            players.push(player);
+            vessels.insert(saved.index, vessel);
+        }
+
+        // hostile droids without players keep their AI, it idles until a player shows up
+        let enemy = players.first().copied().unwrap_or(Entity::PLACEHOLDER);
+        for (saved, droid) in self.droids.iter().zip(&droids) {
+            if saved.team != Team::Player && !controlled.contains(droid) {
+                spawn_droid_ai(commands, *droid, enemy);
+            }
+        }
+
+        for pickup in &self.pickups {
+            spawn_pickup(
+                commands,
+                pickup.kind,
+                Vec2::from(pickup.position).extend(0.0),
+            );
+        }
+        for turret in &self.turrets {
+            let entity = spawn_turret(
+                commands,
+                turret.team,
+                Vec2::from(turret.position).extend(0.0),
+            );
+            commands
+                .entity(entity)
+                .insert(DroidHealth::from(turret.health));
+        }
+        for mine in &self.mines {
+            // mines of vessels that are gone only follow the team rules
+            let owner = mine
+                .owner
+                .and_then(|index| vessels.get(&index).copied())
+                .unwrap_or(Entity::PLACEHOLDER);
+            let entity = spawn_mine(
+                commands,
+                owner,
+                mine.team,
+                Vec2::from(mine.position).extend(0.0),
+            );
+            commands.entity(entity).insert(ProximityMine {
+                arm_timeout: mine.arm_timeout,
+                ..ProximityMine::new(owner)
+            });
+        }
+        for portal in &self.portals {
+            let mut timer = Timer::from_seconds(portal.period, TimerMode::Repeating);
+            timer.set_elapsed(Duration::from_secs_f32(portal.elapsed));
+            commands.spawn((
+                Portal {
+                    tile_pos: tile_pos(portal.tile),
+                    timer,
+                },
+                GameMarker,
+            ));
+        }
+        for tile in &self.recharge_tiles {
+            commands.spawn((
+                RechargeTile {
+                    tile_pos: tile_pos(*tile),
+                },
+                GameMarker,
+            ));
+        }
+
+        // after the fog reset of the game start
+        let explored = self.explored;
+        commands.add(move |world: &mut World| {
+            world.resource_mut::<FogOfWar>().restore_explored(&explored);
+        });
+    }
+}
+
+fn quicksave_input_system(action_input: ActionInput, mut requests: EventWriter<SaveGameRequest>) {
+    if action_input.just_pressed(InputAction::QuickSave) {
+        requests.send(SaveGameRequest::Save);
+    }
+    if action_input.just_pressed(InputAction::QuickLoad) {
+        requests.send(SaveGameRequest::Load);
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn savegame_save_system(
+    mut requests: EventReader<SaveGameRequest>,
+    spawn_info: Res<GameSpawnInfo>,
+    level: Res<Level>,
+    tile_cache: Res<TileCache>,
+    fog: Res<FogOfWar>,
+    player_query: Query<(&PlayerIndex, &PlayerScore, &Parent)>,
+    droid_query: Query<
+        (
+            Entity,
+            &DroidClass,
+            Option<&Team>,
+            &Transform,
+            Option<&Velocity>,
+            &DroidHealth,
+        ),
+        (With<DroidMarker>, Without<VesselArchetype>),
+    >,
+    vessel_query: Query<(
+        &VesselArchetype,
+        &Transform,
+        Option<&Velocity>,
+        Option<&DroidHealth>,
+    )>,
+    loadout_query: Query<(
+        Option<&WeaponState>,
+        Option<&WeaponEnergy>,
+        Option<&ActiveBuffs>,
+        Option<&Deployer>,
+    )>,
+    pickup_query: Query<(&Pickup, &Transform)>,
+    turret_query: Query<(&Team, &Transform, &DroidHealth), With<TurretMarker>>,
+    mine_query: Query<(&ProximityMine, &Team, &Transform)>,
+    portal_query: Query<&Portal>,
+    recharge_query: Query<&RechargeTile>,
+) {
+    if !requests
+        .read()
+        .any(|request| *request == SaveGameRequest::Save)
+    {
+        return;
+    }
+
+    let mut droid_indices = HashMap::new();
+    let mut droids = Vec::new();
+    for (entity, class, team, transform, velocity, health) in &droid_query {
+        droid_indices.insert(entity, droids.len());
+        droids.push(SavedDroid {
+            class: *class,
+            team: team.copied().unwrap_or(Team::Hostile),
+            body: SavedBody::new(transform, velocity),
+            health: health.into(),
+        });
+    }
+
+    let mut players_state = Vec::new();
+    let mut vessel_players = HashMap::new();
+    for (index, score, parent) in &player_query {
+        let vessel = if let Some(droid_index) = droid_indices.get(&parent.get()) {
+            SavedVessel::Droid(*droid_index)
+        } else if let Ok((archetype, transform, velocity, health)) = vessel_query.get(parent.get())
+        {
+            SavedVessel::Archetype {
+                archetype: archetype.0,
+                body: SavedBody::new(transform, velocity),
+                health: health.map(SavedHealth::from),
+            }
+        } else {
+            warn!("not saving {}: unknown vessel", index.name());
+            continue;
+        };
+        let (weapon_state, weapon_energy, buffs, deployer) =
+            loadout_query.get(parent.get()).unwrap_or_default();
+        let loadout = SavedLoadout::new(weapon_state, weapon_energy, buffs, deployer);
+        vessel_players.insert(parent.get(), index.0);
+        players_state.push(SavedPlayer {
+            index: index.0,
+            kills: score.kills,
+            vessel,
+            loadout,
+        });
+    }
+
+    let save_game = SaveGame {
+        player: spawn_info.player,
+        players: spawn_info.players,
+        gravity: spawn_info.gravity,
+        spawn_enemy_droids: spawn_info.spawn_enemy_droids,
+        level: level.number,
+        tiles: tile_cache.tiles.keys().copied().map(tile_coords).collect(),
+        droids,
+        players_state,
+        pickups: pickup_query
+            .iter()
+            .map(|(pickup, transform)| SavedPickup {
+                kind: pickup.kind,
+                position: transform.translation.xy().into(),
+            })
+            .collect(),
+        turrets: turret_query
+            .iter()
+            .map(|(team, transform, health)| SavedTurret {
+                team: *team,
+                position: transform.translation.xy().into(),
+                health: health.into(),
+            })
+            .collect(),
+        mines: mine_query
+            .iter()
+            .map(|(mine, team, transform)| SavedMine {
+                team: *team,
+                position: transform.translation.xy().into(),
+                arm_timeout: mine.arm_timeout.max(0.0),
+                owner: vessel_players.get(&mine.owner).copied(),
+            })
+            .collect(),
+        portals: portal_query
+            .iter()
+            .map(|portal| SavedPortal {
+                tile: tile_coords(portal.tile_pos),
+                period: portal.timer.duration().as_secs_f32(),
+                elapsed: portal.timer.elapsed_secs(),
+            })
+            .collect(),
+        recharge_tiles: recharge_query
+            .iter()
+            .map(|recharge_tile| tile_coords(recharge_tile.tile_pos))
+            .collect(),
+        explored: fog.explored_tiles(),
+    };
+    save_game.save();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn savegame_load_system(
+    mut requests: EventReader<SaveGameRequest>,
+    mut pending_load: ResMut<PendingLoad>,
+    mut spawn_info: ResMut<GameSpawnInfo>,
+    mut level: ResMut<Level>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    cur_game_state: Res<State<GameState>>,
+    mut auto_start: ResMut<AutoStart>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut menu_state: ResMut<NextState<MenuState>>,
+) {
+    if !requests
+        .read()
+        .any(|request| *request == SaveGameRequest::Load)
+    {
+        return;
+    }
+    let Some(save_game) = SaveGame::load() else {
+        return;
+    };
+    info!("load game");
+    spawn_info.player = save_game.player;
+    spawn_info.players = save_game.players;
+    spawn_info.gravity = save_game.gravity;
+    spawn_info.spawn_enemy_droids = save_game.spawn_enemy_droids;
+    level.number = save_game.level;
+    rapier_config.gravity = if save_game.gravity {
+        Vec2::Y * -9.81 * 10.0
+    } else {
+        Vec2::ZERO
+    };
+    pending_load.0 = Some(save_game);
+
+    // restart through GameState::None, like the restart from the pause menu
+    if *cur_game_state.get() != GameState::None {
+        auto_start.0 = true;
+        game_state.set(GameState::None);
+    } else {
+        game_state.set(GameState::Game);
+    }
+    menu_state.set(MenuState::Disabled);
+}
+
+pub struct SaveGamePlugin;
+impl Plugin for SaveGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingLoad>()
+            .add_event::<SaveGameRequest>()
+            .add_systems(
+                Update,
+                (
+                    quicksave_input_system.run_if(in_state(GameState::Game)),
+                    savegame_save_system.run_if(not(in_state(GameState::None))),
+                    savegame_load_system,
+                )
+                    .chain(),
+            );
+    }
+}
@@ -0,0 +1,429 @@
+//! user settings: menu choices, display and sound options, the camera zoom and the world
+//! builder noise. Persisted as yaml in the platform config directory (local storage on wasm).
+//! Command line flags override single settings for one run without being written back.
+//! Plugins read the Settings resource on startup, the display and game options are also
+//! re-applied whenever they change.
+
+use bevy::{
+    app::AppExit,
+    core_pipeline::bloom::BloomSettings,
+    prelude::*,
+    window::{PresentMode, PrimaryWindow, WindowMode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archetype::PlayerArchetype, camera::CameraRigState, feedback::FeedbackSettings, fog::FogOfWar,
+    minimap::MinimapSettings, worldbuild::NoiseSettings,
+};
+
+pub const SETTINGS_FILE: &str = "settings.yaml";
+
+// changes are written once the settings did not change for this long (seconds), so that
+// dragging a slider does not write the file every frame
+const SAVE_DELAY: f32 = 1.0;
+
+// volume change per click in the sound settings
+pub const VOLUME_STEP: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowModeSetting {
+    #[default]
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+impl WindowModeSetting {
+    pub fn next(self) -> Self {
+        match self {
+            WindowModeSetting::Windowed => WindowModeSetting::Borderless,
+            WindowModeSetting::Borderless => WindowModeSetting::Fullscreen,
+            WindowModeSetting::Fullscreen => WindowModeSetting::Windowed,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WindowModeSetting::Windowed => "windowed",
+            WindowModeSetting::Borderless => "borderless",
+            WindowModeSetting::Fullscreen => "fullscreen",
+        }
+    }
+
+    fn window_mode(self) -> WindowMode {
+        match self {
+            WindowModeSetting::Windowed => WindowMode::Windowed,
+            WindowModeSetting::Borderless => WindowMode::BorderlessFullscreen,
+            WindowModeSetting::Fullscreen => WindowMode::Fullscreen,
+        }
+    }
+}
+
+/// volumes are 0..1. There is no sound playback yet, the master volume is applied as
+/// GlobalVolume, music and effects volumes are kept for the sound sources to come.
+#[derive(Resource, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // choices of the main menu, used for the next game
+    pub player: PlayerArchetype,
+    pub players: usize,
+    pub gravity: bool,
+    pub enemy_droids: bool,
+    // manual camera zoom
+    pub camera_zoom: f32,
+    pub window_mode: WindowModeSetting,
+    pub vsync: bool,
+    pub bloom: bool,
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub effects_volume: f32,
+    pub screen_shake: bool,
+    pub minimap: bool,
+    pub fog: bool,
+    // perlin noise of the world builder
+    pub worldbuild: NoiseSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            player: default(),
+            players: 1,
+            gravity: false,
+            enemy_droids: true,
+            camera_zoom: 1.0,
+            window_mode: default(),
+            vsync: true,
+            bloom: true,
+            master_volume: 1.0,
+            music_volume: 0.8,
+            effects_volume: 0.8,
+            screen_shake: true,
+            minimap: true,
+            fog: true,
+            worldbuild: default(),
+        }
+    }
+}
+
+impl Settings {
+    /// load the settings from SETTINGS_FILE. Falls back to the defaults if the file is missing
+    /// or broken, missing entries keep their default.
+    pub fn load() -> Self {
+        let Some(contents) = read_config(SETTINGS_FILE) else {
+            return Self::default();
+        };
+        match serde_yaml::from_str(&contents) {
+            Ok(settings) => settings,
+            Err(err) => {
+                warn!("failed to load {}: {}", SETTINGS_FILE, err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let result = serde_yaml::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| write_config(SETTINGS_FILE, &contents));
+        if let Err(err) = result {
+            warn!("failed to save {}: {}", SETTINGS_FILE, err);
+        }
+    }
+}
+
+/// settings forced by command line flags. They apply to the current run only.
+#[derive(Clone, Debug, Default)]
+pub struct SettingsOverrides {
+    pub player: Option<PlayerArchetype>,
+    pub players: Option<usize>,
+    pub gravity: Option<bool>,
+    pub enemy_droids: Option<bool>,
+    pub screen_shake: Option<bool>,
+    pub fog: Option<bool>,
+}
+
+impl SettingsOverrides {
+    pub fn apply(&self, settings: &mut Settings) {
+        if let Some(player) = self.player {
+            settings.player = player;
+        }
+        if let Some(players) = self.players {
+            settings.players = players;
+        }
+        if let Some(gravity) = self.gravity {
+            settings.gravity = gravity;
+        }
+        if let Some(enemy_droids) = self.enemy_droids {
+            settings.enemy_droids = enemy_droids;
+        }
+        if let Some(screen_shake) = self.screen_shake {
+            settings.screen_shake = screen_shake;
+        }
+        if let Some(fog) = self.fog {
+            settings.fog = fog;
+        }
+    }
+
+    /// undo `apply`: overridden settings get their stored value back
+    fn restore(&self, stored: &Settings, settings: &mut Settings) {
+        if self.player.is_some() {
+            settings.player = stored.player;
+        }
+        if self.players.is_some() {
+            settings.players = stored.players;
+        }
+        if self.gravity.is_some() {
+            settings.gravity = stored.gravity;
+        }
+        if self.enemy_droids.is_some() {
+            settings.enemy_droids = stored.enemy_droids;
+        }
+        if self.screen_shake.is_some() {
+            settings.screen_shake = stored.screen_shake;
+        }
+        if self.fog.is_some() {
+            settings.fog = stored.fog;
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn config_path(name: &str) -> std::path::PathBuf {
+    // in the working directory if there is no config directory
+    directories::ProjectDirs::from("", "", "hexadroid")
+        .map(|dirs| dirs.config_dir().join(name))
+        .unwrap_or_else(|| name.into())
+}
+
+/// contents of the config file `name`, None if it does not exist (yet). Files that older
+/// versions wrote to the working directory are read from there once and moved into the config
+/// directory.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_config(name: &str) -> Option<String> {
+    let path = config_path(name);
+    if let Ok(contents) = std::fs::read_to_string(&path) {
+        return Some(contents);
+    }
+    let legacy_path = std::path::Path::new(name);
+    if path == legacy_path {
+        return None;
+    }
+    let contents = std::fs::read_to_string(legacy_path).ok()?;
+    match write_config(name, &contents) {
+        Ok(()) => info!("moved {} to {}", name, path.display()),
+        Err(err) => warn!("failed to move {} to the config directory: {}", name, err),
+    }
+    Some(contents)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_config(name: &str, contents: &str) -> Result<(), String> {
+    let path = config_path(name);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    std::fs::write(&path, contents).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// contents of the config file `name`, None if it does not exist (yet)
+#[cfg(target_arch = "wasm32")]
+pub fn read_config(name: &str) -> Option<String> {
+    local_storage()?
+        .get_item(&format!("hexadroid/{}", name))
+        .ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write_config(name: &str, contents: &str) -> Result<(), String> {
+    local_storage()
+        .ok_or_else(|| "no local storage".to_string())?
+        .set_item(&format!("hexadroid/{}", name), contents)
+        .map_err(|err| format!("{:?}", err))
+}
+
+/// the settings as stored, i.e. without the command line overrides
+#[derive(Resource)]
+struct SettingsStore {
+    stored: Settings,
+    overrides: SettingsOverrides,
+    // seconds left until the stored settings are written
+    save_delay: Option<f32>,
+}
+
+pub fn step_volume(volume: f32, steps: i32) -> f32 {
+    // round to whole steps, so that repeated clicks do not accumulate float errors
+    ((volume / VOLUME_STEP).round() + steps as f32).clamp(0.0, 1.0 / VOLUME_STEP) * VOLUME_STEP
+}
+
+fn settings_autosave_system(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut store: ResMut<SettingsStore>,
+    mut exit_events: EventReader<AppExit>,
+) {
+    if settings.is_changed() {
+        let mut stored = settings.clone();
+        store.overrides.restore(&store.stored, &mut stored);
+        if stored != store.stored {
+            store.stored = stored;
+            store.save_delay = Some(SAVE_DELAY);
+        }
+    }
+    let exiting = exit_events.read().count() > 0;
+    let Some(save_delay) = &mut store.save_delay else {
+        return;
+    };
+    *save_delay -= time.delta_seconds();
+    if *save_delay <= 0.0 || exiting {
+        store.stored.save();
+        store.save_delay = None;
+    }
+}
+
+fn apply_display_settings_system(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+    camera_query: Query<(Entity, Has<BloomSettings>), With<CameraRigState>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    if let Ok(mut window) = window_query.get_single_mut() {
+        let mode = settings.window_mode.window_mode();
+        if window.mode != mode {
+            window.mode = mode;
+        }
+        let present_mode = if settings.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        };
+        if window.present_mode != present_mode {
+            window.present_mode = present_mode;
+        }
+    }
+    for (entity, has_bloom) in &camera_query {
+        match (settings.bloom, has_bloom) {
+            (true, false) => {
+                commands.entity(entity).insert(BloomSettings::default());
+            }
+            (false, true) => {
+                commands.entity(entity).remove::<BloomSettings>();
+            }
+            _ => (),
+        }
+    }
+}
+
+fn apply_game_settings_system(
+    settings: Res<Settings>,
+    mut global_volume: ResMut<GlobalVolume>,
+    mut feedback_settings: ResMut<FeedbackSettings>,
+    mut minimap_settings: ResMut<MinimapSettings>,
+    mut fog: ResMut<FogOfWar>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    *global_volume = GlobalVolume::new(settings.master_volume);
+    feedback_settings.screen_shake = settings.screen_shake;
+    minimap_settings.enabled = settings.minimap;
+    if fog.enabled != settings.fog {
+        fog.enabled = settings.fog;
+    }
+}
+
+pub struct SettingsPlugin {
+    pub overrides: SettingsOverrides,
+}
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        let stored = Settings::load();
+        let mut settings = stored.clone();
+        self.overrides.apply(&mut settings);
+        app.insert_resource(settings)
+            .insert_resource(SettingsStore {
+                stored,
+                overrides: self.overrides.clone(),
+                save_delay: None,
+            })
+            .add_systems(
+                Update,
+                (apply_display_settings_system, apply_game_settings_system),
+            )
+            .add_systems(Last, settings_autosave_system);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_steps() {
+        assert_eq!(step_volume(0.5, 1), 0.6);
+        assert_eq!(step_volume(0.5, -1), 0.4);
+        assert_eq!(step_volume(1.0, 1), 1.0);
+        assert_eq!(step_volume(0.0, -1), 0.0);
+        // off-grid volumes snap to the nearest step
+        assert_eq!(step_volume(0.52, 0), 0.5);
+
+        // many steps up and down end on the exact values
+        let mut volume = 0.0;
+        for _ in 0..15 {
+            volume = step_volume(volume, 1);
+        }
+        assert_eq!(volume, 1.0);
+        for _ in 0..7 {
+            volume = step_volume(volume, -1);
+        }
+        assert_eq!(volume, step_volume(0.3, 0));
+    }
+
+    #[test]
+    fn overrides_apply_and_restore() {
+        let stored = Settings::default();
+        let overrides = SettingsOverrides {
+            player: Some(PlayerArchetype::Hexton),
+            players: Some(2),
+            gravity: Some(true),
+            fog: Some(false),
+            ..default()
+        };
+        let mut settings = stored.clone();
+        overrides.apply(&mut settings);
+        assert_eq!(settings.player, PlayerArchetype::Hexton);
+        assert_eq!(settings.players, 2);
+        assert!(settings.gravity);
+        assert!(!settings.fog);
+        // not overridden
+        assert_eq!(settings.enemy_droids, stored.enemy_droids);
+        assert_eq!(settings.screen_shake, stored.screen_shake);
+
+        // changes made in the menu are kept, the overridden values are not written back
+        settings.master_volume = 0.3;
+        let mut to_store = settings.clone();
+        overrides.restore(&stored, &mut to_store);
+        assert_eq!(
+            to_store,
+            Settings {
+                master_volume: 0.3,
+                ..stored.clone()
+            }
+        );
+
+        // without overrides everything is stored as is
+        let mut to_store = settings.clone();
+        SettingsOverrides::default().restore(&stored, &mut to_store);
+        assert_eq!(to_store, settings);
+    }
+}
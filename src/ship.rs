@@ -3,12 +3,16 @@ use crate::prelude::*;
 use crate::{
     collision_groups,
     droid::{AttackRequest, WeaponState, RELOAD_TIMEOUT},
-    weapon::{self, kinetic_projectile_shape_bundle, PROJECTILE_SPEED},
+    weapon::{
+        self, kinetic_projectile_shape_bundle, ProjectileRules, WeaponEnergy, WeaponKind,
+        PROJECTILE_SPEED,
+    },
 };
 use bevy::{math::Vec3Swizzles, prelude::*};
 use bevy_prototype_lyon::prelude::Stroke;
 use bevy_rapier2d::prelude::*;
 use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 
 #[derive(Component, Default)]
@@ -28,12 +32,217 @@ pub struct ShipThruster {
     pub rot_damping: bool,
 }
 
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FlightAssistMode {
+    // raw torque and thrust, no assistance apart from braking
+    #[default]
+    Newtonian,
+    // rotation input turns a target heading, which is held by a PD controller
+    RotationAssist,
+    // rotation assist + thruster automatically nulls any drift while not thrusting
+    VelocityHold,
+}
+
+impl FlightAssistMode {
+    pub fn next(self) -> Self {
+        match self {
+            FlightAssistMode::Newtonian => FlightAssistMode::RotationAssist,
+            FlightAssistMode::RotationAssist => FlightAssistMode::VelocityHold,
+            FlightAssistMode::VelocityHold => FlightAssistMode::Newtonian,
+        }
+    }
+}
+
 #[derive(Component, Default)]
-#[component(storage = "SparseSet")]
-pub struct ShipBrakeManeuver {
-    pub direction: Vec2,
+pub struct FlightAssist {
+    pub mode: FlightAssistMode,
+    // heading (radians, see controller::heading_of) held by the rotation assist
+    pub target_heading: Option<f32>,
+    // external heading request (e.g. mouse aim). Overrides rotation input in all modes.
+    pub aim_heading: Option<f32>,
 }
 
+/// controller math for the flight assist. Everything in here is pure functions of the
+/// ship state, so it can be tested without an App.
+pub mod controller {
+    use bevy::prelude::*;
+    use std::f32::consts::{PI, TAU};
+
+    /// proportional-derivative controller
+    #[derive(Clone, Copy, Debug)]
+    pub struct PdController {
+        pub kp: f32,
+        pub kd: f32,
+    }
+
+    impl PdController {
+        pub const fn new(kp: f32, kd: f32) -> Self {
+            Self { kp, kd }
+        }
+
+        /// control output for the current error and the rate of change of the controlled value
+        pub fn update(&self, error: f32, rate: f32) -> f32 {
+            self.kp * error - self.kd * rate
+        }
+    }
+
+    /// shortest signed angle from [`current`] to [`target`], in [-PI, PI]
+    pub fn angle_difference(current: f32, target: f32) -> f32 {
+        let d = (target - current).rem_euclid(TAU);
+        if d > PI {
+            d - TAU
+        } else {
+            d
+        }
+    }
+
+    /// heading angle (counter clockwise from the X axis) of a direction
+    pub fn heading_of(dir: Vec2) -> f32 {
+        dir.y.atan2(dir.x)
+    }
+
+    /// rotation command in [-1, 1] (positive is counter clockwise) that turns towards [`target_heading`]
+    pub fn rotation_command(
+        pd: &PdController,
+        heading: f32,
+        target_heading: f32,
+        angular_velocity: f32,
+    ) -> f32 {
+        pd.update(angle_difference(heading, target_heading), angular_velocity)
+            .clamp(-1.0, 1.0)
+    }
+
+    /// retro-burn to null the drift [`velocity`]: returns the heading to point the thruster
+    /// against the drift and the thrust in [0, 1] to apply at the current [`heading`].
+    /// Thrust is only applied when roughly aligned and is reduced for low speeds.
+    /// Returns None if the drift is below [`deadband`].
+    pub fn station_keeping(
+        velocity: Vec2,
+        heading: f32,
+        deadband: f32,
+        full_thrust_speed: f32,
+        align_tolerance: f32,
+    ) -> Option<(f32, f32)> {
+        let speed = velocity.length();
+        if speed < deadband {
+            return None;
+        }
+        let target_heading = heading_of(-velocity);
+        let error = angle_difference(heading, target_heading).abs();
+        let alignment = (1.0 - error / align_tolerance).clamp(0.0, 1.0);
+        let thrust = alignment * (speed / full_thrust_speed).clamp(0.0, 1.0);
+        Some((target_heading, thrust))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::f32::consts::FRAC_PI_2;
+
+        const EPSILON: f32 = 1e-4;
+
+        #[test]
+        fn angle_difference_wraps_around() {
+            assert!((angle_difference(0.0, FRAC_PI_2) - FRAC_PI_2).abs() < EPSILON);
+            assert!((angle_difference(FRAC_PI_2, 0.0) + FRAC_PI_2).abs() < EPSILON);
+            // across the +-PI seam the short way is taken
+            assert!((angle_difference(PI - 0.1, -PI + 0.1) - 0.2).abs() < EPSILON);
+            assert!((angle_difference(-PI + 0.1, PI - 0.1) + 0.2).abs() < EPSILON);
+            // full turns do not matter
+            assert!(angle_difference(0.3, 0.3 + 2.0 * TAU).abs() < EPSILON);
+            assert!((angle_difference(0.0, 3.0 * TAU + 0.5) - 0.5).abs() < EPSILON);
+            for (current, target) in [(0.0, PI), (1.0, -2.5), (-7.0, 9.0)] {
+                let d = angle_difference(current, target);
+                assert!((-PI..=PI).contains(&d));
+            }
+        }
+
+        #[test]
+        fn pd_controller_output() {
+            let pd = PdController::new(2.0, 0.5);
+            assert_eq!(pd.update(0.0, 0.0), 0.0);
+            assert!((pd.update(1.0, 0.0) - 2.0).abs() < EPSILON);
+            // the derivative term damps the motion
+            assert!((pd.update(1.0, 2.0) - 1.0).abs() < EPSILON);
+        }
+
+        #[test]
+        fn pd_controller_converges() {
+            // rotation with the command as angular acceleration, as applied to the ship
+            const MAX_ANGULAR_ACCELERATION: f32 = 10.0;
+            const DT: f32 = 1.0 / 60.0;
+            let pd = PdController::new(2.5, 1.2);
+            for (start, target) in [(0.0, 3.0), (3.0, -3.0), (-2.5, 2.5), (0.1, 0.0)] {
+                let mut heading = start;
+                let mut angular_velocity = 0.0;
+                for _ in 0..600 {
+                    let command = rotation_command(&pd, heading, target, angular_velocity);
+                    angular_velocity += command * MAX_ANGULAR_ACCELERATION * DT;
+                    heading += angular_velocity * DT;
+                }
+                assert!(
+                    angle_difference(heading, target).abs() < 0.01,
+                    "{start} -> {target} ended at {heading}"
+                );
+                assert!(angular_velocity.abs() < 0.05);
+            }
+        }
+
+        #[test]
+        fn rotation_command_direction_and_clamp() {
+            let pd = PdController::new(2.5, 1.2);
+            assert!(rotation_command(&pd, 0.0, 0.5, 0.0) > 0.0);
+            assert!(rotation_command(&pd, 0.0, -0.5, 0.0) < 0.0);
+            // short way across the seam: counter clockwise from just below PI
+            assert!(rotation_command(&pd, PI - 0.1, -PI + 0.1, 0.0) > 0.0);
+            assert_eq!(rotation_command(&pd, 0.0, 3.0, 0.0), 1.0);
+            assert_eq!(rotation_command(&pd, 0.0, -3.0, 0.0), -1.0);
+            assert_eq!(rotation_command(&pd, 0.0, 0.0, 0.0), 0.0);
+        }
+
+        #[test]
+        fn station_keeping_deadband() {
+            assert!(station_keeping(Vec2::new(0.5, 0.0), 0.0, 1.0, 100.0, 0.5).is_none());
+            assert!(station_keeping(Vec2::ZERO, 0.0, 1.0, 100.0, 0.5).is_none());
+        }
+
+        #[test]
+        fn station_keeping_points_against_drift() {
+            // drifting along +X: thrust towards -X
+            let (target_heading, thrust) =
+                station_keeping(Vec2::new(200.0, 0.0), PI, 1.0, 100.0, 0.5).unwrap();
+            assert!(angle_difference(target_heading, PI).abs() < EPSILON);
+            // aligned and faster than full_thrust_speed
+            assert!((thrust - 1.0).abs() < EPSILON);
+
+            // slow drift: reduced thrust
+            let (_, thrust) =
+                station_keeping(Vec2::new(0.0, 50.0), -FRAC_PI_2, 1.0, 100.0, 0.5).unwrap();
+            assert!((thrust - 0.5).abs() < EPSILON);
+
+            // misaligned beyond the tolerance: turn first, no thrust
+            let (target_heading, thrust) =
+                station_keeping(Vec2::new(200.0, 0.0), 0.0, 1.0, 100.0, 0.5).unwrap();
+            assert!(angle_difference(target_heading, PI).abs() < EPSILON);
+            assert_eq!(thrust, 0.0);
+
+            // half way within the tolerance: half thrust
+            let (_, thrust) =
+                station_keeping(Vec2::new(200.0, 0.0), PI - 0.25, 1.0, 100.0, 0.5).unwrap();
+            assert!((thrust - 0.5).abs() < EPSILON);
+        }
+    }
+}
+
+pub const ROTATION_PD: controller::PdController = controller::PdController::new(2.5, 1.2);
+// rate (radians per second) at which rotation input turns the assisted target heading
+pub const ASSIST_TURN_RATE: f32 = 3.0;
+// maximum lead of the target heading over the actual heading
+pub const ASSIST_MAX_LEAD: f32 = 1.0;
+pub const STATION_KEEPING_DEADBAND: f32 = 5.0;
+pub const STATION_KEEPING_FULL_THRUST_SPEED: f32 = 100.0;
+pub const STATION_KEEPING_ALIGN_TOLERANCE: f32 = 0.5;
+
 impl ShipThruster {
     pub fn apply_clamping(&mut self) {
         self.forward = self.forward.clamp(0.0, 1.0);
@@ -61,8 +270,10 @@ pub struct ShipBundle {
     // pub ground_friction: GroundFriction,
     // pub weapon_direction: WeaponDirection,
     pub weapon_state: WeaponState,
+    pub weapon_energy: WeaponEnergy,
     pub ship_input: ShipInput,
     pub ship_thruster: ShipThruster,
+    pub flight_assist: FlightAssist,
 
     pub attack_request: AttackRequest,
     pub damping: Damping,
@@ -87,18 +298,29 @@ impl ShipBundle {
             collider: Collider::triangle(SHIP_VERTICES[0], SHIP_VERTICES[1], SHIP_VERTICES[2]),
             collision_groups: CollisionGroups::new(
                 collision_groups::DROIDS,
-                collision_groups::DROIDS | collision_groups::LEVEL, /*  | collision_groups::PROJECTILES*/
+                collision_groups::DROIDS
+                    | collision_groups::LEVEL
+                    | collision_groups::PICKUPS
+                    | collision_groups::PROJECTILES,
             ),
             rigid_body: RigidBody::Dynamic,
             locked_axes: LockedAxes::empty(),
             velocity: Velocity::default(),
             name: Name::new(name),
             // weapon_direction: WeaponDirection { direction: Vec2::X },
-            weapon_state: default(),
+            weapon_state: WeaponState {
+                kind: WeaponKind::Wave,
+                ..default()
+            },
+            weapon_energy: WeaponEnergy {
+                regen: 10.0,
+                ..default()
+            },
             external_force: default(),
             external_impulse: default(),
             ship_input: default(),
             ship_thruster: default(),
+            flight_assist: default(),
             attack_request: default(),
             damping: default(),
             read_mass_properties: default(),
@@ -110,22 +332,76 @@ impl ShipBundle {
 }
 
 pub fn apply_ship_input_system(
-    mut commands: Commands,
-    mut ship_query: Query<(Entity, &ShipInput, &Velocity, &mut ShipThruster)>,
-    brake_query: Query<&ShipBrakeManeuver>,
+    time: Res<Time>,
+    mut ship_query: Query<(
+        &ShipInput,
+        &Transform,
+        &Velocity,
+        &mut FlightAssist,
+        &mut ShipThruster,
+    )>,
 ) {
-    for (entity, ship_input, velocity, mut thruster) in &mut ship_query {
+    // ShipThruster::rot is clockwise (i.e. inverted against the controller output)
+    for (ship_input, transform, velocity, mut flight_assist, mut thruster) in &mut ship_query {
+        let heading = controller::heading_of((transform.rotation * SHIP_MAIN_AXIS).xy());
         thruster.forward = ship_input.thrust;
-        thruster.rot_damping = ship_input.thrust > f32::EPSILON;
+        thruster.rot_damping = false;
 
-        thruster.rot = ship_input.rot * 0.5;
+        match (flight_assist.mode, flight_assist.aim_heading) {
+            (_, Some(aim_heading)) => {
+                // keep holding the aimed heading once aiming stops (in the assisted modes)
+                flight_assist.target_heading = Some(aim_heading);
+                thruster.rot = -controller::rotation_command(
+                    &ROTATION_PD,
+                    heading,
+                    aim_heading,
+                    velocity.angvel,
+                );
+            }
+            (FlightAssistMode::Newtonian, None) => {
+                thruster.rot = ship_input.rot * 0.5;
+                flight_assist.target_heading = None;
+            }
+            (FlightAssistMode::RotationAssist | FlightAssistMode::VelocityHold, None) => {
+                let mut target_heading = flight_assist.target_heading.unwrap_or(heading);
+                target_heading -= ship_input.rot * ASSIST_TURN_RATE * time.delta_seconds();
+                // don't let the target run away if the ship cannot follow
+                target_heading = heading
+                    + controller::angle_difference(heading, target_heading)
+                        .clamp(-ASSIST_MAX_LEAD, ASSIST_MAX_LEAD);
+                flight_assist.target_heading = Some(target_heading);
+                thruster.rot = -controller::rotation_command(
+                    &ROTATION_PD,
+                    heading,
+                    target_heading,
+                    velocity.angvel,
+                );
+            }
+        }
 
-        if ship_input.brake > f32::EPSILON && !brake_query.contains(entity) {
-            commands.entity(entity).insert(ShipBrakeManeuver {
-                direction: velocity.linvel,
-            });
-        } else if ship_input.brake < f32::EPSILON {
-            commands.entity(entity).remove::<ShipBrakeManeuver>();
+        let station_keeping = ship_input.brake > f32::EPSILON
+            || (flight_assist.mode == FlightAssistMode::VelocityHold
+                && ship_input.thrust < f32::EPSILON);
+        if !station_keeping {
+            continue;
+        }
+        if let Some((target_heading, thrust)) = controller::station_keeping(
+            velocity.linvel,
+            heading,
+            STATION_KEEPING_DEADBAND,
+            STATION_KEEPING_FULL_THRUST_SPEED,
+            STATION_KEEPING_ALIGN_TOLERANCE,
+        ) {
+            thruster.rot = -controller::rotation_command(
+                &ROTATION_PD,
+                heading,
+                target_heading,
+                velocity.angvel,
+            );
+            thruster.forward = thruster.forward.max(thrust);
+        } else if flight_assist.mode == FlightAssistMode::Newtonian {
+            // no drift left: braking also stops the rotation
+            thruster.rot = -ROTATION_PD.update(0.0, velocity.angvel).clamp(-1.0, 1.0);
         }
     }
 }
@@ -156,40 +432,6 @@ pub fn ship_attach_thruster_particle_spawner_system(
         });
     }
 }
-pub fn ship_brake_maneuver_system(
-    mut query: Query<(
-        &ShipInput,
-        &Transform,
-        &Velocity,
-        &ShipBrakeManeuver,
-        &mut ShipThruster,
-    )>,
-) {
-    for (ship_input, transform, velocity, brake_maneuver, mut thruster) in &mut query {
-        let (Some(maneuver_dir), Some(cur_dir)) = (
-            brake_maneuver.direction.try_normalize(),
-            velocity.linvel.try_normalize(),
-        ) else {
-            thruster.rot_damping |= ship_input.brake > f32::EPSILON;
-            continue;
-        };
-        let ship_dir = (transform.rotation * SHIP_MAIN_AXIS).xy();
-        let angle = ship_dir.angle_between(maneuver_dir);
-        let diff = 1.0 - angle.abs() / (std::f32::consts::PI * 4.0);
-        if ship_input.brake > f32::EPSILON {
-            let dot = maneuver_dir.dot(cur_dir);
-            if diff < 0.8 && dot > 0.0 {
-                thruster.forward = 1.0;
-                // thruster.rot_damping = true;
-            }
-            if angle.signum() == velocity.angvel.signum() || velocity.angvel.abs() < 2.0 {
-                thruster.rot = diff * angle.signum()
-            }
-            // info!("diff {}", diff);
-        }
-    }
-}
-
 // fn ship_thruster_system(
 //     mut query: Query<(
 //         &mut ShipThruster,
@@ -288,9 +530,11 @@ fn _ship_kinetic_debug_system(
 fn _ship_attack_system_simple(
     mut commands: Commands,
     time: Res<Time>,
+    rules: Res<ProjectileRules>,
     mut query: Query<(Entity, &Transform, &AttackRequest, &mut WeaponState), With<ShipInput>>,
 ) {
-    for (entity, transform, AttackRequest { primary_attack }, mut weapon_state) in query.iter_mut()
+    for (entity, transform, AttackRequest { primary_attack, .. }, mut weapon_state) in
+        query.iter_mut()
     {
         weapon_state.reload_timeout = (weapon_state.reload_timeout - time.delta_seconds()).max(0.0);
         if !primary_attack || weapon_state.reload_timeout > f32::EPSILON {
@@ -301,7 +545,7 @@ fn _ship_attack_system_simple(
         commands
             .spawn(weapon::KineticProjectileBundle::with_direction(
                 entity, // *translation,
-                direction,
+                direction, &rules,
             ))
             .insert(kinetic_projectile_shape_bundle(
                 transform.translation,
@@ -313,15 +557,37 @@ fn _ship_attack_system_simple(
 fn ship_attack_system(
     mut commands: Commands,
     time: Res<Time>,
+    rules: Res<ProjectileRules>,
     _rapier_context: Res<RapierContext>,
-    mut query: Query<(Entity, &Transform, &AttackRequest, &mut WeaponState), With<ShipInput>>,
+    mut query: Query<
+        (
+            Entity,
+            &Transform,
+            &AttackRequest,
+            &mut WeaponState,
+            Option<&mut WeaponEnergy>,
+        ),
+        With<ShipInput>,
+    >,
 ) {
-    for (entity, transform, AttackRequest { primary_attack }, mut weapon_state) in query.iter_mut()
+    for (
+        entity,
+        transform,
+        AttackRequest { primary_attack, .. },
+        mut weapon_state,
+        weapon_energy,
+    ) in query.iter_mut()
     {
         weapon_state.reload_timeout = (weapon_state.reload_timeout - time.delta_seconds()).max(0.0);
         if !primary_attack || weapon_state.reload_timeout > f32::EPSILON {
             continue;
         }
+        if let Some(mut weapon_energy) = weapon_energy {
+            if !weapon_energy.can_fire(weapon_state.kind) {
+                continue;
+            }
+            weapon_energy.fire(weapon_state.kind);
+        }
 
         let _projectile_shape = Collider::ball(10.0);
         let _projectile_pos = transform.translation.xy();
@@ -358,21 +624,27 @@ fn ship_attack_system(
             //     "Hit the entity {:?} with the configuration: {:?}",
             //     entity, hit
             // );
-            weapon_state.reload_timeout = RELOAD_TIMEOUT;
+            weapon_state.reload_timeout = weapon_state.reload_time;
             let direction = (transform.rotation * SHIP_MAIN_AXIS).xy();
-            if false {
-                commands
-                    .spawn(weapon::KineticProjectileBundle::with_direction(
-                        entity, // *translation,
-                        direction,
-                    ))
-                    .insert(kinetic_projectile_shape_bundle(
+            match weapon_state.kind {
+                WeaponKind::Kinetic => {
+                    commands
+                        .spawn(weapon::KineticProjectileBundle::with_direction(
+                            entity, // *translation,
+                            direction, &rules,
+                        ))
+                        .insert(kinetic_projectile_shape_bundle(
+                            transform.translation,
+                            direction,
+                        ))
+                        .insert(Stroke::new(GREEN_HDR, 10.0));
+                }
+                WeaponKind::Wave => {
+                    commands.spawn(weapon::WaveAttackBundle::wave_attack(
+                        entity,
                         transform.translation,
-                        direction,
-                    ))
-                    .insert(Stroke::new(GREEN_HDR, 10.0));
-            } else {
-                commands.spawn(weapon::WaveAttackBundle::wave_attack(transform.translation));
+                    ));
+                }
             }
         }
     }
@@ -405,8 +677,7 @@ impl Plugin for ShipPlugin {
             Update,
             (
                 apply_ship_input_system,
-                ship_brake_maneuver_system.after(apply_ship_input_system),
-                ship_thruster_system.after(ship_brake_maneuver_system),
+                ship_thruster_system.after(apply_ship_input_system),
                 ship_attack_system,
                 ship_thruster_particle_system.after(ship_thruster_system),
             )
@@ -67,6 +67,9 @@ impl std::hash::Hash for TilePos {
 pub struct TileCache {
     pub tiles: HashMap<TilePos, Entity>,
     pub dirty_set: HashSet<Entity>,
+    // positions of spawned / despawned tiles (without neighbors). Drained by the minimap, which
+    // runs independently of the collider update consuming dirty_set.
+    pub dirty_positions: HashSet<TilePos>,
 }
 
 fn setup_system(
@@ -152,6 +155,7 @@ fn spawn_tiles_system(
 
     // add the modified tiles and their neighbors to dirty_set
     for (entity, pos) in dirty_add {
+        tiles_cache.dirty_positions.insert(pos);
         tiles_cache.dirty_set.insert(entity);
         for n in pos.get_neighbors() {
             if let Some(ne) = tiles_cache.tiles.get(&n).cloned() {
@@ -162,8 +166,9 @@ fn spawn_tiles_system(
 }
 
 #[derive(Component, Default)]
-struct BoundaryMarker {
-    tiles: HashSet<Entity>,
+pub(crate) struct BoundaryMarker {
+    // tiles contributing edges to the loop
+    pub(crate) tiles: HashSet<Entity>,
 }
 
 pub mod util {
@@ -0,0 +1,445 @@
+//! on-screen virtual joystick and buttons for the mobile builds. The controls read `Touches`
+//! only, so they can be driven by synthetic `TouchInput` events. The result is exposed through
+//! TouchControls, which ActionInput merges with keyboard and gamepad.
+
+use bevy::{input::touch::TouchInput, prelude::*, utils::HashSet, window::PrimaryWindow};
+
+use crate::{action::InputAction, game::GameMarker, input::MOVE_STICK_DEADZONE, prelude::*};
+
+const STICK_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+const KNOB_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.4);
+const BUTTON_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+const BUTTON_PRESSED_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.5);
+const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+
+#[derive(Resource)]
+pub struct TouchSettings {
+    // show and evaluate the on-screen controls. Switched on by the first touch event.
+    pub enabled: bool,
+}
+
+impl Default for TouchSettings {
+    fn default() -> Self {
+        Self {
+            enabled: cfg!(any(target_os = "android", target_os = "ios")),
+        }
+    }
+}
+
+/// current state of the on-screen controls
+#[derive(Resource, Default)]
+pub struct TouchControls {
+    stick_touch: Option<u64>,
+    pub stick: Option<Vec2>,
+    pressed: HashSet<InputAction>,
+    just_pressed: HashSet<InputAction>,
+}
+
+impl TouchControls {
+    pub fn pressed(&self, action: InputAction) -> bool {
+        self.pressed.contains(&action)
+    }
+    pub fn just_pressed(&self, action: InputAction) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+/// positions of the on-screen controls in window coordinates (logical pixels, y down).
+/// Everything scales with the smaller window dimension.
+pub struct TouchLayout {
+    pub stick_center: Vec2,
+    pub stick_radius: f32,
+    pub buttons: [(InputAction, &'static str, Vec2, f32); 5],
+}
+
+impl TouchLayout {
+    pub fn new(window_size: Vec2) -> Self {
+        let vmin = window_size.min_element() / 100.0;
+        let margin = 5.0 * vmin;
+        let stick_radius = 15.0 * vmin;
+        let fire_radius = 9.0 * vmin;
+        let small_radius = 6.0 * vmin;
+
+        let fire_center = Vec2::new(
+            window_size.x - margin - fire_radius,
+            window_size.y - margin - fire_radius,
+        );
+        let offset = fire_radius + small_radius + 2.0 * vmin;
+        Self {
+            stick_center: Vec2::new(margin + stick_radius, window_size.y - margin - stick_radius),
+            stick_radius,
+            buttons: [
+                (InputAction::Fire, "Fire", fire_center, fire_radius),
+                (
+                    InputAction::Jump,
+                    "Jump",
+                    fire_center - Vec2::new(offset, 0.0),
+                    small_radius,
+                ),
+                (
+                    InputAction::SecondaryFire,
+                    "Deploy",
+                    fire_center - Vec2::new(0.0, offset),
+                    small_radius,
+                ),
+                (
+                    InputAction::TogglePortal,
+                    "Portal",
+                    fire_center - Vec2::splat(offset * std::f32::consts::FRAC_1_SQRT_2),
+                    small_radius,
+                ),
+                // opens the pause menu, whose buttons take touches themselves
+                (
+                    InputAction::Pause,
+                    "Pause",
+                    Vec2::new(window_size.x - margin - small_radius, margin + small_radius),
+                    small_radius,
+                ),
+            ],
+        }
+    }
+
+    pub fn button_at(&self, position: Vec2) -> Option<InputAction> {
+        self.buttons
+            .iter()
+            .find(|(_, _, center, radius)| center.distance(position) <= *radius)
+            .map(|(action, ..)| *action)
+    }
+
+    /// a touch starting this close to the stick grabs it
+    pub fn grabs_stick(&self, position: Vec2) -> bool {
+        self.stick_center.distance(position) <= self.stick_radius * 1.5
+    }
+}
+
+#[derive(Component)]
+enum TouchWidget {
+    StickBase,
+    StickKnob,
+    Button(InputAction),
+}
+
+fn touch_enable_system(
+    mut touch_events: EventReader<TouchInput>,
+    mut touch_settings: ResMut<TouchSettings>,
+) {
+    if touch_events.read().count() > 0 && !touch_settings.enabled {
+        info!("touch input detected. Enabling touch controls");
+        touch_settings.enabled = true;
+    }
+}
+
+fn touch_controls_system(
+    touches: Res<Touches>,
+    touch_settings: Res<TouchSettings>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut controls: ResMut<TouchControls>,
+) {
+    let Ok(window) = window_query.get_single() else {
+        return;
+    };
+    if !touch_settings.enabled {
+        return;
+    }
+    let layout = TouchLayout::new(Vec2::new(window.width(), window.height()));
+
+    if let Some(id) = controls.stick_touch {
+        if touches.get_pressed(id).is_none() {
+            controls.stick_touch = None;
+        }
+    }
+
+    let mut pressed = HashSet::new();
+    for touch in touches.iter() {
+        if controls.stick_touch.is_none()
+            && touches.just_pressed(touch.id())
+            && layout.grabs_stick(touch.start_position())
+        {
+            controls.stick_touch = Some(touch.id());
+        }
+        if controls.stick_touch == Some(touch.id()) {
+            continue;
+        }
+        if let Some(action) = layout.button_at(touch.position()) {
+            pressed.insert(action);
+        }
+    }
+
+    controls.stick = controls
+        .stick_touch
+        .and_then(|id| touches.get_pressed(id))
+        .map(|touch| {
+            let offset = (touch.position() - layout.stick_center) / layout.stick_radius;
+            // window y points down
+            Vec2::new(offset.x, -offset.y).clamp_length_max(1.0)
+        })
+        .filter(|stick| stick.length() > MOVE_STICK_DEADZONE);
+    controls.just_pressed = pressed.difference(&controls.pressed).copied().collect();
+    controls.pressed = pressed;
+}
+
+fn touch_ui_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        font_size: 20.0,
+        color: TEXT_COLOR,
+    };
+    let widget_style = Style {
+        position_type: PositionType::Absolute,
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: widget_style.clone(),
+                background_color: STICK_COLOR.into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            TouchWidget::StickBase,
+            GameMarker,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                NodeBundle {
+                    style: widget_style.clone(),
+                    background_color: KNOB_COLOR.into(),
+                    ..default()
+                },
+                TouchWidget::StickKnob,
+            ));
+        });
+
+    let layout = TouchLayout::new(Vec2::ONE);
+    for (action, label, ..) in layout.buttons {
+        commands
+            .spawn((
+                NodeBundle {
+                    style: widget_style.clone(),
+                    background_color: BUTTON_COLOR.into(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                TouchWidget::Button(action),
+                GameMarker,
+            ))
+            .with_children(|parent| {
+                parent.spawn(TextBundle::from_section(label, text_style.clone()));
+            });
+    }
+}
+
+fn set_circle(style: &mut Style, center: Vec2, radius: f32) {
+    style.left = Val::Px(center.x - radius);
+    style.top = Val::Px(center.y - radius);
+    style.width = Val::Px(2.0 * radius);
+    style.height = Val::Px(2.0 * radius);
+}
+
+fn touch_ui_system(
+    touch_settings: Res<TouchSettings>,
+    controls: Res<TouchControls>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut widget_query: Query<(
+        &TouchWidget,
+        &mut Style,
+        &mut BackgroundColor,
+        &mut Visibility,
+    )>,
+) {
+    let Ok(window) = window_query.get_single() else {
+        return;
+    };
+    let layout = TouchLayout::new(Vec2::new(window.width(), window.height()));
+    for (widget, mut style, mut background_color, mut visibility) in &mut widget_query {
+        match widget {
+            TouchWidget::StickBase => {
+                *visibility = if touch_settings.enabled {
+                    Visibility::Visible
+                } else {
+                    Visibility::Hidden
+                };
+                set_circle(&mut style, layout.stick_center, layout.stick_radius);
+            }
+            TouchWidget::StickKnob => {
+                // relative to the stick base
+                let knob_radius = layout.stick_radius * 0.4;
+                let stick = controls.stick.unwrap_or_default();
+                let center = Vec2::splat(layout.stick_radius)
+                    + Vec2::new(stick.x, -stick.y) * (layout.stick_radius - knob_radius);
+                set_circle(&mut style, center, knob_radius);
+            }
+            TouchWidget::Button(action) => {
+                *visibility = if touch_settings.enabled {
+                    Visibility::Visible
+                } else {
+                    Visibility::Hidden
+                };
+                if let Some((_, _, center, radius)) = layout
+                    .buttons
+                    .iter()
+                    .find(|(button_action, ..)| button_action == action)
+                {
+                    set_circle(&mut style, *center, *radius);
+                }
+                *background_color = if controls.pressed(*action) {
+                    BUTTON_PRESSED_COLOR.into()
+                } else {
+                    BUTTON_COLOR.into()
+                };
+            }
+        }
+    }
+}
+
+pub struct TouchPlugin;
+impl Plugin for TouchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TouchSettings>()
+            .init_resource::<TouchControls>()
+            .add_systems(OnExit(GameState::None), touch_ui_setup)
+            .add_systems(
+                PreUpdate,
+                (touch_enable_system, touch_controls_system)
+                    .chain()
+                    .after(bevy::input::InputSystem),
+            )
+            .add_systems(Update, touch_ui_system);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        input::{
+            touch::{TouchInput, TouchPhase},
+            InputPlugin,
+        },
+        window::WindowResolution,
+    };
+
+    use super::*;
+
+    const WINDOW_SIZE: Vec2 = Vec2::new(800.0, 600.0);
+
+    fn test_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(InputPlugin)
+            .insert_resource(TouchSettings { enabled: false })
+            .init_resource::<TouchControls>()
+            .add_systems(
+                PreUpdate,
+                (touch_enable_system, touch_controls_system)
+                    .chain()
+                    .after(bevy::input::InputSystem),
+            );
+        app.world.spawn((
+            Window {
+                resolution: WindowResolution::new(WINDOW_SIZE.x, WINDOW_SIZE.y),
+                ..default()
+            },
+            PrimaryWindow,
+        ));
+        app
+    }
+
+    fn touch(app: &mut App, id: u64, phase: TouchPhase, position: Vec2) {
+        app.world.send_event(TouchInput {
+            phase,
+            position,
+            force: None,
+            id,
+        });
+    }
+
+    fn controls(app: &App) -> &TouchControls {
+        app.world.resource::<TouchControls>()
+    }
+
+    #[test]
+    fn stick() {
+        let mut app = test_app();
+        let layout = TouchLayout::new(WINDOW_SIZE);
+
+        // the first touch enables the controls and grabs the stick
+        touch(
+            &mut app,
+            1,
+            TouchPhase::Started,
+            layout.stick_center + Vec2::new(layout.stick_radius * 1.2, 0.0),
+        );
+        app.update();
+        assert!(app.world.resource::<TouchSettings>().enabled);
+        let stick = controls(&app).stick.unwrap();
+        assert!(stick.abs_diff_eq(Vec2::X, 1e-4), "{stick}");
+
+        // window y points down, the stick y up
+        touch(
+            &mut app,
+            1,
+            TouchPhase::Moved,
+            layout.stick_center - Vec2::new(0.0, layout.stick_radius * 0.5),
+        );
+        app.update();
+        let stick = controls(&app).stick.unwrap();
+        assert!(stick.abs_diff_eq(Vec2::new(0.0, 0.5), 1e-4), "{stick}");
+
+        // inside the deadzone
+        touch(&mut app, 1, TouchPhase::Moved, layout.stick_center);
+        app.update();
+        assert_eq!(controls(&app).stick, None);
+
+        touch(
+            &mut app,
+            1,
+            TouchPhase::Moved,
+            layout.stick_center + Vec2::new(0.0, layout.stick_radius),
+        );
+        app.update();
+        assert!(controls(&app).stick.is_some());
+        touch(&mut app, 1, TouchPhase::Ended, layout.stick_center);
+        app.update();
+        assert_eq!(controls(&app).stick, None);
+    }
+
+    #[test]
+    fn buttons() {
+        let mut app = test_app();
+        let layout = TouchLayout::new(WINDOW_SIZE);
+        let center = |action: InputAction| {
+            layout
+                .buttons
+                .iter()
+                .find(|(button_action, ..)| *button_action == action)
+                .map(|(_, _, center, _)| *center)
+                .unwrap()
+        };
+
+        touch(&mut app, 1, TouchPhase::Started, center(InputAction::Fire));
+        app.update();
+        assert!(controls(&app).pressed(InputAction::Fire));
+        assert!(controls(&app).just_pressed(InputAction::Fire));
+        assert!(!controls(&app).pressed(InputAction::Jump));
+        // a button touch does not move the stick
+        assert_eq!(controls(&app).stick, None);
+
+        app.update();
+        assert!(controls(&app).pressed(InputAction::Fire));
+        assert!(!controls(&app).just_pressed(InputAction::Fire));
+
+        // a second finger on the pause button
+        touch(&mut app, 2, TouchPhase::Started, center(InputAction::Pause));
+        app.update();
+        assert!(controls(&app).pressed(InputAction::Fire));
+        assert!(controls(&app).just_pressed(InputAction::Pause));
+
+        // sliding off a button releases it
+        touch(&mut app, 1, TouchPhase::Moved, WINDOW_SIZE * 0.5);
+        touch(&mut app, 2, TouchPhase::Ended, center(InputAction::Pause));
+        app.update();
+        assert!(!controls(&app).pressed(InputAction::Fire));
+        assert!(!controls(&app).pressed(InputAction::Pause));
+    }
+}
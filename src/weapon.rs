@@ -1,17 +1,250 @@
 use crate::{
     droid::DroidHealth,
+    feedback::FeedbackEvent,
     particle::{ColorGenerator, ParticleDamping},
     prelude::*,
+    HEX_LAYOUT,
 };
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, math::Vec3Swizzles, prelude::*};
 use bevy_prototype_lyon::{entity::ShapeBundle, prelude::*, shapes};
 use bevy_rapier2d::prelude::*;
+use hexagon_tiles::{hexagon::HexRound, layout::LayoutTool, point::Point};
 use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
 
 use crate::{collision_groups, prelude::ParticleSource, Despawn};
+
+/// allegiance of an entity. Used to decide if weapons of one entity can hit another.
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Team {
+    Player,
+    Hostile,
+    #[default]
+    Neutral,
+}
+
+/// global rules for which weapon hits are registered (and physically resolved).
+#[derive(Resource)]
+pub struct ProjectileRules {
+    // projectiles can hit their owner (after owner_grace ran out)
+    pub self_hit: bool,
+    // projectiles can hit entities of the same team as their owner
+    pub team_hit: bool,
+    // projectiles to / from Team::Neutral entities are registered
+    pub neutral_hit: bool,
+    // time (in seconds) after spawning during which a projectile never collides with its owner
+    pub owner_grace: f32,
+}
+
+impl Default for ProjectileRules {
+    fn default() -> Self {
+        Self {
+            self_hit: true,
+            team_hit: false,
+            neutral_hit: true,
+            owner_grace: 0.3,
+        }
+    }
+}
+
+impl ProjectileRules {
+    /// check if a weapon owned by [`owner`] may hit [`target`], based only on ownership and teams.
+    /// Immunities of the target (see [`WeaponTarget`]) are checked separately.
+    pub fn allows_hit(
+        &self,
+        owner: Entity,
+        owner_team: Option<Team>,
+        target: Entity,
+        target_team: Option<Team>,
+    ) -> bool {
+        if owner == target {
+            return self.self_hit;
+        }
+        match (owner_team, target_team) {
+            (Some(Team::Neutral), _) | (_, Some(Team::Neutral)) => self.neutral_hit,
+            (Some(a), Some(b)) if a == b => self.team_hit,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WeaponKind {
+    #[default]
+    Kinetic,
+    Wave,
+}
+
+impl WeaponKind {
+    pub fn energy_cost(&self) -> f32 {
+        match self {
+            WeaponKind::Kinetic => 10.0,
+            WeaponKind::Wave => 35.0,
+        }
+    }
+    // heat added per shot. Weapon jams when heat reaches 1.0
+    pub fn heat(&self) -> f32 {
+        match self {
+            WeaponKind::Kinetic => 0.15,
+            WeaponKind::Wave => 0.4,
+        }
+    }
+}
+
+/// energy and heat of the weapon system of a ship or droid. Firing drains energy and heats up
+/// the weapon. An overheated weapon is jammed until it has cooled down.
+#[derive(Component)]
+pub struct WeaponEnergy {
+    pub energy: f32,
+    pub max_energy: f32,
+    // energy regeneration per second
+    pub regen: f32,
+    pub heat: f32,
+    // heat reduction per second
+    pub cooling: f32,
+    pub jammed: bool,
+}
+
+impl Default for WeaponEnergy {
+    fn default() -> Self {
+        Self {
+            energy: 100.0,
+            max_energy: 100.0,
+            regen: 15.0,
+            heat: 0.0,
+            cooling: 0.3,
+            jammed: false,
+        }
+    }
+}
+
+pub const JAM_RECOVER_HEAT: f32 = 0.25;
+pub const RECHARGE_TILE_MULTIPLIER: f32 = 4.0;
+
+impl WeaponEnergy {
+    pub fn can_fire(&self, kind: WeaponKind) -> bool {
+        !self.jammed && self.energy >= kind.energy_cost()
+    }
+    pub fn fire(&mut self, kind: WeaponKind) {
+        self.energy = (self.energy - kind.energy_cost()).max(0.0);
+        self.heat += kind.heat();
+        if self.heat >= 1.0 {
+            self.heat = 1.0;
+            self.jammed = true;
+        }
+    }
+    pub fn energy_fraction(&self) -> f32 {
+        if self.max_energy > 0.0 {
+            (self.energy / self.max_energy).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+    pub fn update(&mut self, dt: f32, regen_multiplier: f32) {
+        self.energy = (self.energy + self.regen * regen_multiplier * dt).min(self.max_energy);
+        self.heat = (self.heat - self.cooling * dt).max(0.0);
+        if self.jammed && self.heat <= JAM_RECOVER_HEAT {
+            self.jammed = false;
+        }
+    }
+}
+
+/// floor tile that speeds up energy regeneration of everything on top of it.
+#[derive(Component)]
+pub struct RechargeTile {
+    pub tile_pos: TilePos,
+}
+
+fn recharge_tile_spawn_system(
+    mut commands: Commands,
+    query: Query<(Entity, &RechargeTile), Added<RechargeTile>>,
+) {
+    for (entity, recharge_tile) in &query {
+        let center = LayoutTool::hex_to_pixel(HEX_LAYOUT, recharge_tile.tile_pos.0);
+        let shape = shapes::RegularPolygon {
+            sides: 6,
+            feature: shapes::RegularPolygonFeature::Radius(48.0),
+            ..default()
+        };
+        commands
+            .entity(entity)
+            .insert(ShapeBundle {
+                path: GeometryBuilder::build_as(&shape),
+                spatial: SpatialBundle::from_transform(Transform::from_xyz(
+                    center.x as f32,
+                    center.y as f32,
+                    -1.0,
+                )),
+                ..default()
+            })
+            .insert(Stroke::new(BLUE_HDR * 0.3, 2.0));
+    }
+}
+
+fn weapon_energy_system(
+    time: Res<Time>,
+    mut query: Query<(&mut WeaponEnergy, &GlobalTransform)>,
+    recharge_query: Query<&RechargeTile>,
+) {
+    for (mut weapon_energy, transform) in &mut query {
+        let hex_pos = LayoutTool::pixel_to_hex(
+            HEX_LAYOUT,
+            Point {
+                x: transform.translation().x.into(),
+                y: transform.translation().y.into(),
+            },
+        )
+        .round();
+        let regen_multiplier = if recharge_query
+            .iter()
+            .any(|recharge_tile| recharge_tile.tile_pos.0 == hex_pos)
+        {
+            RECHARGE_TILE_MULTIPLIER
+        } else {
+            1.0
+        };
+        weapon_energy.update(time.delta_seconds(), regen_multiplier);
+    }
+}
+
+/// what happens when a projectile hits something it cannot bounce off (anymore).
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum ImpactBehaviour {
+    // burst into sparks and despawn
+    #[default]
+    Explode,
+    // stay stuck (harmless) at the impact position for a while
+    Stick,
+    // pierce weapon targets without being deflected. Still explodes on the level.
+    PassThrough,
+}
+
+/// a 'live' projectile. The component is removed as soon as the projectile is spent, so
+/// damage is only ever applied while it exists.
 #[derive(Component)]
 pub struct Projectile {
     pub owner: Entity,
+    // remaining time during which the projectile ignores its owner
+    pub grace: f32,
+    pub damage: f32,
+    // number of ricochets off the level before the impact behaviour kicks in
+    pub bounces_left: u32,
+    // velocity factor applied on each ricochet
+    pub bounce_damping: f32,
+    pub impact: ImpactBehaviour,
+}
+
+impl Projectile {
+    pub fn new(owner: Entity, grace: f32) -> Self {
+        Self {
+            owner,
+            grace,
+            damage: PROJECTILE_DAMAGE,
+            bounces_left: 2,
+            bounce_damping: 0.8,
+            impact: default(),
+        }
+    }
 }
 
 #[derive(Bundle)]
@@ -23,15 +256,22 @@ pub struct KineticProjectileBundle {
     velocity: Velocity,
     active_events: ActiveEvents,
     active_collision_types: ActiveCollisionTypes,
+    active_hooks: ActiveHooks,
     projectile: Projectile,
     despawn: GameDespawn,
     mass_properies: ColliderMassProperties,
+    restitution: Restitution,
 }
 
 pub const PROJECTILE_SPEED: f32 = 800.0;
+pub const PROJECTILE_DAMAGE: f32 = 25.0;
 
 impl KineticProjectileBundle {
-    pub fn with_direction(owner: Entity, /*translation: Vec3, */ direction: Vec2) -> Self {
+    pub fn with_direction(
+        owner: Entity,
+        /*translation: Vec3, */ direction: Vec2,
+        rules: &ProjectileRules,
+    ) -> Self {
         Self {
             collider: Collider::ball(20.0),
             collision_groups: CollisionGroups::new(
@@ -43,11 +283,28 @@ impl KineticProjectileBundle {
             active_events: ActiveEvents::COLLISION_EVENTS,
             active_collision_types: ActiveCollisionTypes::default()
                 | ActiveCollisionTypes::KINEMATIC_STATIC,
-            projectile: Projectile { owner },
+            active_hooks: ActiveHooks::FILTER_CONTACT_PAIRS,
+            projectile: Projectile::new(owner, rules.owner_grace),
             despawn: GameDespawn::time_to_live(10.0),
             mass_properies: ColliderMassProperties::Density(0.3),
+            // energy loss on bounces is controlled explicitly by Projectile::bounce_damping
+            restitution: Restitution {
+                coefficient: 1.0,
+                combine_rule: CoefficientCombineRule::Max,
+            },
         }
     }
+
+    pub fn with_bounces(mut self, bounces: u32, damping: f32) -> Self {
+        self.projectile.bounces_left = bounces;
+        self.projectile.bounce_damping = damping;
+        self
+    }
+
+    pub fn with_impact(mut self, impact: ImpactBehaviour) -> Self {
+        self.projectile.impact = impact;
+        self
+    }
 }
 
 pub fn kinetic_projectile_shape_bundle(translation: Vec3, direction: Vec2) -> ShapeBundle {
@@ -71,8 +328,72 @@ pub fn kinetic_projectile_shape_bundle(translation: Vec3, direction: Vec2) -> Sh
     }
 }
 
+fn projectile_grace_system(time: Res<Time>, mut query: Query<&mut Projectile>) {
+    for mut projectile in &mut query {
+        if projectile.grace > 0.0 {
+            projectile.grace = (projectile.grace - time.delta_seconds()).max(0.0);
+        }
+    }
+}
+
+/// rapier physics hooks: filter out contacts between projectiles and entities they are not
+/// allowed to hit according to [`ProjectileRules`] and [`WeaponTarget`], so they just pass through.
+/// Colliders must opt in via [`ActiveHooks::FILTER_CONTACT_PAIRS`].
+#[derive(SystemParam)]
+pub struct ProjectileHooks<'w, 's> {
+    rules: Res<'w, ProjectileRules>,
+    projectile_query: Query<'w, 's, &'static Projectile>,
+    team_query: Query<'w, 's, &'static Team>,
+    target_query: Query<'w, 's, &'static WeaponTarget>,
+}
+
+impl ProjectileHooks<'_, '_> {
+    fn projectile_hits(&self, projectile: &Projectile, other: Entity) -> bool {
+        if other == projectile.owner && projectile.grace > 0.0 {
+            return false;
+        }
+        // only weapon targets are subject to the rules, everything else (i.e. the level) is always hit
+        let Ok(weapon_target) = self.target_query.get(other) else {
+            return true;
+        };
+        weapon_target.kinetic_projectile
+            && self.rules.allows_hit(
+                projectile.owner,
+                self.team_query.get(projectile.owner).ok().copied(),
+                other,
+                self.team_query.get(other).ok().copied(),
+            )
+    }
+}
+
+impl BevyPhysicsHooks for ProjectileHooks<'_, '_> {
+    fn filter_contact_pair(&self, context: PairFilterContextView) -> Option<SolverFlags> {
+        let (projectile, other) =
+            if let Ok(projectile) = self.projectile_query.get(context.collider1()) {
+                (projectile, context.collider2())
+            } else if let Ok(projectile) = self.projectile_query.get(context.collider2()) {
+                (projectile, context.collider1())
+            } else {
+                return Some(SolverFlags::COMPUTE_IMPULSES);
+            };
+
+        if !self.projectile_hits(projectile, other) {
+            None
+        } else if projectile.impact == ImpactBehaviour::PassThrough
+            && self.target_query.contains(other)
+        {
+            // still generate the contact (and the collision event) but do not deflect the projectile
+            Some(SolverFlags::empty())
+        } else {
+            Some(SolverFlags::COMPUTE_IMPULSES)
+        }
+    }
+}
+
 #[derive(Component)]
-pub struct WaveAttack;
+pub struct WaveAttack {
+    pub owner: Entity,
+}
 
 #[derive(Bundle)]
 pub struct WaveAttackBundle {
@@ -83,7 +404,7 @@ pub struct WaveAttackBundle {
 }
 
 impl WaveAttackBundle {
-    pub fn wave_attack(translation: Vec3) -> WaveAttackBundle {
+    pub fn wave_attack(owner: Entity, translation: Vec3) -> WaveAttackBundle {
         WaveAttackBundle {
             spatial: SpatialBundle::from_transform(Transform::from_translation(translation)),
             particle_source: ParticleSource {
@@ -98,7 +419,7 @@ impl WaveAttackBundle {
                 color_generator: ColorGenerator::Random,
             },
             despawn: GameDespawn::frames_to_live(1),
-            wave_attack: WaveAttack,
+            wave_attack: WaveAttack { owner },
         }
     }
 }
@@ -112,11 +433,19 @@ pub struct WaveAttackProxy {
 
 pub fn wave_attack_spawn_proxies(
     mut commands: Commands,
-    query: Query<&Transform, Added<WaveAttack>>,
-    target_query: Query<(Entity, &Transform), With<WeaponTarget>>,
+    rules: Res<ProjectileRules>,
+    query: Query<(&Transform, &WaveAttack), Added<WaveAttack>>,
+    target_query: Query<(Entity, &Transform, &WeaponTarget, Option<&Team>)>,
+    team_query: Query<&Team>,
 ) {
-    for transform in &query {
-        for (target, target_transform) in &target_query {
+    for (transform, wave_attack) in &query {
+        let owner_team = team_query.get(wave_attack.owner).ok().copied();
+        for (target, target_transform, weapon_target, target_team) in &target_query {
+            if !weapon_target.wave_attack
+                || !rules.allows_hit(wave_attack.owner, owner_team, target, target_team.copied())
+            {
+                continue;
+            }
             let d = transform.translation - target_transform.translation;
             let dist = d.length();
             let timeout = dist / 1800.0;
@@ -133,6 +462,7 @@ pub fn wave_attack_proxy_update(
     time: Res<Time>,
     mut query: Query<(Entity, &mut WaveAttackProxy)>,
     mut target_query: Query<(&Transform, &mut DroidHealth), With<WeaponTarget>>, // FIXME: assuming that only Droids are target
+    mut feedback_events: EventWriter<FeedbackEvent>,
 ) {
     for (proxy_entity, mut proxy) in &mut query {
         proxy.timeout -= time.delta_seconds();
@@ -162,10 +492,17 @@ pub fn wave_attack_proxy_update(
                 .insert(GameDespawn::frames_to_live(1));
             commands.entity(proxy_entity).insert(Despawn::ThisFrame);
             droid_health.emp_load += 0.5;
+            feedback_events.send(FeedbackEvent::new(
+                0.2,
+                0.3,
+                target_transform.translation.xy(),
+            ));
         }
     }
 }
-#[derive(Component)]
+/// marks entities that can be hit by weapons. Setting one of the flags to false makes the
+/// entity immune against that kind of weapon.
+#[derive(Component, Clone, Copy)]
 pub struct WeaponTarget {
     pub kinetic_projectile: bool,
     pub wave_attack: bool,
@@ -179,13 +516,84 @@ impl Default for WeaponTarget {
         }
     }
 }
+
+impl WeaponTarget {
+    pub fn immune_to_kinetic() -> Self {
+        Self {
+            kinetic_projectile: false,
+            ..default()
+        }
+    }
+    pub fn immune_to_wave() -> Self {
+        Self {
+            wave_attack: false,
+            ..default()
+        }
+    }
+}
 pub struct WeaponPlugin;
 
 impl Plugin for WeaponPlugin {
     fn build(&self, app: &mut App) {
-        app
+        app.init_resource::<ProjectileRules>()
             // .add_system(droid_stop_system)
             .add_systems(Update, wave_attack_proxy_update) //.after(droid_stop_system))
-            .add_systems(Update, wave_attack_spawn_proxies);
+            .add_systems(Update, wave_attack_spawn_proxies)
+            .add_systems(
+                Update,
+                (projectile_grace_system, weapon_energy_system).run_if(in_state(GameState::Game)),
+            )
+            .add_systems(PostUpdate, recharge_tile_spawn_system);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: Entity = Entity::from_raw(1);
+    const TARGET: Entity = Entity::from_raw(2);
+
+    #[test]
+    fn self_hit() {
+        let mut rules = ProjectileRules::default();
+        assert!(rules.allows_hit(OWNER, Some(Team::Player), OWNER, Some(Team::Player)));
+        rules.self_hit = false;
+        assert!(!rules.allows_hit(OWNER, Some(Team::Player), OWNER, Some(Team::Player)));
+        // team rules do not apply to the owner itself
+        rules.self_hit = true;
+        rules.team_hit = false;
+        assert!(rules.allows_hit(OWNER, Some(Team::Player), OWNER, Some(Team::Player)));
+    }
+
+    #[test]
+    fn team_hit() {
+        let mut rules = ProjectileRules::default();
+        assert!(!rules.allows_hit(OWNER, Some(Team::Player), TARGET, Some(Team::Player)));
+        assert!(!rules.allows_hit(OWNER, Some(Team::Hostile), TARGET, Some(Team::Hostile)));
+        assert!(rules.allows_hit(OWNER, Some(Team::Player), TARGET, Some(Team::Hostile)));
+        assert!(rules.allows_hit(OWNER, Some(Team::Hostile), TARGET, Some(Team::Player)));
+        rules.team_hit = true;
+        assert!(rules.allows_hit(OWNER, Some(Team::Player), TARGET, Some(Team::Player)));
+    }
+
+    #[test]
+    fn neutral_hit() {
+        let mut rules = ProjectileRules::default();
+        assert!(rules.allows_hit(OWNER, Some(Team::Neutral), TARGET, Some(Team::Neutral)));
+        rules.neutral_hit = false;
+        assert!(!rules.allows_hit(OWNER, Some(Team::Neutral), TARGET, Some(Team::Player)));
+        assert!(!rules.allows_hit(OWNER, Some(Team::Hostile), TARGET, Some(Team::Neutral)));
+        // the neutral rule wins over the team rule
+        rules.team_hit = true;
+        assert!(!rules.allows_hit(OWNER, Some(Team::Neutral), TARGET, Some(Team::Neutral)));
+    }
+
+    #[test]
+    fn missing_team() {
+        let rules = ProjectileRules::default();
+        assert!(rules.allows_hit(OWNER, None, TARGET, Some(Team::Player)));
+        assert!(rules.allows_hit(OWNER, Some(Team::Player), TARGET, None));
+        assert!(rules.allows_hit(OWNER, None, TARGET, None));
     }
 }
@@ -1,5 +1,10 @@
 use crate::{
-    camera::CameraTarget, hex_point_to_vec2, prelude::*, vec2_to_hex_point, Despawn, HEX_LAYOUT,
+    action::{ActionInput, InputAction},
+    camera::CameraTarget,
+    hex_point_to_vec2,
+    prelude::*,
+    settings::Settings,
+    vec2_to_hex_point, Despawn, HEX_LAYOUT,
 };
 use bevy::{math::Vec3Swizzles, prelude::*};
 use bevy_egui::{egui, EguiContext};
@@ -8,6 +13,7 @@ use bevy_rapier2d::prelude::RapierContext;
 use egui_extras::RetainedImage;
 use hexagon_tiles::hexagon::{Hex, HexMath, HexRound};
 use perlin2d::PerlinNoise2D;
+use serde::{Deserialize, Serialize};
 use std::io::BufWriter;
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -35,8 +41,51 @@ pub struct WorldState {
 const NOISE_SCALE: f32 = 0.1;
 
 const PERLIN_SCALE: f64 = 64.0;
+const PERLIN_SEED: i32 = 101;
 const INITIAL_SIZE: i32 = 20;
 
+/// parameters of the wall noise, edited with the sliders of the world builder ui
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseSettings {
+    pub octaves: i32,
+    pub amplitude: f64,
+    pub frequency: f64,
+    pub persistence: f64,
+    pub lacunarity: f64,
+    pub scale: (f64, f64),
+    pub bias: f64,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        Self {
+            octaves: 4,
+            amplitude: 1.0,
+            frequency: 1.0,
+            persistence: 2.4,
+            lacunarity: 2.9,
+            scale: (PERLIN_SCALE, PERLIN_SCALE),
+            bias: -0.35,
+        }
+    }
+}
+
+impl NoiseSettings {
+    fn perlin(&self) -> PerlinNoise2D {
+        PerlinNoise2D::new(
+            self.octaves,
+            self.amplitude,
+            self.frequency,
+            self.persistence,
+            self.lacunarity,
+            self.scale,
+            self.bias,
+            PERLIN_SEED,
+        )
+    }
+}
+
 impl Default for WorldState {
     fn default() -> Self {
         Self {
@@ -45,16 +94,7 @@ impl Default for WorldState {
             min_target: Hex::new(-INITIAL_SIZE, -INITIAL_SIZE),
             max_target: Hex::new(INITIAL_SIZE, INITIAL_SIZE),
             center: Hex::new(0, 0),
-            perlin: PerlinNoise2D::new(
-                4,
-                1.0,
-                1.0,
-                2.4,
-                2.9,
-                (PERLIN_SCALE, PERLIN_SCALE),
-                -0.35,
-                101,
-            ),
+            perlin: NoiseSettings::default().perlin(),
             rebuild: RebuildState::None,
             noise_preview: false,
         }
@@ -158,10 +198,15 @@ fn update_walls_noise(
     };
 }
 
+fn worldbuild_setup_system(settings: Res<Settings>, mut world_state: ResMut<WorldState>) {
+    world_state.perlin = settings.worldbuild.perlin();
+}
+
 #[allow(deprecated)]
 fn worldbuid_egui_ui_system(
     mut egui_context: Query<&mut EguiContext>,
     mut world_state: ResMut<WorldState>,
+    mut settings: ResMut<Settings>,
     mut image: Local<Option<RetainedImage>>,
 ) {
     let mut amplitude = world_state.perlin.get_amplitude();
@@ -256,10 +301,23 @@ fn worldbuid_egui_ui_system(
     world_state.perlin.set_octaves(octaves);
     world_state.perlin.set_persistence(persistence);
     world_state.perlin.set_scale(scale);
+
+    let noise = NoiseSettings {
+        octaves,
+        amplitude,
+        frequency,
+        persistence,
+        lacunarity,
+        scale,
+        bias,
+    };
+    if settings.worldbuild != noise {
+        settings.worldbuild = noise;
+    }
 }
 
 fn world_debug_input_system(
-    keyboard_input: Res<Input<KeyCode>>,
+    action_input: ActionInput,
     rapier_context: Res<RapierContext>,
     mut world_state: ResMut<WorldState>,
 ) {
@@ -267,17 +325,17 @@ fn world_debug_input_system(
 
     const INCREMENT_I: i32 = 1;
     let increment = Hex::new(INCREMENT_I, INCREMENT_I);
-    if keyboard_input.just_pressed(KeyCode::Y) {
+    if action_input.just_pressed(InputAction::WorldShrink) {
         if world_state.max_target.q() > 1 {
             world_state.max_target = world_state.max_target.sub(increment);
             world_state.min_target = world_state.min_target.add(increment);
         }
-    } else if keyboard_input.just_pressed(KeyCode::U) {
+    } else if action_input.just_pressed(InputAction::WorldGrow) {
         if world_state.max_target.q() < 40 {
             world_state.max_target = world_state.max_target.add(increment);
             world_state.min_target = world_state.min_target.sub(increment);
         }
-    } else if keyboard_input.just_pressed(KeyCode::Q) {
+    } else if action_input.just_pressed(InputAction::DumpPhysics) {
         if let Ok(file) = std::fs::File::create("physics.yaml") {
             let _ = serde_yaml::to_writer(BufWriter::new(file), &*rapier_context);
         }
@@ -288,13 +346,15 @@ pub struct WorldbuildPlugin;
 
 impl Plugin for WorldbuildPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<WorldState>().add_systems(
-            Update,
-            (
-                update_walls_noise,
-                worldbuid_egui_ui_system,
-                world_debug_input_system,
-            ),
-        );
+        app.init_resource::<WorldState>()
+            .add_systems(Startup, worldbuild_setup_system)
+            .add_systems(
+                Update,
+                (
+                    update_walls_noise,
+                    worldbuid_egui_ui_system,
+                    world_debug_input_system,
+                ),
+            );
     }
 }